@@ -10,11 +10,12 @@ fn build_graph(doc: &XFlowDocument) -> String {
         r#"
 digraph G {{
 {nodes}
-
+{clusters}
 {edges}
 }}
 "#,
         nodes = build_nodes(&doc),
+        clusters = build_fork_clusters(&doc),
         edges = build_edges(&doc),
     );
     out
@@ -22,6 +23,20 @@ digraph G {{
 
 fn build_node(node: &XFlowNode) -> String {
     match node.nodetype {
+        XFlowNodeType::Flow if node.action == "foreach" => {
+            format!(
+                "  node_{id}[label={label},shape=hexagon,style=filled,color=skyblue];",
+                id = node.id,
+                label = node.label,
+                )
+        }
+        XFlowNodeType::Flow if node.action == "fork" || node.action == "join" => {
+            format!(
+                "  node_{id}[label={label},shape=box,style=\"filled,bold\",color=skyblue];",
+                id = node.id,
+                label = node.label,
+                )
+        }
         XFlowNodeType::Flow => {
             format!(
                 "  node_{id}[label={label},shape=octagon,style=filled,color=skyblue];",
@@ -57,6 +72,42 @@ fn build_nodes(doc: &XFlowDocument) -> String {
     vars.join("\n")
 }
 
+/// Draw each fork as a dashed cluster around its join, with one nested cluster per
+/// branch holding the nodes that run in parallel
+fn build_fork_clusters(doc: &XFlowDocument) -> String {
+    let clusters: Vec<String> = doc.body
+        .nodes
+        .iter()
+        .filter_map(|node| match node.parameters {
+            XFlowNodeParameters::Fork(ref params) => {
+                let branches: Vec<String> = doc.body
+                    .get_fork_regions(node, params.join)
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, &(_, ref region))| {
+                        let nodes: Vec<String> =
+                            region.iter().map(|id| format!("node_{};", id)).collect();
+                        format!(
+                            "    subgraph cluster_fork_{id}_{idx} {{ label=\"branch {idx}\"; {nodes} }}",
+                            id = node.id,
+                            idx = idx,
+                            nodes = nodes.join(" "),
+                        )
+                    })
+                    .collect();
+                Some(format!(
+                    "  subgraph cluster_fork_{id} {{\n    label=\"parallel\";\n    style=dashed;\n    node_{id}; node_{join};\n{branches}\n  }}",
+                    id = node.id,
+                    join = params.join,
+                    branches = branches.join("\n"),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    clusters.join("\n")
+}
+
 fn build_edges(doc: &XFlowDocument) -> String {
     let vars: Vec<String> = doc.body
         .edges
@@ -64,12 +115,34 @@ fn build_edges(doc: &XFlowDocument) -> String {
         .map({
             |tup| {
                 format!(
-                    "  node_{start} -> node_{end};",
+                    "  node_{start} -> node_{end}{attributes};",
                     start = tup.0,
                     end = tup.1,
+                    attributes = build_edge_attributes(&doc, tup),
                 )
             }
         })
+        .chain(doc.body.error_edges.iter().map(|edge| {
+            format!(
+                "  node_{start} -> node_{end}[style=dashed,color=red,label=\"error\"];",
+                start = edge.0,
+                end = edge.1,
+            )
+        }))
         .collect();
     vars.join("\n")
 }
+
+/// Label the edges out of a `foreach` node with the list they loop over
+fn build_edge_attributes(doc: &XFlowDocument, edge: &XFlowEdge) -> String {
+    match doc.body.get_node_id(edge.0).map(|node| &node.parameters) {
+        Some(&XFlowNodeParameters::Foreach(ref params)) => {
+            if edge.1 == params.body {
+                format!("[label=\"each {}\"]", params.list)
+            } else {
+                format!("[label=\"done\"]")
+            }
+        }
+        _ => "".to_owned(),
+    }
+}
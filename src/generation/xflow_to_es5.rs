@@ -1,4 +1,8 @@
 use crate::structure::xflow::*;
+use crate::parser::flox;
+use crate::parser::flox::{BinaryOp, Expr, ExprKind, Function, UnaryOp};
+use serde_json;
+use std::collections::HashMap;
 use crate::util::naming::uuid_to_label;
 
 use ratel::{transformer, parser, codegen};
@@ -86,7 +90,7 @@ fn local_variables(doc: &XFlowDocument) -> String {
                 format!(
                     "this.input_vars.{name} = {value};",
                     name = v.name,
-                    value = v.value.string_value()
+                    value = value_to_js(&v.value)
                 )
             }
         })
@@ -95,6 +99,29 @@ fn local_variables(doc: &XFlowDocument) -> String {
 
 }
 
+/// Decimals are held as strings of their exact value, as JS numbers are doubles. They
+/// are converted to numbers where expressions use them, see `expr_to_js`.
+fn value_to_js(value: &XFlowValue) -> String {
+    match *value {
+        XFlowValue::String(ref s) => serde_json::to_string(s).unwrap(),
+        XFlowValue::Decimal(ref d) => serde_json::to_string(&d.to_string()).unwrap(),
+        XFlowValue::List(ref items) => {
+            let items: Vec<String> = items.iter().map(value_to_js).collect();
+            format!("[{}]", items.join(", "))
+        }
+        XFlowValue::Record(ref fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| {
+                    format!("{}: {}", serde_json::to_string(name).unwrap(), value_to_js(value))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        _ => value.string_value(),
+    }
+}
+
 fn build_nodes(doc: &XFlowDocument) -> String {
     let vars: Vec<String> = doc.body
         .nodes
@@ -104,7 +131,7 @@ fn build_nodes(doc: &XFlowDocument) -> String {
                 format!(
                     r#" {fn_id}() {{ {body} }}  "#,
                     fn_id = method_name_for_node_id(&node.id),
-                    body = build_function_body(&node, &doc)
+                    body = build_error_handling(&node, &doc, build_function_body(&node, &doc))
                 )
             }
         })
@@ -118,6 +145,7 @@ fn build_function_body(node: &XFlowNode, doc: &XFlowDocument) -> String {
         XFlowNodeType::Flow => build_xflow_body(&node, &doc),
         XFlowNodeType::Flox => build_flox_body(&node, &doc),
         XFlowNodeType::Call => "call_xflow();".to_owned(),
+        XFlowNodeType::Custom(_) => format!("unimplemented();"),
     };
 
     format!(
@@ -127,27 +155,171 @@ fn build_function_body(node: &XFlowNode, doc: &XFlowDocument) -> String {
     )
 }
 
+/// Wrap the body of a node so its failures follow the error edge of the node, or the
+/// error handler of the flow. As every node calls the next one, errors raised further
+/// down the flow pass through here too, and are marked with the node that raised them
+/// so only that node handles them.
+fn build_error_handling(node: &XFlowNode, doc: &XFlowDocument, body: String) -> String {
+    if !doc.body.handles_errors() {
+        return body;
+    }
+
+    let target = match doc.body.get_error_edge(node.id) {
+        Some(edge) => Some(edge.1),
+        None => doc.body.error_handler.filter(|handler| *handler != node.id),
+    };
+    let handling = match target {
+        Some(target) => {
+            format!(
+                "this.local_vars.{name} = {{node: {id}, kind: 'dispatch', message: String(e)}}; \
+                 this.{fn_id}();",
+                name = ERROR_VARIABLE,
+                id = node.id,
+                fn_id = method_name_for_node_id(&target)
+            )
+        }
+        None => "throw e;".to_owned(),
+    };
+
+    format!(
+        r#"
+    try {{
+        {body}
+    }} catch (e) {{
+        if (e.xflow_node !== undefined) {{
+            throw e;
+        }}
+        e.xflow_node = {id};
+        {handling}
+    }}
+    "#,
+        body = body,
+        id = node.id,
+        handling = handling
+    )
+}
+
 fn build_xflow_body(node: &XFlowNode, doc: &XFlowDocument) -> String {
 
     match node.action.as_ref() {
         "start" => build_node_body_call_next_node(node, doc),
         "end" => format!("this.finalize(); this.callback(this.output_vars);"),
         "branch" => build_node_body_branch(node, doc),
+        "foreach" => build_node_body_foreach(node, doc),
+        "fork" => build_node_body_fork(node, doc),
+        "join" => build_node_body_join(node, doc),
         _ => format!("unimplemented();"),
 
     }
 }
 
 fn build_flox_body(node: &XFlowNode, doc: &XFlowDocument) -> String {
+    let assignment = match node.parameters {
+        XFlowNodeParameters::Flox(ref params) => {
+            let value = build_expression(&params.expression, doc);
+            format!(
+                "this.local_vars.{name} = {value};",
+                name = params.returns.name,
+                value = if params.returns.vtype == XFlowValueType::Decimal {
+                    format!("String({})", value)
+                } else {
+                    value
+                }
+            )
+        }
+        _ => format!("throw new Error('Incorrect parameters for flox node');"),
+    };
+
     format!(
         r#"
     console.log('flox node');
+    {assignment}
     {call_next_node}
     "#,
+        assignment = assignment,
         call_next_node = build_node_body_call_next_node(node, doc)
     )
 }
 
+fn build_expression(expression: &str, doc: &XFlowDocument) -> String {
+    match flox::compile(expression) {
+        Ok(expr) => expr_to_js(&expr, &doc.body.get_variable_types()),
+        Err(err) => {
+            error!("build_expression: Invalid expression '{}' - {:?}", expression, err);
+            format!("(function() {{ throw new Error('Invalid expression'); }})()")
+        }
+    }
+}
+
+/// Return the JS number of the decimal held in `value`, which is a string
+fn decimal_to_js(value: String, expr: &Expr, types: &HashMap<String, XFlowValueType>) -> String {
+    match expr.infer_type(types) {
+        Ok(XFlowValueType::Decimal) => format!("Number({})", value),
+        _ => value,
+    }
+}
+
+fn expr_to_js(expr: &Expr, types: &HashMap<String, XFlowValueType>) -> String {
+    let to_js = |e: &Expr| expr_to_js(e, types);
+    let round = |function: &str, d: &str, places: &str| {
+        format!(
+            "(Math.{f}({d} * Math.pow(10, {p})) / Math.pow(10, {p}))",
+            f = function,
+            d = d,
+            p = places
+        )
+    };
+    match expr.kind {
+        ExprKind::Integer(i) => i.to_string(),
+        ExprKind::Decimal(ref d) => d.to_string(),
+        ExprKind::Boolean(b) => b.to_string(),
+        ExprKind::String(ref s) => serde_json::to_string(s).unwrap(),
+        ExprKind::Variable(ref name) => decimal_to_js(format!("this.local_vars.{}", name), expr, types),
+        ExprKind::Call(function, ref args) => {
+            let args: Vec<String> = args.iter().map(to_js).collect();
+            match (function, args.as_slice()) {
+                (Function::Len, &[ref s]) => format!("{}.length", s),
+                (Function::Count, &[ref l]) => format!("{}.length", l),
+                (Function::Upper, &[ref s]) => format!("{}.toUpperCase()", s),
+                (Function::Lower, &[ref s]) => format!("{}.toLowerCase()", s),
+                (Function::Contains, &[ref s, ref sub]) => format!("({}.indexOf({}) !== -1)", s, sub),
+                (Function::Substr, &[ref s, ref start, ref length]) => {
+                    format!("{}.substr({}, {})", s, start, length)
+                }
+                (Function::Decimal, &[ref s]) => format!("Number({})", s),
+                (Function::Round, &[ref d, ref places]) |
+                (Function::RoundHalfUp, &[ref d, ref places]) => round("round", d, places),
+                (Function::Floor, &[ref d, ref places]) => round("floor", d, places),
+                (Function::Ceil, &[ref d, ref places]) => round("ceil", d, places),
+                (Function::Trunc, &[ref d, ref places]) => round("trunc", d, places),
+                (function, _) => {
+                    error!("expr_to_js: Unsupported call of '{}'", function);
+                    format!("(function() {{ throw new Error('Invalid expression'); }})()")
+                }
+            }
+        }
+        ExprKind::Unary(UnaryOp::Not, ref e) => format!("(!{})", to_js(e)),
+        ExprKind::Unary(UnaryOp::Neg, ref e) => format!("(-{})", to_js(e)),
+        ExprKind::Binary(BinaryOp::Div, ref l, ref r) => {
+            // Integer division truncates, decimal division does not
+            match expr.infer_type(types) {
+                Ok(XFlowValueType::Decimal) => format!("({} / {})", to_js(l), to_js(r)),
+                _ => format!("Math.trunc({} / {})", to_js(l), to_js(r)),
+            }
+        }
+        ExprKind::Binary(BinaryOp::Pow, ref l, ref r) => {
+            format!("Math.pow({}, {})", to_js(l), to_js(r))
+        }
+        ExprKind::Binary(op, ref l, ref r) => format!("({} {} {})", to_js(l), op, to_js(r)),
+        ExprKind::Field(ref e, ref name) => {
+            decimal_to_js(format!("{}.{}", to_js(e), name), expr, types)
+        }
+        ExprKind::Index(ref e, ref index) => {
+            decimal_to_js(format!("{}[{}]", to_js(e), to_js(index)), expr, types)
+        }
+    }
+}
+
 fn build_node_body_call_next_node(node: &XFlowNode, doc: &XFlowDocument) -> String {
     let edges = doc.body.get_out_edges(node);
     match edges.len() {
@@ -185,26 +357,160 @@ fn build_node_body_branch(node: &XFlowNode, doc: &XFlowDocument) -> String {
             )
         }
         _ => {
-            let mut res: Vec<String> = doc.body
-                .get_out_branches(node.id)
+            let branches = doc.body.get_out_branches(node.id);
+            let mut res: Vec<String> = branches
                 .iter()
+                .filter(|branch| !branch.default)
                 .map({
                     |branch| {
                         format!(
                             r#"
-                            if ({var} == {condition}) {{
+                            if ({condition}) {{
                                 this.{fn_id}();
                             }}
                              "#,
-                            var = branch.xvar.name,
-                            condition = branch.xvar.value.string_value(),
+                            condition = build_branch_condition(branch, doc),
                             fn_id = method_name_for_node_id(&branch.edge.1)
                         )
                     }
                 })
                 .collect();
-            res.push(format!("throw new Error('Unhandled branch');"));
-            res.join(";")
+            match branches.iter().find(|branch| branch.default) {
+                Some(branch) => {
+                    res.push(format!(
+                        "this.{fn_id}();",
+                        fn_id = method_name_for_node_id(&branch.edge.1)
+                    ))
+                }
+                None => res.push(format!("throw new Error('Unhandled branch');")),
+            }
+            res.join(" else ")
+        }
+    }
+}
+
+fn build_node_body_foreach(node: &XFlowNode, doc: &XFlowDocument) -> String {
+    let params = match node.parameters {
+        XFlowNodeParameters::Foreach(ref params) => params,
+        _ => return format!("throw new Error('Incorrect parameters for foreach node');"),
+    };
+
+    let done = doc.body.get_out_edges(node).into_iter().find(|edge| edge.1 != params.body);
+    let (body, done) = match done {
+        Some(done) => (method_name_for_node_id(&params.body), method_name_for_node_id(&done.1)),
+        None => {
+            error!("build_node_body_foreach: No edge out of the loop");
+            return format!("throw new Error('Foreach node without exit');");
+        }
+    };
+
+    let accumulator = match params.accumulator {
+        Some(ref xvar) => {
+            format!(
+                "this.local_vars.{name} = {value};",
+                name = xvar.name,
+                value = value_to_js(&xvar.value)
+            )
+        }
+        None => "".to_owned(),
+    };
+
+    format!(
+        r#"
+    var items = this.local_vars.{list};
+    if (this.local_vars.{index} === undefined) {{
+        this.local_vars.{index} = 0;
+        {accumulator}
+    }} else {{
+        this.local_vars.{index} += 1;
+    }}
+    if (this.local_vars.{index} < items.length) {{
+        this.local_vars.{element} = items[this.local_vars.{index}];
+        this.{body}();
+    }} else {{
+        delete this.local_vars.{index};
+        delete this.local_vars.{element};
+        this.{done}();
+    }}
+    "#,
+        list = params.list,
+        index = params.index,
+        element = params.element,
+        accumulator = accumulator,
+        body = body,
+        done = done
+    )
+}
+
+/// JS has no threads here, so the branches of a fork run one after the other
+fn build_node_body_fork(node: &XFlowNode, doc: &XFlowDocument) -> String {
+    let params = match node.parameters {
+        XFlowNodeParameters::Fork(ref params) => params,
+        _ => return format!("throw new Error('Incorrect parameters for fork node');"),
+    };
+
+    let branches: Vec<String> = doc.body
+        .get_out_edges(node)
+        .iter()
+        .map(|edge| format!("this.{}();", method_name_for_node_id(&edge.1)))
+        .collect();
+
+    format!(
+        r#"
+    this.joins = this.joins || {{}};
+    this.joins[{join}] = 0;
+    {branches}
+    "#,
+        join = params.join,
+        branches = branches.join(" ")
+    )
+}
+
+/// A join continues once every branch of its fork has arrived
+fn build_node_body_join(node: &XFlowNode, doc: &XFlowDocument) -> String {
+    let fork = doc.body.nodes.iter().find(|fork| match fork.parameters {
+        XFlowNodeParameters::Fork(ref params) => params.join == node.id,
+        _ => false,
+    });
+    let branches = match fork {
+        Some(fork) => doc.body.get_out_edges(fork).len(),
+        None => {
+            error!("build_node_body_join: No fork for join node");
+            return format!("throw new Error('Join node without fork');");
+        }
+    };
+
+    format!(
+        r#"
+    this.joins[{id}] += 1;
+    if (this.joins[{id}] === {branches}) {{
+        delete this.joins[{id}];
+        {call_next_node}
+    }}
+    "#,
+        id = node.id,
+        branches = branches,
+        call_next_node = build_node_body_call_next_node(node, doc)
+    )
+}
+
+fn build_branch_condition(branch: &XFlowBranch, doc: &XFlowDocument) -> String {
+    match (&branch.condition, &branch.xvar) {
+        (&Some(ref condition), _) => build_expression(condition, doc),
+        (&None, &Some(ref xvar)) if xvar.vtype == XFlowValueType::Decimal => {
+            format!(
+                "Number(this.local_vars.{var}) == Number({value})",
+                var = xvar.name,
+                value = value_to_js(&xvar.value)
+            )
+        }
+        (&None, &Some(ref xvar)) => {
+            format!(
+                "this.local_vars.{var} == {value}",
+                var = xvar.name,
+                value = value_to_js(&xvar.value)
+            )
         }
+        (&None, &None) => format!("false"),
     }
 }
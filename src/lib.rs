@@ -17,6 +17,10 @@ extern crate serde_yaml;
 extern crate log;
 extern crate glob;
 extern crate uuid;
+extern crate sha2;
+extern crate elsa;
+#[cfg(feature = "sled")]
+extern crate sled;
 
 extern crate ratel;
 
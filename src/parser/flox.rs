@@ -1,4 +1,8 @@
-use crate::structure::xflow::XFlowValue;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::structure::decimal::{Decimal, RoundingMode, MAX_EXPONENT};
+use crate::structure::xflow::{XFlowValue, XFlowValueType};
 use crate::runtime::xfstate::XFState;
 
 #[allow(dead_code)]
@@ -9,65 +13,952 @@ mod flox_grammar {
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Error {
     ParseError(String),
+    /// An integer or decimal division by zero
+    DivisionByZero(String),
+    /// An arithmetic result out of the range of its type
+    Overflow(String),
+    /// A negative or too large exponent
+    InvalidExponent(String),
 }
 
-pub fn parse_context(input: &str, state: &XFState) -> Result<XFlowValue, Error> {
-    match flox_grammar::expression(input, state) {
-        Ok(res) => Ok(res),
-        Err(err) => {
-            Err(Error::ParseError(
-                format!("Bad expression {:?} - Error : {:?}", input, err),
-            ))
+/// What made the evaluation of an expression fail
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The expression can not be parsed, or does not apply to the values it is given
+    Invalid,
+    DivisionByZero,
+    Overflow,
+    InvalidExponent,
+}
+
+/// Failure to parse or evaluate an expression, with the offset in the expression at
+/// which it failed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EvaluationError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub offset: usize,
+}
+
+impl EvaluationError {
+    /// Report the failure to evaluate `input` as an `Error`
+    pub fn into_error(self, input: &str) -> Error {
+        let kind = self.kind;
+        let message = format!("Bad expression {:?} - Error : {:?}", input, self);
+        match kind {
+            ErrorKind::Invalid => Error::ParseError(message),
+            ErrorKind::DivisionByZero => Error::DivisionByZero(message),
+            ErrorKind::Overflow => Error::Overflow(message),
+            ErrorKind::InvalidExponent => Error::InvalidExponent(message),
         }
     }
 }
 
-pub fn parse(input: &str) -> Result<XFlowValue, Error> {
-    let state = XFState::default();
-    match flox_grammar::expression(input, &state) {
+/// A type error found by `Expr::infer_type`, with the offset in the expression at which
+/// it was found
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+    And,
+    Or,
+}
+
+/// Built-in functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    /// `len(s)` - the number of characters in `s`
+    Len,
+    /// `count(l)` - the number of elements in the list `l`
+    Count,
+    /// `upper(s)`
+    Upper,
+    /// `lower(s)`
+    Lower,
+    /// `contains(s, sub)` - whether `sub` occurs in `s`
+    Contains,
+    /// `substr(s, start, length)` - at most `length` characters of `s` from character `start`
+    Substr,
+    /// `decimal(s)` - the decimal number in `s`
+    Decimal,
+    /// `round(d, places)` - `d` rounded half-even to `places` digits
+    Round,
+    /// `round_half_up(d, places)` - `d` rounded half away from zero to `places` digits
+    RoundHalfUp,
+    /// `floor(d, places)` - `d` rounded towards negative infinity to `places` digits
+    Floor,
+    /// `ceil(d, places)` - `d` rounded towards positive infinity to `places` digits
+    Ceil,
+    /// `trunc(d, places)` - `d` rounded towards zero to `places` digits
+    Trunc,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "len" => Some(Function::Len),
+            "count" => Some(Function::Count),
+            "upper" => Some(Function::Upper),
+            "lower" => Some(Function::Lower),
+            "contains" => Some(Function::Contains),
+            "substr" => Some(Function::Substr),
+            "decimal" => Some(Function::Decimal),
+            "round" => Some(Function::Round),
+            "round_half_up" => Some(Function::RoundHalfUp),
+            "floor" => Some(Function::Floor),
+            "ceil" => Some(Function::Ceil),
+            "trunc" => Some(Function::Trunc),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Function::Len => "len",
+            Function::Count => "count",
+            Function::Upper => "upper",
+            Function::Lower => "lower",
+            Function::Contains => "contains",
+            Function::Substr => "substr",
+            Function::Decimal => "decimal",
+            Function::Round => "round",
+            Function::RoundHalfUp => "round_half_up",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Trunc => "trunc",
+        }
+    }
+
+    /// The number of arguments taken by the function
+    pub fn arity(&self) -> usize {
+        self.signature().0.len()
+    }
+
+    /// The argument types and the return type of the function
+    pub fn signature(&self) -> (Vec<XFlowValueType>, XFlowValueType) {
+        match *self {
+            Function::Len => (vec![XFlowValueType::String], XFlowValueType::Integer),
+            Function::Count => (vec![XFlowValueType::List], XFlowValueType::Integer),
+            Function::Upper | Function::Lower => {
+                (vec![XFlowValueType::String], XFlowValueType::String)
+            }
+            Function::Contains => {
+                (
+                    vec![XFlowValueType::String, XFlowValueType::String],
+                    XFlowValueType::Boolean,
+                )
+            }
+            Function::Substr => {
+                (
+                    vec![
+                        XFlowValueType::String,
+                        XFlowValueType::Integer,
+                        XFlowValueType::Integer,
+                    ],
+                    XFlowValueType::String,
+                )
+            }
+            Function::Decimal => (vec![XFlowValueType::String], XFlowValueType::Decimal),
+            Function::Round |
+            Function::RoundHalfUp |
+            Function::Floor |
+            Function::Ceil |
+            Function::Trunc => {
+                (
+                    vec![XFlowValueType::Decimal, XFlowValueType::Integer],
+                    XFlowValueType::Decimal,
+                )
+            }
+        }
+    }
+
+    /// The rounding mode of the rounding functions
+    fn rounding_mode(&self) -> Option<RoundingMode> {
+        match *self {
+            Function::Round => Some(RoundingMode::HalfEven),
+            Function::RoundHalfUp => Some(RoundingMode::HalfUp),
+            Function::Floor => Some(RoundingMode::Floor),
+            Function::Ceil => Some(RoundingMode::Ceiling),
+            Function::Trunc => Some(RoundingMode::Down),
+            _ => None,
+        }
+    }
+}
+
+impl BinaryOp {
+    /// The result type of applying the operator to operands of type `l` and `r`, if the
+    /// operator applies to them
+    pub fn result_type(&self, l: &XFlowValueType, r: &XFlowValueType) -> Option<XFlowValueType> {
+        use crate::structure::xflow::XFlowValueType as T;
+
+        // The type of values of type `Any` is only known at runtime
+        if *l == T::Any || *r == T::Any {
+            return match *self {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Pow => {
+                    Some(T::Any)
+                }
+                _ => Some(T::Boolean),
+            };
+        }
+
+        let numeric = |t: &T| *t == T::Integer || *t == T::Decimal;
+        // Integers are promoted to decimals when the other operand is a decimal
+        let decimal = numeric(l) && numeric(r) && (*l == T::Decimal || *r == T::Decimal);
+
+        match (*self, l, r) {
+            (BinaryOp::Add, &T::Integer, &T::Integer) |
+            (BinaryOp::Sub, &T::Integer, &T::Integer) |
+            (BinaryOp::Mul, &T::Integer, &T::Integer) |
+            (BinaryOp::Div, &T::Integer, &T::Integer) |
+            (BinaryOp::Pow, &T::Integer, &T::Integer) => Some(T::Integer),
+            (BinaryOp::Add, _, _) |
+            (BinaryOp::Sub, _, _) |
+            (BinaryOp::Mul, _, _) |
+            (BinaryOp::Div, _, _) if decimal => Some(T::Decimal),
+            (BinaryOp::Pow, &T::Decimal, &T::Integer) => Some(T::Decimal),
+            (BinaryOp::Add, &T::String, &T::String) => Some(T::String),
+            (BinaryOp::Eq, l, r) |
+            (BinaryOp::Ne, l, r) if l == r || decimal => Some(T::Boolean),
+            (BinaryOp::Lt, l, r) |
+            (BinaryOp::Gt, l, r) |
+            (BinaryOp::Lte, l, r) |
+            (BinaryOp::Gte, l, r) if (l == r && *l != T::Boolean) || decimal => Some(T::Boolean),
+            (BinaryOp::And, &T::Boolean, &T::Boolean) |
+            (BinaryOp::Or, &T::Boolean, &T::Boolean) => Some(T::Boolean),
+            _ => None,
+        }
+    }
+}
+
+/// Read an integer or decimal value as a decimal
+fn to_decimal(value: &XFlowValue) -> Option<Decimal> {
+    match *value {
+        XFlowValue::Integer(i) => Some(Decimal::from_i64(i)),
+        XFlowValue::Decimal(d) => Some(d),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Integer(i64),
+    Decimal(Decimal),
+    Boolean(bool),
+    String(String),
+    Variable(String),
+    Call(Function, Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `e.name` - a field of a record
+    Field(Box<Expr>, String),
+    /// `e[index]` - an element of a list, or a field of a record
+    Index(Box<Expr>, Box<Expr>),
+}
+
+/// A field access or an index following an atom, as parsed by the grammar
+enum Accessor {
+    Field(String),
+    Index(Expr),
+}
+
+/// A parsed Flox expression. `offset` is the position in the source expression at which
+/// this part of the expression starts, or of the operator of a binary operation.
+///
+/// partof: #SPC-flox
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub offset: usize,
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnaryOp::Not => write!(f, "!"),
+            UnaryOp::Neg => write!(f, "-"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Pow => "^",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Lte => "<=",
+            BinaryOp::Gte => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ExprKind::Integer(i) => write!(f, "{}", i),
+            ExprKind::Decimal(d) => write!(f, "{}", d),
+            ExprKind::Boolean(b) => write!(f, "{}", b),
+            ExprKind::String(ref s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            ExprKind::Variable(ref name) => write!(f, "${}", name),
+            ExprKind::Call(function, ref args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", function, args.join(", "))
+            }
+            ExprKind::Unary(op, ref e) => write!(f, "{}({})", op, e),
+            ExprKind::Binary(op, ref l, ref r) => write!(f, "({} {} {})", l, op, r),
+            ExprKind::Field(ref e, ref name) => write!(f, "{}.{}", e, name),
+            ExprKind::Index(ref e, ref index) => write!(f, "{}[{}]", e, index),
+        }
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, offset: usize) -> Self {
+        Expr {
+            kind: kind,
+            offset: offset,
+        }
+    }
+
+    /// Apply a field access or an index at `offset` to the expression
+    fn access(self, offset: usize, accessor: Accessor) -> Self {
+        let kind = match accessor {
+            Accessor::Field(name) => ExprKind::Field(Box::new(self), name),
+            Accessor::Index(index) => ExprKind::Index(Box::new(self), Box::new(index)),
+        };
+        Expr::new(kind, offset)
+    }
+
+    /// Apply the binary operator `op` at `offset` to `l` and `r`
+    pub fn binary(op: BinaryOp, offset: usize, l: Expr, r: Expr) -> Self {
+        Expr::new(ExprKind::Binary(op, Box::new(l), Box::new(r)), offset)
+    }
+
+    /// Return the names of all variables referenced by the expression, in order of
+    /// appearance
+    ///
+    /// partof: #SPC-flox-variable-extraction
+    pub fn variable_names(&self) -> Vec<&str> {
+        let mut names = Vec::<&str>::new();
+        self.collect_variable_names(&mut names);
+        names
+    }
+
+    fn collect_variable_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self.kind {
+            ExprKind::Integer(_) |
+            ExprKind::Decimal(_) |
+            ExprKind::Boolean(_) |
+            ExprKind::String(_) => {}
+            ExprKind::Variable(ref name) => names.push(name),
+            ExprKind::Call(_, ref args) => {
+                for arg in args {
+                    arg.collect_variable_names(names);
+                }
+            }
+            ExprKind::Unary(_, ref e) |
+            ExprKind::Field(ref e, _) => e.collect_variable_names(names),
+            ExprKind::Binary(_, ref l, ref r) |
+            ExprKind::Index(ref l, ref r) => {
+                l.collect_variable_names(names);
+                r.collect_variable_names(names);
+            }
+        }
+    }
+
+    /// Infer the type of the expression from the types of the variables it references,
+    /// without evaluating it
+    ///
+    /// partof: #SPC-flox-type-checking
+    pub fn infer_type(
+        &self,
+        types: &HashMap<String, XFlowValueType>,
+    ) -> Result<XFlowValueType, TypeError> {
+        match self.kind {
+            ExprKind::Integer(_) => Ok(XFlowValueType::Integer),
+            ExprKind::Decimal(_) => Ok(XFlowValueType::Decimal),
+            ExprKind::Boolean(_) => Ok(XFlowValueType::Boolean),
+            ExprKind::String(_) => Ok(XFlowValueType::String),
+            ExprKind::Variable(ref name) => {
+                match types.get(name) {
+                    Some(vtype) => Ok(vtype.clone()),
+                    None => Err(self.type_error(format!("Variable '${}' is not declared", name))),
+                }
+            }
+            ExprKind::Call(function, ref args) => {
+                let (params, returns) = function.signature();
+                if args.len() != params.len() {
+                    return Err(self.type_error(format!(
+                        "Function '{}' takes {} argument(s), found {}",
+                        function,
+                        params.len(),
+                        args.len()
+                    )));
+                }
+                for (arg, param) in args.iter().zip(params.iter()) {
+                    let vtype = arg.infer_type(types)?;
+                    if !param.accepts(&vtype) {
+                        return Err(arg.type_error(format!(
+                            "Function '{}' expects {:?}, found {:?}",
+                            function,
+                            param,
+                            vtype
+                        )));
+                    }
+                }
+                Ok(returns)
+            }
+            ExprKind::Unary(UnaryOp::Not, ref e) => {
+                match e.infer_type(types)? {
+                    XFlowValueType::Boolean |
+                    XFlowValueType::Any => Ok(XFlowValueType::Boolean),
+                    vtype => Err(e.type_error(format!("Expected Boolean, found {:?}", vtype))),
+                }
+            }
+            ExprKind::Unary(UnaryOp::Neg, ref e) => {
+                match e.infer_type(types)? {
+                    XFlowValueType::Integer => Ok(XFlowValueType::Integer),
+                    XFlowValueType::Decimal => Ok(XFlowValueType::Decimal),
+                    XFlowValueType::Any => Ok(XFlowValueType::Any),
+                    vtype => Err(e.type_error(format!("Expected a number, found {:?}", vtype))),
+                }
+            }
+            ExprKind::Field(ref e, _) => {
+                match e.infer_type(types)? {
+                    XFlowValueType::Record |
+                    XFlowValueType::Any => Ok(XFlowValueType::Any),
+                    vtype => Err(e.type_error(format!("Expected a record, found {:?}", vtype))),
+                }
+            }
+            ExprKind::Index(ref e, ref index) => {
+                let it = index.infer_type(types)?;
+                match (e.infer_type(types)?, it) {
+                    (XFlowValueType::List, XFlowValueType::Integer) |
+                    (XFlowValueType::List, XFlowValueType::Any) |
+                    (XFlowValueType::Record, XFlowValueType::String) |
+                    (XFlowValueType::Record, XFlowValueType::Any) |
+                    (XFlowValueType::Any, _) => Ok(XFlowValueType::Any),
+                    (XFlowValueType::List, it) => {
+                        Err(index.type_error(format!("Expected an Integer index, found {:?}", it)))
+                    }
+                    (XFlowValueType::Record, it) => {
+                        Err(index.type_error(format!("Expected a String index, found {:?}", it)))
+                    }
+                    (vtype, _) => {
+                        Err(e.type_error(format!("Expected a list or a record, found {:?}", vtype)))
+                    }
+                }
+            }
+            ExprKind::Binary(op, ref l, ref r) => {
+                let lt = l.infer_type(types)?;
+                let rt = r.infer_type(types)?;
+                match op.result_type(&lt, &rt) {
+                    Some(vtype) => Ok(vtype),
+                    None => {
+                        Err(self.type_error(format!(
+                            "Operator '{}' can not be applied to {:?} and {:?}",
+                            op,
+                            lt,
+                            rt
+                        )))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Evaluate the expression against `state`
+    pub fn evaluate(&self, state: &XFState) -> Result<XFlowValue, EvaluationError> {
+        match self.kind {
+            ExprKind::Integer(i) => Ok(XFlowValue::Integer(i)),
+            ExprKind::Decimal(d) => Ok(XFlowValue::Decimal(d)),
+            ExprKind::Boolean(b) => Ok(XFlowValue::Boolean(b)),
+            ExprKind::String(ref s) => Ok(XFlowValue::String(s.clone())),
+            ExprKind::Call(function, ref args) => {
+                if args.len() != function.arity() {
+                    return Err(self.error(format!(
+                        "Function '{}' takes {} argument(s), found {}",
+                        function,
+                        function.arity(),
+                        args.len()
+                    )));
+                }
+                let mut values = Vec::<XFlowValue>::new();
+                for arg in args {
+                    values.push(arg.evaluate(state)?);
+                }
+                self.call(function, values)
+            }
+            ExprKind::Variable(ref name) => {
+                match state.get(name) {
+                    Some(xvar) => Ok(xvar.value.clone()),
+                    None => Err(self.error(format!("Variable '${}' not found in state", name))),
+                }
+            }
+            ExprKind::Unary(UnaryOp::Not, ref e) => {
+                match e.evaluate(state)? {
+                    XFlowValue::Boolean(b) => Ok(XFlowValue::Boolean(!b)),
+                    value => Err(e.error(format!("Expected a boolean, found {:?}", value))),
+                }
+            }
+            ExprKind::Unary(UnaryOp::Neg, ref e) => {
+                match e.evaluate(state)? {
+                    XFlowValue::Integer(i) => {
+                        match i.checked_neg() {
+                            Some(res) => Ok(XFlowValue::Integer(res)),
+                            None => Err(self.overflow(format!("Integer overflow in '-({})'", i))),
+                        }
+                    }
+                    XFlowValue::Decimal(d) => {
+                        match d.checked_neg() {
+                            Some(res) => Ok(XFlowValue::Decimal(res)),
+                            None => Err(self.overflow(format!("Decimal overflow in '-({})'", d))),
+                        }
+                    }
+                    value => Err(e.error(format!("Expected a number, found {:?}", value))),
+                }
+            }
+            ExprKind::Field(ref e, ref name) => {
+                match e.evaluate(state)? {
+                    XFlowValue::Record(mut fields) => {
+                        match fields.remove(name) {
+                            Some(value) => Ok(value),
+                            None => Err(self.error(format!("Field '{}' not found in {}", name, e))),
+                        }
+                    }
+                    value => Err(e.error(format!("Expected a record, found {:?}", value))),
+                }
+            }
+            ExprKind::Index(ref e, ref index) => {
+                match (e.evaluate(state)?, index.evaluate(state)?) {
+                    (XFlowValue::List(mut items), XFlowValue::Integer(i)) => {
+                        if i >= 0 && (i as u64) < items.len() as u64 {
+                            Ok(items.swap_remove(i as usize))
+                        } else {
+                            Err(index.error(format!(
+                                "Index {} out of range for a list of {}",
+                                i,
+                                items.len()
+                            )))
+                        }
+                    }
+                    (XFlowValue::Record(mut fields), XFlowValue::String(name)) => {
+                        match fields.remove(&name) {
+                            Some(value) => Ok(value),
+                            None => Err(index.error(format!("Field '{}' not found in {}", name, e))),
+                        }
+                    }
+                    (XFlowValue::List(_), value) => {
+                        Err(index.error(format!("Expected an integer index, found {:?}", value)))
+                    }
+                    (XFlowValue::Record(_), value) => {
+                        Err(index.error(format!("Expected a string index, found {:?}", value)))
+                    }
+                    (value, _) => {
+                        Err(e.error(format!("Expected a list or a record, found {:?}", value)))
+                    }
+                }
+            }
+            ExprKind::Binary(op, ref l, ref r) => {
+                let lv = l.evaluate(state)?;
+                // The right operand of && and || is only evaluated when it decides the
+                // result, so it can rely on the left one
+                match (op, &lv) {
+                    (BinaryOp::And, &XFlowValue::Boolean(false)) => return Ok(lv),
+                    (BinaryOp::Or, &XFlowValue::Boolean(true)) => return Ok(lv),
+                    _ => {}
+                }
+                let rv = r.evaluate(state)?;
+                if let (&XFlowValue::Decimal(_), _) | (_, &XFlowValue::Decimal(_)) = (&lv, &rv) {
+                    return self.evaluate_decimal(op, lv, rv);
+                }
+                match (op, lv, rv) {
+                    (BinaryOp::Add, XFlowValue::Integer(x), XFlowValue::Integer(y)) |
+                    (BinaryOp::Sub, XFlowValue::Integer(x), XFlowValue::Integer(y)) |
+                    (BinaryOp::Mul, XFlowValue::Integer(x), XFlowValue::Integer(y)) |
+                    (BinaryOp::Div, XFlowValue::Integer(x), XFlowValue::Integer(y)) |
+                    (BinaryOp::Pow, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        self.evaluate_integer(op, x, y)
+                    }
+                    (BinaryOp::Eq, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x == y))
+                    }
+                    (BinaryOp::Ne, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x != y))
+                    }
+                    (BinaryOp::Lt, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x < y))
+                    }
+                    (BinaryOp::Gt, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x > y))
+                    }
+                    (BinaryOp::Lte, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x <= y))
+                    }
+                    (BinaryOp::Gte, XFlowValue::Integer(x), XFlowValue::Integer(y)) => {
+                        Ok(XFlowValue::Boolean(x >= y))
+                    }
+                    (BinaryOp::Eq, XFlowValue::Boolean(x), XFlowValue::Boolean(y)) => {
+                        Ok(XFlowValue::Boolean(x == y))
+                    }
+                    (BinaryOp::Ne, XFlowValue::Boolean(x), XFlowValue::Boolean(y)) => {
+                        Ok(XFlowValue::Boolean(x != y))
+                    }
+                    (BinaryOp::Add, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::String(x + &y))
+                    }
+                    (BinaryOp::Eq, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x == y))
+                    }
+                    (BinaryOp::Ne, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x != y))
+                    }
+                    (BinaryOp::Lt, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x < y))
+                    }
+                    (BinaryOp::Gt, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x > y))
+                    }
+                    (BinaryOp::Lte, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x <= y))
+                    }
+                    (BinaryOp::Gte, XFlowValue::String(x), XFlowValue::String(y)) => {
+                        Ok(XFlowValue::Boolean(x >= y))
+                    }
+                    (BinaryOp::Eq, XFlowValue::List(x), XFlowValue::List(y)) => {
+                        Ok(XFlowValue::Boolean(x == y))
+                    }
+                    (BinaryOp::Ne, XFlowValue::List(x), XFlowValue::List(y)) => {
+                        Ok(XFlowValue::Boolean(x != y))
+                    }
+                    (BinaryOp::Eq, XFlowValue::Record(x), XFlowValue::Record(y)) => {
+                        Ok(XFlowValue::Boolean(x == y))
+                    }
+                    (BinaryOp::Ne, XFlowValue::Record(x), XFlowValue::Record(y)) => {
+                        Ok(XFlowValue::Boolean(x != y))
+                    }
+                    (BinaryOp::And, XFlowValue::Boolean(x), XFlowValue::Boolean(y)) => {
+                        Ok(XFlowValue::Boolean(x && y))
+                    }
+                    (BinaryOp::Or, XFlowValue::Boolean(x), XFlowValue::Boolean(y)) => {
+                        Ok(XFlowValue::Boolean(x || y))
+                    }
+                    (op, lv, rv) => {
+                        Err(self.error(format!(
+                            "Operator '{}' can not be applied to {:?} and {:?}",
+                            op,
+                            lv,
+                            rv
+                        )))
+                    }
+                }
+            }
+        }
+    }
+
+    fn evaluate_integer(&self, op: BinaryOp, x: i64, y: i64) -> Result<XFlowValue, EvaluationError> {
+        let res = match op {
+            BinaryOp::Add => x.checked_add(y),
+            BinaryOp::Sub => x.checked_sub(y),
+            BinaryOp::Mul => x.checked_mul(y),
+            BinaryOp::Div if y == 0 => {
+                return Err(self.division_by_zero(format!("Division by zero in '{} / {}'", x, y)))
+            }
+            BinaryOp::Div => x.checked_div(y),
+            BinaryOp::Pow if y < 0 || y > i64::from(u32::max_value()) => {
+                return Err(self.invalid_exponent(format!(
+                    "Exponent {} in '{} ^ {}' is not between 0 and {}",
+                    y,
+                    x,
+                    y,
+                    u32::max_value()
+                )))
+            }
+            BinaryOp::Pow => x.checked_pow(y as u32),
+            _ => {
+                return Err(self.error(format!(
+                    "Operator '{}' can not be applied to {:?} and {:?}",
+                    op,
+                    x,
+                    y
+                )))
+            }
+        };
+
+        match res {
+            Some(i) => Ok(XFlowValue::Integer(i)),
+            None => Err(self.overflow(format!("Integer overflow in '{} {} {}'", x, op, y))),
+        }
+    }
+
+    fn evaluate_decimal(
+        &self,
+        op: BinaryOp,
+        lv: XFlowValue,
+        rv: XFlowValue,
+    ) -> Result<XFlowValue, EvaluationError> {
+        let (x, y) = match (to_decimal(&lv), to_decimal(&rv)) {
+            (Some(x), Some(y)) => (x, y),
+            _ => {
+                return Err(self.error(format!(
+                    "Operator '{}' can not be applied to {:?} and {:?}",
+                    op,
+                    lv,
+                    rv
+                )))
+            }
+        };
+
+        let res = match op {
+            BinaryOp::Add => x.checked_add(&y),
+            BinaryOp::Sub => x.checked_sub(&y),
+            BinaryOp::Mul => x.checked_mul(&y),
+            BinaryOp::Div if y.is_zero() => {
+                return Err(self.division_by_zero(format!("Division by zero in '{} / {}'", x, y)))
+            }
+            BinaryOp::Div => x.checked_div(&y),
+            BinaryOp::Pow => {
+                match rv {
+                    XFlowValue::Integer(exp) if exp >= 0 && exp <= i64::from(MAX_EXPONENT) => {
+                        x.checked_pow(exp as u32)
+                    }
+                    _ => {
+                        return Err(self.invalid_exponent(format!(
+                            "A decimal can only be raised to an integer power between 0 and {}, found {:?}",
+                            MAX_EXPONENT,
+                            rv
+                        )))
+                    }
+                }
+            }
+            BinaryOp::Eq => return Ok(XFlowValue::Boolean(x == y)),
+            BinaryOp::Ne => return Ok(XFlowValue::Boolean(x != y)),
+            BinaryOp::Lt => return Ok(XFlowValue::Boolean(x < y)),
+            BinaryOp::Gt => return Ok(XFlowValue::Boolean(x > y)),
+            BinaryOp::Lte => return Ok(XFlowValue::Boolean(x <= y)),
+            BinaryOp::Gte => return Ok(XFlowValue::Boolean(x >= y)),
+            BinaryOp::And | BinaryOp::Or => {
+                return Err(self.error(format!(
+                    "Operator '{}' can not be applied to {:?} and {:?}",
+                    op,
+                    lv,
+                    rv
+                )))
+            }
+        };
+
+        match res {
+            Some(d) => Ok(XFlowValue::Decimal(d)),
+            None => Err(self.overflow(format!("Decimal overflow in '{} {} {}'", x, op, y))),
+        }
+    }
+
+    fn call(&self, function: Function, args: Vec<XFlowValue>) -> Result<XFlowValue, EvaluationError> {
+        if let Some(mode) = function.rounding_mode() {
+            return match (to_decimal(&args[0]), &args[1]) {
+                (Some(d), &XFlowValue::Integer(places)) if places >= 0 && places <= 28 => {
+                    Ok(XFlowValue::Decimal(d.round(places as u32, mode)))
+                }
+                _ => {
+                    Err(self.error(format!(
+                        "Function '{}' takes a number and between 0 and 28 places, found {:?}",
+                        function,
+                        args
+                    )))
+                }
+            };
+        }
+
+        match (function, args.as_slice()) {
+            (Function::Decimal, &[XFlowValue::String(ref s)]) => {
+                match s.trim().parse() {
+                    Ok(d) => Ok(XFlowValue::Decimal(d)),
+                    Err(err) => Err(self.error(err)),
+                }
+            }
+            (Function::Len, &[XFlowValue::String(ref s)]) => {
+                Ok(XFlowValue::Integer(s.chars().count() as i64))
+            }
+            (Function::Count, &[XFlowValue::List(ref items)]) => {
+                Ok(XFlowValue::Integer(items.len() as i64))
+            }
+            (Function::Upper, &[XFlowValue::String(ref s)]) => {
+                Ok(XFlowValue::String(s.to_uppercase()))
+            }
+            (Function::Lower, &[XFlowValue::String(ref s)]) => {
+                Ok(XFlowValue::String(s.to_lowercase()))
+            }
+            (Function::Contains, &[XFlowValue::String(ref s), XFlowValue::String(ref sub)]) => {
+                Ok(XFlowValue::Boolean(s.contains(sub.as_str())))
+            }
+            (Function::Substr,
+             &[XFlowValue::String(ref s), XFlowValue::Integer(start), XFlowValue::Integer(length)]) => {
+                if start < 0 || length < 0 {
+                    return Err(self.error(format!(
+                        "Function 'substr' takes a positive start and length, found {} and {}",
+                        start,
+                        length
+                    )));
+                }
+                Ok(XFlowValue::String(
+                    s.chars().skip(start as usize).take(length as usize).collect(),
+                ))
+            }
+            (function, args) => {
+                Err(self.error(format!(
+                    "Function '{}' can not be applied to {:?}",
+                    function,
+                    args
+                )))
+            }
+        }
+    }
+
+    fn type_error(&self, message: String) -> TypeError {
+        TypeError {
+            message: message,
+            offset: self.offset,
+        }
+    }
+
+    fn error(&self, message: String) -> EvaluationError {
+        self.error_of_kind(ErrorKind::Invalid, message)
+    }
+
+    fn division_by_zero(&self, message: String) -> EvaluationError {
+        self.error_of_kind(ErrorKind::DivisionByZero, message)
+    }
+
+    fn overflow(&self, message: String) -> EvaluationError {
+        self.error_of_kind(ErrorKind::Overflow, message)
+    }
+
+    fn invalid_exponent(&self, message: String) -> EvaluationError {
+        self.error_of_kind(ErrorKind::InvalidExponent, message)
+    }
+
+    fn error_of_kind(&self, kind: ErrorKind, message: String) -> EvaluationError {
+        EvaluationError {
+            kind: kind,
+            message: message,
+            offset: self.offset,
+        }
+    }
+}
+
+/// Parse `input` into an expression that can be evaluated any number of times
+pub fn compile(input: &str) -> Result<Expr, EvaluationError> {
+    match flox_grammar::expression(input) {
         Ok(res) => Ok(res),
         Err(err) => {
-            Err(Error::ParseError(
-                format!("Bad expression {:?} - Error : {:?}", input, err),
-            ))
+            let mut expected: Vec<&str> = err.expected.iter().cloned().collect();
+            expected.sort();
+            Err(EvaluationError {
+                kind: ErrorKind::Invalid,
+                message: format!("Expected one of {:?}", expected),
+                offset: err.offset,
+            })
         }
     }
 }
 
+/// Parse and evaluate `input` against `state`, reporting the position of any failure
+pub fn evaluate(input: &str, state: &XFState) -> Result<XFlowValue, EvaluationError> {
+    compile(input)?.evaluate(state)
+}
+
+pub fn parse_context(input: &str, state: &XFState) -> Result<XFlowValue, Error> {
+    evaluate(input, state).map_err(|err| err.into_error(input))
+}
+
+pub fn parse(input: &str) -> Result<XFlowValue, Error> {
+    parse_context(input, &XFState::default())
+}
+
 pub fn parse_arithmetic(input: &str) -> Result<XFlowValue, Error> {
-    let state = XFState::default();
-    match flox_grammar::arithmetic_expression(input, &state) {
-        Ok(res) => Ok(res),
-        Err(err) => {
-            Err(Error::ParseError(
-                format!("Bad expression {:?} - Error : {:?}", input, err),
-            ))
+    match parse(input)? {
+        XFlowValue::Integer(i) => Ok(XFlowValue::Integer(i)),
+        XFlowValue::Decimal(d) => Ok(XFlowValue::Decimal(d)),
+        res => {
+            Err(Error::ParseError(format!(
+                "Bad expression {:?} - Error : not arithmetic, found {:?}",
+                input,
+                res
+            )))
         }
     }
 }
 
 pub fn parse_boolean(input: &str) -> Result<XFlowValue, Error> {
-    let state = XFState::default();
-    match flox_grammar::boolean_expression(input, &state) {
-        Ok(res) => Ok(res),
-        Err(err) => {
-            Err(Error::ParseError(
-                format!("Bad expression {:?} - Error : {:?}", input, err),
-            ))
+    match parse(input)? {
+        XFlowValue::Boolean(b) => Ok(XFlowValue::Boolean(b)),
+        res => {
+            Err(Error::ParseError(format!(
+                "Bad expression {:?} - Error : not boolean, found {:?}",
+                input,
+                res
+            )))
         }
     }
 }
+
 // #SPC-flox-variable-extraction
-pub fn extract_variable_names(input: &str) -> Result<Vec<&str>, Error> {
-    let state = XFState::default();
-    match flox_grammar::variable_names(input, &state) {
-        //
-        // XXX: Currently reporting zero variables for bad expressions
-        //
-        Ok(res) => Ok(res),
-        Err(_ /* err */) => {
-            Ok(Vec::<&str>::default())
-            // Err(Error::ParseError(format!("Bad expression {:?} - Error : {:?}", input, err)))
+pub fn extract_variable_names(input: &str) -> Result<Vec<String>, Error> {
+    match compile(input) {
+        Ok(expr) => Ok(expr.variable_names().iter().map(|name| (*name).to_owned()).collect()),
+        Err(err) => {
+            Err(Error::ParseError(
+                format!("Bad expression {:?} - Error : {:?}", input, err),
+            ))
         }
     }
 }
@@ -0,0 +1,305 @@
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use elsa::sync::FrozenMap;
+use uuid::Uuid;
+
+use crate::runtime::actiondispatch::dispatchable::*;
+use crate::runtime::docstore::XFlowSource;
+use crate::runtime::error::RuntimeError;
+use crate::runtime::xfrunner::{OwnedRunAsync, XFlowLimits, XFlowRunner};
+use crate::runtime::xfstate::XFState;
+use crate::structure::model::ModelDocument;
+use crate::structure::xflow::*;
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 32;
+
+/// Dispatch receiver for `call` nodes. Called flows are resolved from an `XFlowSource`,
+/// such as the enclosing `ModelDocument` or a `DocStore`, and run in a nested
+/// `XFlowRunner` with the dispatcher of the calling flow, so they can use every receiver
+/// registered there. Registered as an asynchronous receiver as well, it runs called
+/// flows with `XFlowRunner::run_async` when the calling flow runs asynchronously.
+#[derive(Debug)]
+pub struct Call<'a> {
+    source: &'a dyn XFlowSource,
+    max_depth: usize,
+    limits: XFlowLimits,
+    /// The flows called asynchronously, by id and version, which their runs borrow
+    loaded: FrozenMap<(Uuid, i64), Box<XFlowDocument>>,
+}
+
+impl<'a> Call<'a> {
+    /// Constructs a new `Call` receiver that resolves flows from `model`
+    pub fn new(model: &'a ModelDocument) -> Self {
+        Self::from_source(model)
+    }
+
+    /// Constructs a new `Call` receiver that resolves flows from `source`
+    pub fn from_source(source: &'a dyn XFlowSource) -> Self {
+        Call {
+            source: source,
+            max_depth: DEFAULT_MAX_CALL_DEPTH,
+            limits: XFlowLimits::default(),
+            loaded: FrozenMap::new(),
+        }
+    }
+
+    /// Set the maximum nesting depth of flows calling other flows
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the execution limits applied to each called flow, on top of what remains of
+    /// the limits of the calling flow
+    pub fn with_limits(mut self, limits: XFlowLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Check the call of `node` and load the called flow, returning the parameters of
+    /// the node, the flow and its input
+    fn prepare<'n>(
+        &self,
+        node: &'n XFlowNode,
+        state: &XFState,
+        context: &DispatchContext,
+    ) -> Result<(&'n CallParameters, XFlowDocument, XFState), RuntimeError> {
+        debug!("Call: {} - {} - depth {}", node.id, state, context.depth);
+
+        let params = match node.parameters {
+            XFlowNodeParameters::Call(ref params) => params,
+            _ => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: "Incorrect parameters for Call node".to_owned(),
+                    state: state.clone(),
+                })
+            }
+        };
+
+        if node.action != "call" {
+            return Err(RuntimeError::UnknownNode {
+                node: node.id,
+                nodetype: node.nodetype.clone(),
+                action: node.action.clone(),
+                state: state.clone(),
+            });
+        }
+
+        if context.depth >= self.max_depth {
+            return Err(RuntimeError::Dispatch {
+                node: node.id,
+                message: format!(
+                    "Maximum call depth of {} exceeded calling xflow '{}'",
+                    self.max_depth,
+                    params.xflowid
+                ),
+                state: state.clone(),
+            });
+        }
+
+        let xflow = match self.source.get_xflow_document(&params.xflowid) {
+            Ok(Some(xflow)) => xflow,
+            Ok(None) => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: format!(
+                        "XFlow '{}' not found in {}",
+                        params.xflowid,
+                        self.source.describe()
+                    ),
+                    state: state.clone(),
+                })
+            }
+            Err(err) => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: format!("Unable to load xflow '{}' : {}", params.xflowid, err),
+                    state: state.clone(),
+                })
+            }
+        };
+
+        let mut input = XFState::default();
+        for &(ref from, ref to) in &params.inputmatch {
+            match state.get(from) {
+                Some(xvar) => {
+                    input.add(&XFlowVariable {
+                        name: to.clone(),
+                        vtype: xvar.vtype.clone(),
+                        value: xvar.value.clone(),
+                    })
+                }
+                None => {
+                    return Err(RuntimeError::MissingVariable {
+                        node: Some(node.id),
+                        name: from.clone(),
+                        state: state.clone(),
+                    })
+                }
+            }
+        }
+
+        Ok((params, xflow, input))
+    }
+
+    /// The runner of the called flow `xflow`, one level deeper in `context`
+    fn runner<'r>(
+        &self,
+        xflow: &'r XFlowDocument,
+        input: &XFState,
+        context: &DispatchContext<'r>,
+    ) -> Result<XFlowRunner<'r>, RuntimeError> {
+        Ok(XFlowRunner::new(xflow, context.dispatcher, input)?
+            .with_limits(self.limits.clone())
+            .with_budget(context.budget.clone())
+            .with_call_depth(context.depth + 1))
+    }
+
+    fn process_node(
+        &self,
+        node: &XFlowNode,
+        state: &mut XFState,
+        context: &DispatchContext,
+    ) -> Result<(), RuntimeError> {
+        let (params, xflow, input) = self.prepare(node, state, context)?;
+        let output = self.runner(&xflow, &input, context).and_then(|mut runner| {
+            runner.run()?;
+            runner.get_output()
+        });
+        finish_call(node, params, xflow.id, state, output)
+    }
+}
+
+/// Copy the output of the flow called by `node` into `state`
+fn finish_call(
+    node: &XFlowNode,
+    params: &CallParameters,
+    xflowid: Uuid,
+    state: &mut XFState,
+    output: Result<XFState, RuntimeError>,
+) -> Result<(), RuntimeError> {
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            return Err(RuntimeError::CallFailed {
+                node: node.id,
+                xflowid: xflowid,
+                source: Box::new(err),
+                state: state.clone(),
+            })
+        }
+    };
+
+    for &(ref from, ref to) in &params.outputmatch {
+        match output.get(from) {
+            Some(xvar) => {
+                state.add(&XFlowVariable {
+                    name: to.clone(),
+                    vtype: xvar.vtype.clone(),
+                    value: xvar.value.clone(),
+                })
+            }
+            None => {
+                return Err(RuntimeError::MissingVariable {
+                    node: Some(node.id),
+                    name: from.clone(),
+                    state: output.clone(),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The asynchronous run of a flow called by `node`, which holds the state of the
+/// calling flow until the run completes
+struct CallRun<'f> {
+    node: &'f XFlowNode,
+    params: &'f CallParameters,
+    xflowid: Uuid,
+    state: Option<XFState>,
+    run: OwnedRunAsync<'f>,
+}
+
+impl<'f> Future for CallRun<'f> {
+    type Output = (XFState, Result<(), RuntimeError>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.run).poll(cx) {
+            Poll::Ready((runner, res)) => {
+                let output = res.and_then(|_| runner.get_output());
+                let mut state = this.state.take().unwrap();
+                let res = finish_call(this.node, this.params, this.xflowid, &mut state, output);
+                Poll::Ready((state, res))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'a> Dispatchable for Call<'a> {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        Err(RuntimeError::Dispatch {
+            node: node.id,
+            message: "Call nodes can only be dispatched by a Dispatcher".to_owned(),
+            state: state.clone(),
+        })
+    }
+
+    fn dispatch_in(
+        &self,
+        node: &XFlowNode,
+        state: &mut XFState,
+        context: &DispatchContext,
+    ) -> Result<(), RuntimeError> {
+        self.process_node(node, state, context)
+    }
+}
+
+impl<'a> AsyncDispatchable for Call<'a> {
+    fn dispatch_async<'f>(&'f self, node: &'f XFlowNode, state: XFState) -> DispatchFuture<'f> {
+        let res = Err(RuntimeError::Dispatch {
+            node: node.id,
+            message: "Call nodes can only be dispatched by a Dispatcher".to_owned(),
+            state: state.clone(),
+        });
+        Box::pin(future::ready((state, res)))
+    }
+
+    fn dispatch_async_in<'f>(
+        &'f self,
+        node: &'f XFlowNode,
+        mut state: XFState,
+        context: DispatchContext<'f>,
+    ) -> DispatchFuture<'f> {
+        let (params, xflow, input) = match self.prepare(node, &state, &context) {
+            Ok(prepared) => prepared,
+            Err(err) => return Box::pin(future::ready((state, Err(err)))),
+        };
+        let xflow = self.loaded.insert((xflow.id, xflow.version), Box::new(xflow));
+        match self.runner(xflow, &input, &context) {
+            Ok(runner) => {
+                Box::pin(CallRun {
+                    node: node,
+                    params: params,
+                    xflowid: xflow.id,
+                    state: Some(state),
+                    run: runner.into_run_async(),
+                })
+            }
+            Err(err) => {
+                let res = finish_call(node, params, xflow.id, &mut state, Err(err));
+                Box::pin(future::ready((state, res)))
+            }
+        }
+    }
+}
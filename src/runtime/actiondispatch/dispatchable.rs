@@ -1,8 +1,93 @@
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+
 use crate::structure::xflow::*;
+use crate::parser::flox;
+use crate::runtime::dispatcher::Dispatcher;
+use crate::runtime::xfrunner::XFlowBudget;
 use crate::runtime::xfstate::XFState;
+use crate::runtime::error::RuntimeError;
+
+/// The run a node is dispatched in: the dispatcher running the flow, how deep the flow
+/// is nested in flows calling it, what remains of the execution limits of the run, and
+/// the Flox expressions of the nodes of the flow, by node id, compiled when it was loaded
+#[derive(Clone)]
+pub struct DispatchContext<'c> {
+    pub dispatcher: &'c Dispatcher<'c>,
+    pub depth: usize,
+    pub budget: XFlowBudget,
+    pub expressions: Arc<HashMap<i32, flox::Expr>>,
+}
+
+impl<'c> DispatchContext<'c> {
+    /// The context of a flow run directly by `dispatcher`
+    pub fn new(dispatcher: &'c Dispatcher<'c>) -> Self {
+        DispatchContext {
+            dispatcher: dispatcher,
+            depth: 0,
+            budget: XFlowBudget::default(),
+            expressions: Arc::new(HashMap::new()),
+        }
+    }
+}
 
-pub trait Dispatchable {
+/// A receiver for the nodes of one node type. Receivers are shared between the threads
+/// running the branches of a fork, so they have to be `Sync`.
+pub trait Dispatchable: Sync {
     fn init(&mut self) -> Result<(), ()>;
 
-    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), ()>;
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError>;
+
+    /// Dispatch `node` in `context`. Receivers running flows of their own, such as
+    /// `Call`, run them with the dispatcher of the context; the others ignore it.
+    fn dispatch_in(
+        &self,
+        node: &XFlowNode,
+        state: &mut XFState,
+        _context: &DispatchContext,
+    ) -> Result<(), RuntimeError> {
+        self.dispatch(node, state)
+    }
+}
+
+/// The pending dispatch of a node by an `AsyncDispatchable`. It resolves to the state,
+/// changed by the node on success, and the result of the dispatch.
+pub type DispatchFuture<'f> = Pin<Box<dyn Future<Output = (XFState, Result<(), RuntimeError>)> + 'f>>;
+
+/// A receiver whose nodes wait for I/O, such as database or HTTP calls, without
+/// blocking the executor of an `XFlowRunner::run_async`. The state is moved into the
+/// dispatch and handed back when it completes.
+pub trait AsyncDispatchable: Sync {
+    fn dispatch_async<'f>(&'f self, node: &'f XFlowNode, state: XFState) -> DispatchFuture<'f>;
+
+    /// Dispatch `node` in `context`, as `Dispatchable::dispatch_in` does. The context
+    /// moves into the dispatch, which may outlive the call.
+    fn dispatch_async_in<'f>(
+        &'f self,
+        node: &'f XFlowNode,
+        state: XFState,
+        _context: DispatchContext<'f>,
+    ) -> DispatchFuture<'f> {
+        self.dispatch_async(node, state)
+    }
+}
+
+/// Runs a synchronous receiver as an asynchronous one. The dispatch happens when it is
+/// started and the returned future is ready at once.
+pub struct SyncAdapter<'r>(pub &'r dyn Dispatchable);
+
+impl<'r> SyncAdapter<'r> {
+    /// Dispatch `node` right away and return the outcome as a ready future
+    pub fn dispatch_ready(&self, node: &XFlowNode, mut state: XFState) -> DispatchFuture<'static> {
+        let res = self.0.dispatch(node, &mut state);
+        Box::pin(future::ready((state, res)))
+    }
+}
+
+impl<'r> AsyncDispatchable for SyncAdapter<'r> {
+    fn dispatch_async<'f>(&'f self, node: &'f XFlowNode, state: XFState) -> DispatchFuture<'f> {
+        self.dispatch_ready(node, state)
+    }
 }
@@ -1,28 +1,129 @@
 use crate::runtime::actiondispatch::dispatchable::*;
 use crate::structure::xflow::*;
 use crate::runtime::xfstate::XFState;
+use crate::runtime::error::RuntimeError;
 
 #[derive(Debug)]
 pub struct Flow;
 
 impl Flow {
-    fn process_node(&self, node: &XFlowNode, state: &mut XFState) -> () {
+    fn process_node(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
         debug!("Flow: {} - {}", node.id, state);
         match node.action.as_ref() {
             "start" => {
                 debug!("Start {} - {}", node.id, state);
+                Ok(())
             }
             "end" => {
                 debug!("End {} - {}", node.id, state);
+                Ok(())
             }
             "branch" => {
                 debug!("Branch {} - {}", node.id, state);
+                Ok(())
+            }
+            "fork" => {
+                debug!("Fork {} - {}", node.id, state);
+                Ok(())
+            }
+            "join" => {
+                debug!("Join {} - {}", node.id, state);
+                Ok(())
+            }
+            "foreach" => {
+                debug!("Foreach {} - {}", node.id, state);
+                match node.parameters {
+                    XFlowNodeParameters::Foreach(ref params) => self.foreach(node, params, state),
+                    _ => Err(RuntimeError::Dispatch {
+                        node: node.id,
+                        message: "Incorrect parameters for foreach node".to_owned(),
+                        state: state.clone(),
+                    }),
+                }
             }
             _ => {
                 error!("Unimplemented/unhandled {} - {}", node.id, state);
+                Err(RuntimeError::UnknownNode {
+                    node: node.id,
+                    nodetype: node.nodetype.clone(),
+                    action: node.action.clone(),
+                    state: state.clone(),
+                })
+            }
+
+        }
+    }
+}
+
+impl Flow {
+    /// Move a `foreach` loop to its next element. The loop starts when the index
+    /// variable is not in the state, and ends by removing the index and element
+    /// variables, which makes the runner leave the loop.
+    fn foreach(
+        &self,
+        node: &XFlowNode,
+        params: &ForeachParameters,
+        state: &mut XFState,
+    ) -> Result<(), RuntimeError> {
+        let index = match state.get(&params.index) {
+            Some(&XFlowVariable { value: XFlowValue::Integer(i), .. }) => i + 1,
+            Some(xvar) => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: format!("Foreach index '{}' is not an integer", xvar.name),
+                    state: state.clone(),
+                })
             }
+            None => {
+                if let Some(ref accumulator) = params.accumulator {
+                    state.add(accumulator);
+                }
+                0
+            }
+        };
+
+        let element = match state.get(&params.list) {
+            Some(&XFlowVariable { value: XFlowValue::List(ref items), .. }) => {
+                items.get(index as usize).cloned()
+            }
+            Some(xvar) => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: format!("Foreach variable '{}' is not a list", xvar.name),
+                    state: state.clone(),
+                })
+            }
+            None => {
+                return Err(RuntimeError::MissingVariable {
+                    node: Some(node.id),
+                    name: params.list.clone(),
+                    state: state.clone(),
+                })
+            }
+        };
 
+        match element {
+            Some(value) => {
+                debug!("Foreach {} - element {} of '{}'", node.id, index, params.list);
+                state.add(&XFlowVariable {
+                    name: params.index.clone(),
+                    vtype: XFlowValueType::Integer,
+                    value: XFlowValue::Integer(index),
+                });
+                state.add(&XFlowVariable {
+                    name: params.element.clone(),
+                    vtype: value.vtype(),
+                    value: value,
+                });
+            }
+            None => {
+                debug!("Foreach {} - done with '{}'", node.id, params.list);
+                state.remove(&params.index);
+                state.remove(&params.element);
+            }
         }
+
+        Ok(())
     }
 }
 
@@ -37,8 +138,7 @@ impl Dispatchable for Flow {
         Ok(())
     }
 
-    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), ()> {
-        self.process_node(node, state);
-        Ok(())
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        self.process_node(node, state)
     }
 }
@@ -1,6 +1,7 @@
 use crate::runtime::actiondispatch::dispatchable::*;
 use crate::structure::xflow::*;
 use crate::runtime::xfstate::XFState;
+use crate::runtime::error::RuntimeError;
 use crate::parser::flox;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -9,11 +10,20 @@ struct FloxParameters {
     returns: XFlowVariableDefinition,
 }
 
-#[derive(Debug)]
+/// Dispatch receiver for `flox` nodes. The expressions of the nodes of a flow are
+/// compiled once when its `XFlowRunner` is created, and handed to the receiver in the
+/// `DispatchContext`. Nodes dispatched outside of a runner are compiled on every
+/// dispatch.
+#[derive(Debug, Default)]
 pub struct Flox;
 
 impl Flox {
-    fn process_node(&self, node: &XFlowNode, state: &mut XFState) -> () {
+    fn process_node(
+        &self,
+        node: &XFlowNode,
+        state: &mut XFState,
+        compiled: Option<&flox::Expr>,
+    ) -> Result<(), RuntimeError> {
         debug!("Flox: {} - {}", node.id, state);
         match node.parameters {
             XFlowNodeParameters::Flox(ref node_params) => {
@@ -27,17 +37,54 @@ impl Flox {
                         );
                         let expr = node_params.expression.as_str();
                         debug!("Expression: '{}'", expr);
-                        match flox::parse_context(node_params.expression.as_str(), state) {
+                        // Integer results are stored as decimals in decimal variables
+                        let vtype = &node_params.returns.vtype;
+                        let res = match compiled {
+                            Some(compiled) => compiled.evaluate(state),
+                            None => flox::compile(expr).and_then(|compiled| compiled.evaluate(state)),
+                        };
+                        match res.and_then(
+                            |res| {
+                                res.coerce(vtype).map_err(|message| {
+                                    flox::EvaluationError {
+                                        kind: flox::ErrorKind::Invalid,
+                                        message: message,
+                                        offset: 0,
+                                    }
+                                })
+                            },
+                        ) {
                             Ok(res) => {
                                 debug!("Expression: '{}' - Result: '{:?}'", expr, res);
+                                let name = &node_params.returns.name;
+                                if name.contains('.') {
+                                    // A field of a record or an element of a list
+                                    return state.set_path(name, res).map_err(|message| {
+                                        RuntimeError::FloxEvaluation {
+                                            node: node.id,
+                                            expression: expr.to_owned(),
+                                            position: 0,
+                                            message: message,
+                                            state: state.clone(),
+                                        }
+                                    });
+                                }
                                 state.add(&XFlowVariable {
-                                    name: node_params.returns.name.clone(),
+                                    name: name.clone(),
                                     vtype: node_params.returns.vtype.clone(),
                                     value: res.clone(),
                                 });
+                                Ok(())
                             }
                             Err(err) => {
                                 error!("Expression: '{}' - Result: '{:?}'", expr, err);
+                                Err(RuntimeError::FloxEvaluation {
+                                    node: node.id,
+                                    expression: expr.to_owned(),
+                                    position: err.offset,
+                                    message: err.message,
+                                    state: state.clone(),
+                                })
                             }
 
                         }
@@ -48,6 +95,12 @@ impl Flox {
                             node.id,
                             state
                         );
+                        Err(RuntimeError::UnknownNode {
+                            node: node.id,
+                            nodetype: node.nodetype.clone(),
+                            action: node.action.clone(),
+                            state: state.clone(),
+                        })
                     }
                 }
             }
@@ -56,24 +109,31 @@ impl Flox {
                     "Incorrect NodeType dispatched to Flox processor {:?}!",
                     node
                 );
+                Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: "Incorrect parameters for Flox node".to_owned(),
+                    state: state.clone(),
+                })
             }
         }
     }
 }
 
-impl Default for Flox {
-    fn default() -> Self {
-        Flox {}
-    }
-}
-
 impl Dispatchable for Flox {
     fn init(&mut self) -> Result<(), ()> {
         Ok(())
     }
 
-    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), ()> {
-        self.process_node(node, state);
-        Ok(())
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        self.process_node(node, state, None)
+    }
+
+    fn dispatch_in(
+        &self,
+        node: &XFlowNode,
+        state: &mut XFState,
+        context: &DispatchContext,
+    ) -> Result<(), RuntimeError> {
+        self.process_node(node, state, context.expressions.get(&node.id))
     }
 }
@@ -1,3 +1,4 @@
 pub mod dispatchable;
 pub mod flow;
 pub mod flox;
+pub mod call;
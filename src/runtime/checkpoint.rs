@@ -0,0 +1,43 @@
+use serde_json;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::runtime::error::RuntimeError;
+use crate::runtime::xfrunner::XFlowStatus;
+use crate::runtime::xfstate::XFState;
+use crate::structure::xflow::XFlowEdge;
+
+/// A serializable snapshot of a paused `XFlowRunner`, which can be resumed later against
+/// the same version of the flow it was taken from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XFlowCheckpoint {
+    pub xflow_id: Uuid,
+    pub xflow_version: i64,
+    pub current_node: Option<i32>,
+    /// The error edge to follow next, when the current node failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_edge: Option<XFlowEdge>,
+    pub status: XFlowStatus,
+    pub steps: usize,
+    pub visits: HashMap<i32, usize>,
+    pub state: XFState,
+}
+
+impl XFlowCheckpoint {
+    /// Return an indented JSON representation of the checkpoint
+    ///
+    /// partof: SPC-serialization-json
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self).unwrap()
+    }
+
+    /// Initialize a checkpoint from a JSON string
+    ///
+    /// partof: SPC-serialization-json
+    pub fn from_json(s: &str) -> Result<Self, RuntimeError> {
+        match serde_json::from_str(s) {
+            Ok(res) => Ok(res),
+            Err(err) => Err(RuntimeError::InvalidCheckpoint { message: format!("{}", err) }),
+        }
+    }
+}
@@ -1,57 +1,231 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::future;
 
-use crate::runtime::actiondispatch::dispatchable::Dispatchable;
-use crate::structure::xflow::{XFlowNode, XFlowNodeType};
+use crate::runtime::actiondispatch::dispatchable::{AsyncDispatchable, DispatchContext, DispatchFuture,
+                                                   Dispatchable};
+use crate::structure::xflow::{XFlow, XFlowNode, XFlowNodeType};
 use super::xfstate::XFState;
+use super::error::RuntimeError;
 
-type Receivers<'a> = HashMap<XFlowNodeType, Box<Dispatchable + 'a>>;
+/// What a receiver can dispatch: nodes of `nodetype` with `action`, or with any action
+/// when `action` is `None`, at `version`. A receiver of version `n` satisfies the
+/// requirements of flows for versions up to and including `n`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Capability {
+    pub nodetype: XFlowNodeType,
+    pub action: Option<String>,
+    pub version: i32,
+}
+
+impl Capability {
+    /// The capability to dispatch every action of `nodetype`, at `version`
+    pub fn new(nodetype: XFlowNodeType, version: i32) -> Self {
+        Capability {
+            nodetype: nodetype,
+            action: None,
+            version: version,
+        }
+    }
+
+    /// The capability to dispatch `action` of `nodetype`, at `version`
+    pub fn action(nodetype: XFlowNodeType, action: &str, version: i32) -> Self {
+        Capability {
+            nodetype: nodetype,
+            action: Some(action.to_owned()),
+            version: version,
+        }
+    }
+
+    /// Check whether this capability covers `needed`. A need for any action of a node
+    /// type is covered by a receiver for some of its actions.
+    pub fn provides(&self, needed: &Capability) -> bool {
+        self.nodetype == needed.nodetype && self.version >= needed.version &&
+            (self.action.is_none() || needed.action.is_none() || self.action == needed.action)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            Some(ref action) => write!(f, "{}/{} v{}", self.nodetype, action, self.version),
+            None => write!(f, "{} v{}", self.nodetype, self.version),
+        }
+    }
+}
+
+type Receivers<'a> = HashMap<Capability, Box<dyn Dispatchable + 'a>>;
+type AsyncReceivers<'a> = HashMap<Capability, Box<dyn AsyncDispatchable + 'a>>;
 
 pub struct Dispatcher<'a> {
     receivers: Receivers<'a>,
+    async_receivers: AsyncReceivers<'a>,
+}
+
+/// Find the receiver for `node` in `receivers`. A receiver for the action of the node is
+/// preferred over one for the whole node type, and a higher version over a lower one.
+fn find_receiver<'m, R: ?Sized>(
+    receivers: &'m HashMap<Capability, Box<R>>,
+    node: &XFlowNode,
+) -> Option<&'m Box<R>> {
+    let needed = Capability::action(node.nodetype.clone(), &node.action, 0);
+    receivers
+        .iter()
+        .filter(|&(capability, _)| capability.provides(&needed))
+        .max_by_key(|&(capability, _)| (capability.action.is_some(), capability.version))
+        .map(|(_, receiver)| receiver)
 }
 
 impl<'a> Dispatcher<'a> {
+    /// Register a receiver for every action of nodes of `xflow_node_type`, at version 1
     pub fn register_receiver<T: Dispatchable + 'a>(
         &mut self,
         xflow_node_type: XFlowNodeType,
         receiver: T,
     ) -> () {
-        let receiver_box = Box::new(receiver);
-        self.receivers.insert(
-            xflow_node_type.to_owned(),
-            receiver_box,
-        );
+        self.register(Capability::new(xflow_node_type, 1), receiver);
     }
 
-    pub fn dispatch(&self, xfnode: &XFlowNode, xfstate: &mut XFState) -> Result<(), String> {
+    /// Register a receiver providing `capability`, replacing the receiver registered for
+    /// the same capability before
+    pub fn register<T: Dispatchable + 'a>(&mut self, capability: Capability, receiver: T) -> () {
+        self.receivers.insert(capability, Box::new(receiver));
+    }
+
+    /// Register a receiver for every action of nodes of `xflow_node_type`, at version 1,
+    /// that is only used by `dispatch_async`. It takes precedence over a synchronous
+    /// receiver for the same node type there.
+    pub fn register_async_receiver<T: AsyncDispatchable + 'a>(
+        &mut self,
+        xflow_node_type: XFlowNodeType,
+        receiver: T,
+    ) -> () {
+        self.register_async(Capability::new(xflow_node_type, 1), receiver);
+    }
+
+    /// Register an asynchronous receiver providing `capability`
+    pub fn register_async<T: AsyncDispatchable + 'a>(
+        &mut self,
+        capability: Capability,
+        receiver: T,
+    ) -> () {
+        self.async_receivers.insert(capability, Box::new(receiver));
+    }
+
+    /// Return what `xflow` needs that no synchronous receiver provides, so `dispatch`
+    /// cannot run it: its declared requirements, and the actions of its nodes at the
+    /// version required for their node type
+    pub fn missing_capabilities(&self, xflow: &XFlow) -> Vec<Capability> {
+        self.missing(xflow, false)
+    }
+
+    /// Return what `xflow` needs that no receiver, synchronous or asynchronous, provides,
+    /// so `dispatch_async` cannot run it
+    pub fn missing_async_capabilities(&self, xflow: &XFlow) -> Vec<Capability> {
+        self.missing(xflow, true)
+    }
+
+    fn missing(&self, xflow: &XFlow, asynchronous: bool) -> Vec<Capability> {
+        let mut needed = Vec::<Capability>::new();
+        for req in &xflow.requirements {
+            needed.push(Capability::new(req.xtype.clone(), req.version));
+        }
+        for node in &xflow.nodes {
+            let version = xflow
+                .requirements
+                .iter()
+                .filter(|req| req.xtype == node.nodetype)
+                .map(|req| req.version)
+                .max()
+                .unwrap_or(1);
+            needed.push(Capability::action(node.nodetype.clone(), &node.action, version));
+        }
+
+        let mut missing = Vec::<Capability>::new();
+        for capability in needed {
+            let provides = |registered: &Capability| registered.provides(&capability);
+            let provided = self.receivers.keys().any(&provides) ||
+                (asynchronous && self.async_receivers.keys().any(&provides));
+            if !provided && !missing.contains(&capability) {
+                missing.push(capability);
+            }
+        }
+        missing
+    }
+
+    pub fn dispatch(&self, xfnode: &XFlowNode, xfstate: &mut XFState) -> Result<(), RuntimeError> {
+        self.dispatch_in(xfnode, xfstate, &DispatchContext::new(self))
+    }
+
+    /// Dispatch `xfnode` in `context`, the run of a flow that may have been called by
+    /// another one
+    pub fn dispatch_in(
+        &self,
+        xfnode: &XFlowNode,
+        xfstate: &mut XFState,
+        context: &DispatchContext,
+    ) -> Result<(), RuntimeError> {
         debug!("Nodetype {:?}, action {}", xfnode.nodetype, xfnode.action);
 
-        if let Some(receiver) = self.receivers.get(&xfnode.nodetype) {
-            match receiver.dispatch(xfnode, xfstate) {
+        if let Some(receiver) = find_receiver(&self.receivers, xfnode) {
+            match receiver.dispatch_in(xfnode, xfstate, context) {
                 Ok(()) => Ok(()),
-                Err(()) => {
-                    let msg = "An error has occurred dispatching, but this is currently not handled".to_owned();
-                    error!("{}", msg);
-                    Err(msg)
+                Err(err) => {
+                    error!("Error dispatching node {} : {}", xfnode.id, err);
+                    Err(err)
                 }
             }
         } else {
-            let msg = format!(
+            error!(
                 "No dispatcher found for {:?}/{}!",
                 xfnode.nodetype,
                 xfnode.action
             );
-            error!("{}", msg);
-            Err(msg)
+            Err(RuntimeError::UnknownNode {
+                node: xfnode.id,
+                nodetype: xfnode.nodetype.clone(),
+                action: xfnode.action.clone(),
+                state: xfstate.clone(),
+            })
         }
 
     }
+
+    /// Dispatch `xfnode` to its asynchronous receiver, or to its synchronous receiver
+    /// when it has no asynchronous one. The synchronous dispatch happens at once and the
+    /// returned future is ready.
+    pub fn dispatch_async<'f>(&'f self, xfnode: &'f XFlowNode, xfstate: XFState) -> DispatchFuture<'f> {
+        self.dispatch_async_in(xfnode, xfstate, &DispatchContext::new(self))
+    }
+
+    /// Dispatch `xfnode` asynchronously in `context`, the run of a flow that may have
+    /// been called by another one
+    pub fn dispatch_async_in<'f>(
+        &'f self,
+        xfnode: &'f XFlowNode,
+        xfstate: XFState,
+        context: &DispatchContext<'f>,
+    ) -> DispatchFuture<'f> {
+        debug!("Nodetype {:?}, action {} (async)", xfnode.nodetype, xfnode.action);
+
+        if let Some(receiver) = find_receiver(&self.async_receivers, xfnode) {
+            receiver.dispatch_async_in(xfnode, xfstate, context.clone())
+        } else {
+            let mut xfstate = xfstate;
+            let res = self.dispatch_in(xfnode, &mut xfstate, context);
+            Box::pin(future::ready((xfstate, res)))
+        }
+    }
 }
 
 impl<'a> Default for Dispatcher<'a> {
     fn default() -> Dispatcher<'a> {
         let receivers: Receivers = HashMap::new();
+        let async_receivers: AsyncReceivers = HashMap::new();
 
-        Dispatcher { receivers: receivers }
+        Dispatcher {
+            receivers: receivers,
+            async_receivers: async_receivers,
+        }
     }
 }
@@ -1,16 +1,765 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+
+use glob::glob;
+use serde;
+use serde_json;
+use uuid::Uuid;
+
+use crate::structure::common::{Document, DocumentHeader, ModelLoadError};
+use crate::structure::diff::DocumentDiff;
+use crate::structure::domain::{Domain, DomainDocument};
+use crate::structure::model::{ModelConfig, ModelConfigDocument, ModelDocument};
+use crate::structure::page::{Page, PageDocument};
+use crate::structure::translation::{Translation, TranslationDocument};
+use crate::structure::xflow::{XFlow, XFlowDocument};
+use crate::util::fs;
+
+/// The kinds of documents held in a `DocStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DocType {
+    Model,
+    Config,
+    Domain,
+    XFlow,
+    Page,
+    Translation,
+}
+
+impl DocType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DocType::Model => "model",
+            DocType::Config => "config",
+            DocType::Domain => "domain",
+            DocType::XFlow => "xflow",
+            DocType::Page => "page",
+            DocType::Translation => "translation",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DocType> {
+        match s {
+            "model" => Some(DocType::Model),
+            "config" => Some(DocType::Config),
+            "domain" => Some(DocType::Domain),
+            "xflow" => Some(DocType::XFlow),
+            "page" => Some(DocType::Page),
+            "translation" => Some(DocType::Translation),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DocType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The body of a `Document` that can be kept in a `DocStore`
+pub trait Storable: serde::Serialize + serde::de::DeserializeOwned + Eq + Default {
+    fn doctype() -> DocType;
+}
+
+impl Storable for ModelRefs {
+    fn doctype() -> DocType {
+        DocType::Model
+    }
+}
+
+impl Storable for ModelConfig {
+    fn doctype() -> DocType {
+        DocType::Config
+    }
+}
+
+impl Storable for Domain {
+    fn doctype() -> DocType {
+        DocType::Domain
+    }
+}
+
+impl Storable for XFlow {
+    fn doctype() -> DocType {
+        DocType::XFlow
+    }
+}
+
+impl Storable for Page {
+    fn doctype() -> DocType {
+        DocType::Page
+    }
+}
+
+impl Storable for Translation {
+    fn doctype() -> DocType {
+        DocType::Translation
+    }
+}
+
+/// One version of a document stored on its own in a `DocStore`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocRef {
+    pub id: Uuid,
+    pub version: i64,
+}
+
+impl DocRef {
+    fn of<T>(doc: &Document<T>) -> Self {
+        DocRef {
+            id: doc.id,
+            version: doc.version,
+        }
+    }
+}
+
+/// The body a model is stored with in a `DocStore`: the versions of its config, domain,
+/// xflow, page and translation documents, which are stored on their own
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelRefs {
+    pub config: DocRef,
+    pub domain: DocRef,
+    pub xflows: Vec<DocRef>,
+    pub pages: Vec<DocRef>,
+    pub translations: Vec<DocRef>,
+}
+
+/// The key of one version of one document in a `DocStoreBackend`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DocKey {
+    pub doctype: DocType,
+    pub id: Uuid,
+    pub version: i64,
+}
+
+impl DocKey {
+    pub fn new(doctype: DocType, id: Uuid, version: i64) -> Self {
+        DocKey {
+            doctype: doctype,
+            id: id,
+            version: version,
+        }
+    }
+
+    /// Parse a key from its `doctype/id/version` representation
+    pub fn parse(s: &str) -> Option<DocKey> {
+        let parts: Vec<&str> = s.split('/').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let doctype = DocType::parse(parts[0])?;
+        let id = Uuid::parse_str(parts[1]).ok()?;
+        let version = parts[2].parse::<i64>().ok()?;
+        Some(DocKey::new(doctype, id, version))
+    }
+}
+
+impl fmt::Display for DocKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.doctype, self.id, self.version)
+    }
+}
+
+/// Errors raised by a `DocStore` or its backend
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocStoreError {
+    /// The backend failed to read or write a document
+    Backend(String),
+    /// A stored document could not be serialized or deserialized
+    Serialization { key: DocKey, message: String },
+    /// A different document is already stored with this version, or a later one
+    VersionConflict {
+        doctype: DocType,
+        id: Uuid,
+        version: i64,
+        latest: i64,
+    },
+    /// More than one document of the same type has the name that was looked up
+    AmbiguousName {
+        doctype: DocType,
+        name: String,
+        ids: Vec<Uuid>,
+    },
+    /// A stored model refers to a version of a document that is not stored
+    MissingDocument { model: Uuid, key: DocKey },
+}
+
+impl fmt::Display for DocStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DocStoreError::Backend(ref message) => write!(f, "Document store error : {}", message),
+            DocStoreError::Serialization {
+                ref key,
+                ref message,
+            } => write!(f, "Unable to (de)serialize document {} : {}", key, message),
+            DocStoreError::VersionConflict {
+                doctype,
+                ref id,
+                version,
+                latest,
+            } => {
+                write!(
+                    f,
+                    "Cannot store version {} of {} '{}', version {} is already stored",
+                    version,
+                    doctype,
+                    id,
+                    latest
+                )
+            }
+            DocStoreError::AmbiguousName {
+                doctype,
+                ref name,
+                ref ids,
+            } => write!(f, "The name '{}' is used by {} {} documents", name, ids.len(), doctype),
+            DocStoreError::MissingDocument { ref model, ref key } => {
+                write!(f, "Document {} of model '{}' is not stored", key, model)
+            }
+        }
+    }
+}
+
+/// Storage for the serialized versions of documents. Backends only store and return
+/// data by key, the `DocStore` keeps track of names and versions.
+pub trait DocStoreBackend {
+    /// Store `data` under `key`, replacing what was stored there
+    fn put(&mut self, key: &DocKey, data: &str) -> Result<(), DocStoreError>;
+
+    /// Return the data stored under `key`
+    fn get(&self, key: &DocKey) -> Result<Option<String>, DocStoreError>;
+
+    /// Remove the data stored under `key`, if any
+    fn remove(&mut self, key: &DocKey) -> Result<(), DocStoreError>;
+
+    /// Return the keys of all stored data
+    fn keys(&self) -> Result<Vec<DocKey>, DocStoreError>;
+}
+
+/// A backend keeping documents in memory, for tests and short-lived runtimes
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    docs: BTreeMap<DocKey, String>,
+}
+
+impl DocStoreBackend for MemoryBackend {
+    fn put(&mut self, key: &DocKey, data: &str) -> Result<(), DocStoreError> {
+        self.docs.insert(key.clone(), data.to_owned());
+        Ok(())
+    }
+
+    fn get(&self, key: &DocKey) -> Result<Option<String>, DocStoreError> {
+        Ok(self.docs.get(key).cloned())
+    }
+
+    fn remove(&mut self, key: &DocKey) -> Result<(), DocStoreError> {
+        self.docs.remove(key);
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<DocKey>, DocStoreError> {
+        Ok(self.docs.keys().cloned().collect())
+    }
+}
+
+fn backend_error(err: ModelLoadError) -> DocStoreError {
+    DocStoreError::Backend(format!("{}", err))
+}
+
+/// A backend keeping each document version as a JSON file in a directory, laid out as
+/// `{path}/{doctype}/{id}/{version}.json`
+#[derive(Debug)]
+pub struct DirectoryBackend {
+    path: PathBuf,
+}
+
+impl DirectoryBackend {
+    /// Open the store in directory `path`, creating it when it does not exist
+    pub fn open(path: &str) -> Result<Self, DocStoreError> {
+        fs::create_dir(path).map_err(backend_error)?;
+        Ok(DirectoryBackend { path: PathBuf::from(path) })
+    }
+
+    fn doc_path(&self, key: &DocKey) -> PathBuf {
+        self.path
+            .join(key.doctype.as_str())
+            .join(key.id.to_string())
+            .join(format!("{}.json", key.version))
+    }
+}
+
+impl DocStoreBackend for DirectoryBackend {
+    fn put(&mut self, key: &DocKey, data: &str) -> Result<(), DocStoreError> {
+        let doctype_path = self.path.join(key.doctype.as_str());
+        fs::create_dir(&doctype_path.to_string_lossy()).map_err(backend_error)?;
+        fs::create_dir(&doctype_path.join(key.id.to_string()).to_string_lossy())
+            .map_err(backend_error)?;
+        fs::write_file(&self.doc_path(key).to_string_lossy(), data).map_err(backend_error)
+    }
+
+    fn get(&self, key: &DocKey) -> Result<Option<String>, DocStoreError> {
+        let path = self.doc_path(key);
+        if path.is_file() {
+            fs::read_json_file(&path).map(Some).map_err(backend_error)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn remove(&mut self, key: &DocKey) -> Result<(), DocStoreError> {
+        let path = self.doc_path(key);
+        if path.is_file() {
+            if let Err(err) = ::std::fs::remove_file(&path) {
+                return Err(DocStoreError::Backend(
+                    format!("Unable to remove {} : {}", path.display(), err),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<DocKey>, DocStoreError> {
+        let pattern = format!("{}/*/*/*.json", self.path.display());
+        let paths = match glob(&pattern) {
+            Ok(paths) => paths,
+            Err(err) => return Err(DocStoreError::Backend(format!("{}", err))),
+        };
+
+        let mut keys = Vec::<DocKey>::new();
+        for path in paths.flatten() {
+            let key = path.strip_prefix(&self.path).ok().and_then(|rel| {
+                DocKey::parse(&rel.with_extension("").to_string_lossy())
+            });
+            match key {
+                Some(key) => keys.push(key),
+                None => warn!("Ignoring unexpected file '{:?}' in document store", path),
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// A backend keeping documents in an embedded `sled` database
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+pub struct SledBackend {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledBackend {
+    /// Open the database at `path`, creating it when it does not exist
+    pub fn open(path: &str) -> Result<Self, DocStoreError> {
+        match sled::open(path) {
+            Ok(db) => Ok(SledBackend { db: db }),
+            Err(err) => Err(DocStoreError::Backend(format!("{}", err))),
+        }
+    }
+
+    /// Open a database that is removed when the backend is dropped
+    pub fn temporary() -> Result<Self, DocStoreError> {
+        match sled::Config::new().temporary(true).open() {
+            Ok(db) => Ok(SledBackend { db: db }),
+            Err(err) => Err(DocStoreError::Backend(format!("{}", err))),
+        }
+    }
+}
+
+#[cfg(feature = "sled")]
+impl DocStoreBackend for SledBackend {
+    fn put(&mut self, key: &DocKey, data: &str) -> Result<(), DocStoreError> {
+        match self.db.insert(key.to_string().as_bytes(), data.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(DocStoreError::Backend(format!("{}", err))),
+        }
+    }
+
+    fn get(&self, key: &DocKey) -> Result<Option<String>, DocStoreError> {
+        match self.db.get(key.to_string().as_bytes()) {
+            Ok(Some(data)) => Ok(Some(String::from_utf8_lossy(&data).into_owned())),
+            Ok(None) => Ok(None),
+            Err(err) => Err(DocStoreError::Backend(format!("{}", err))),
+        }
+    }
+
+    fn remove(&mut self, key: &DocKey) -> Result<(), DocStoreError> {
+        match self.db.remove(key.to_string().as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(DocStoreError::Backend(format!("{}", err))),
+        }
+    }
+
+    fn keys(&self) -> Result<Vec<DocKey>, DocStoreError> {
+        let mut keys = Vec::<DocKey>::new();
+        for item in self.db.iter().keys() {
+            match item {
+                Ok(key) => {
+                    match DocKey::parse(&String::from_utf8_lossy(&key)) {
+                        Some(key) => keys.push(key),
+                        None => warn!("Ignoring unexpected key {:?} in document store", key),
+                    }
+                }
+                Err(err) => return Err(DocStoreError::Backend(format!("{}", err))),
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// A store for documents of all types, looked up by id or name. Every stored version of
+/// a document is kept, and lookups without a version return the latest one.
 #[derive(Debug)]
-enum DocType {
-    XFlowStruct,
+pub struct DocStore<B: DocStoreBackend> {
+    backend: B,
+    versions: HashMap<(DocType, Uuid), BTreeMap<i64, DocumentHeader>>,
+}
+
+impl DocStore<MemoryBackend> {
+    /// Construct an empty store keeping its documents in memory
+    pub fn in_memory() -> Self {
+        DocStore {
+            backend: MemoryBackend::default(),
+            versions: HashMap::new(),
+        }
+    }
+}
+
+impl<B: DocStoreBackend> DocStore<B> {
+    /// Open a store on `backend`, indexing the documents it already holds
+    pub fn open(backend: B) -> Result<Self, DocStoreError> {
+        let mut versions = HashMap::<(DocType, Uuid), BTreeMap<i64, DocumentHeader>>::new();
+        for key in backend.keys()? {
+            let data = match backend.get(&key)? {
+                Some(data) => data,
+                None => continue,
+            };
+            let header: DocumentHeader = match serde_json::from_str(&data) {
+                Ok(header) => header,
+                Err(err) => {
+                    return Err(DocStoreError::Serialization {
+                        key: key,
+                        message: format!("{}", err),
+                    })
+                }
+            };
+            versions
+                .entry((key.doctype, key.id))
+                .or_default()
+                .insert(key.version, header);
+        }
+
+        Ok(DocStore {
+            backend: backend,
+            versions: versions,
+        })
+    }
+
+    /// Store `doc` as a new version. Storing a version that is already stored with the
+    /// same content does nothing, storing an older or different version is an error.
+    pub fn add<T: Storable>(&mut self, doc: &Document<T>) -> Result<(), DocStoreError> {
+        let doctype = T::doctype();
+        let key = DocKey::new(doctype, doc.id, doc.version);
+
+        if let Some(latest) = self.latest_header(doctype, &doc.id) {
+            if doc.version <= latest.version {
+                if self.get_version::<T>(&doc.id, doc.version)?.as_ref() == Some(doc) {
+                    return Ok(());
+                }
+                return Err(DocStoreError::VersionConflict {
+                    doctype: doctype,
+                    id: doc.id,
+                    version: doc.version,
+                    latest: latest.version,
+                });
+            }
+        }
+
+        let data = match serde_json::to_string(doc) {
+            Ok(data) => data,
+            Err(err) => {
+                return Err(DocStoreError::Serialization {
+                    key: key,
+                    message: format!("{}", err),
+                })
+            }
+        };
+        self.backend.put(&key, &data)?;
+        self.versions
+            .entry((doctype, doc.id))
+            .or_default()
+            .insert(doc.version, doc.get_header());
+        Ok(())
+    }
+
+    /// Store `doc` as the next version of the stored document when it changed, setting
+    /// its version accordingly. Returns whether a new version was stored.
+    pub fn save<T: Storable>(&mut self, doc: &mut Document<T>) -> Result<bool, DocStoreError> {
+        let changed = match self.get::<T>(&doc.id)? {
+            Some(latest) => doc.bump_version_from(&latest),
+            None => true,
+        };
+        if changed {
+            self.add(doc)?;
+        }
+        Ok(changed)
+    }
+
+    /// Return the changes between versions `from` and `to` of the document with `id`
+    pub fn diff<T: Storable>(
+        &self,
+        id: &Uuid,
+        from: i64,
+        to: i64,
+    ) -> Result<Option<DocumentDiff>, DocStoreError> {
+        match (self.get_version::<T>(id, from)?, self.get_version::<T>(id, to)?) {
+            (Some(old), Some(new)) => Ok(Some(old.diff(&new))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Store the config, domain, xflow, page and translation documents of `model`, and
+    /// the model itself as references to the versions stored
+    pub fn add_model(&mut self, model: &ModelDocument) -> Result<(), DocStoreError> {
+        self.add(&model.body.config)?;
+        self.add(&model.body.domain)?;
+        for doc in &model.body.xflows {
+            self.add(doc)?;
+        }
+        for doc in &model.body.pages {
+            self.add(doc)?;
+        }
+        for doc in &model.body.translations {
+            self.add(doc)?;
+        }
+
+        self.add(&Document {
+            id: model.id,
+            name: model.name.clone(),
+            doctype: model.doctype.clone(),
+            doctype_version: model.doctype_version,
+            version: model.version,
+            body: ModelRefs {
+                config: DocRef::of(&model.body.config),
+                domain: DocRef::of(&model.body.domain),
+                xflows: model.body.xflows.iter().map(DocRef::of).collect(),
+                pages: model.body.pages.iter().map(DocRef::of).collect(),
+                translations: model.body.translations.iter().map(DocRef::of).collect(),
+            },
+        })
+    }
+
+    /// Return version `version` of the model with `id`, with the versions of the
+    /// documents it was stored with
+    pub fn get_model_version(
+        &self,
+        id: &Uuid,
+        version: i64,
+    ) -> Result<Option<ModelDocument>, DocStoreError> {
+        match self.get_version::<ModelRefs>(id, version)? {
+            Some(refs) => self.resolve_model(refs).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the latest version of the document with `id`
+    pub fn get<T: Storable>(&self, id: &Uuid) -> Result<Option<Document<T>>, DocStoreError> {
+        match self.latest_header(T::doctype(), id) {
+            Some(header) => self.get_version(id, header.version),
+            None => Ok(None),
+        }
+    }
+
+    /// Return version `version` of the document with `id`
+    pub fn get_version<T: Storable>(
+        &self,
+        id: &Uuid,
+        version: i64,
+    ) -> Result<Option<Document<T>>, DocStoreError> {
+        let key = DocKey::new(T::doctype(), *id, version);
+        match self.backend.get(&key)? {
+            Some(data) => {
+                match serde_json::from_str(&data) {
+                    Ok(doc) => Ok(Some(doc)),
+                    Err(err) => Err(DocStoreError::Serialization {
+                        key: key,
+                        message: format!("{}", err),
+                    }),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Return the latest version of the document whose latest version is named `name`.
+    /// Names are not unique, looking up a name shared by several documents is an error.
+    pub fn get_by_name<T: Storable>(&self, name: &str) -> Result<Option<Document<T>>, DocStoreError> {
+        let mut ids: Vec<Uuid> = self.find_by_name(T::doctype(), name)
+            .iter()
+            .map(|header| header.id)
+            .collect();
+        match ids.len() {
+            0 => Ok(None),
+            1 => self.get(&ids[0]),
+            _ => {
+                ids.sort();
+                Err(DocStoreError::AmbiguousName {
+                    doctype: T::doctype(),
+                    name: name.to_owned(),
+                    ids: ids,
+                })
+            }
+        }
+    }
+
+    /// Return the stored versions of the document with `id`, oldest first
+    pub fn versions<T: Storable>(&self, id: &Uuid) -> Vec<i64> {
+        match self.versions.get(&(T::doctype(), *id)) {
+            Some(versions) => versions.keys().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return every stored version of the document with `id`, oldest first
+    pub fn history<T: Storable>(&self, id: &Uuid) -> Result<Vec<Document<T>>, DocStoreError> {
+        let mut docs = Vec::<Document<T>>::new();
+        for version in self.versions::<T>(id) {
+            if let Some(doc) = self.get_version(id, version)? {
+                docs.push(doc);
+            }
+        }
+        Ok(docs)
+    }
+
+    /// Return the headers of the latest versions of all documents of `doctype`
+    pub fn list(&self, doctype: DocType) -> Vec<DocumentHeader> {
+        let mut headers: Vec<DocumentHeader> = self.versions
+            .iter()
+            .filter(|&((dt, _), _)| *dt == doctype)
+            .filter_map(|(_, versions)| versions.values().next_back().cloned())
+            .collect();
+        headers.sort_by(|a, b| a.name.cmp(&b.name));
+        headers
+    }
+
+    /// Remove all versions of the document with `id`
+    pub fn remove<T: Storable>(&mut self, id: &Uuid) -> Result<(), DocStoreError> {
+        let doctype = T::doctype();
+        if let Some(versions) = self.versions.remove(&(doctype, *id)) {
+            for version in versions.keys() {
+                self.backend.remove(&DocKey::new(doctype, *id, *version))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the backend of the store
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    fn resolve_model(&self, refs: Document<ModelRefs>) -> Result<ModelDocument, DocStoreError> {
+        let mut model = ModelDocument::default();
+        model.set_header(&refs.get_header());
+        model.body.config = self.resolve(&refs, &refs.body.config)?;
+        model.body.domain = self.resolve(&refs, &refs.body.domain)?;
+        for doc in &refs.body.xflows {
+            model.body.xflows.push(self.resolve(&refs, doc)?);
+        }
+        for doc in &refs.body.pages {
+            model.body.pages.push(self.resolve(&refs, doc)?);
+        }
+        for doc in &refs.body.translations {
+            model.body.translations.push(self.resolve(&refs, doc)?);
+        }
+        Ok(model)
+    }
+
+    fn resolve<T: Storable>(
+        &self,
+        model: &Document<ModelRefs>,
+        doc: &DocRef,
+    ) -> Result<Document<T>, DocStoreError> {
+        match self.get_version(&doc.id, doc.version)? {
+            Some(doc) => Ok(doc),
+            None => Err(DocStoreError::MissingDocument {
+                model: model.id,
+                key: DocKey::new(T::doctype(), doc.id, doc.version),
+            }),
+        }
+    }
+
+    fn latest_header(&self, doctype: DocType, id: &Uuid) -> Option<&DocumentHeader> {
+        self.versions
+            .get(&(doctype, *id))
+            .and_then(|versions| versions.values().next_back())
+    }
+
+    fn find_by_name(&self, doctype: DocType, name: &str) -> Vec<&DocumentHeader> {
+        self.versions
+            .iter()
+            .filter(|&((dt, _), _)| *dt == doctype)
+            .filter_map(|(_, versions)| versions.values().next_back())
+            .filter(|header| header.name == name)
+            .collect()
+    }
 }
 
-pub struct DocStore {
-    docs: Vec<(String, DocType)>,
+/// Where `call` nodes resolve the flows they call
+pub trait XFlowSource: fmt::Debug + Sync {
+    /// Return the xflow with `id`
+    fn get_xflow_document(&self, id: &Uuid) -> Result<Option<XFlowDocument>, DocStoreError>;
+
+    /// Describe the source in error messages
+    fn describe(&self) -> String;
 }
 
-impl DocStore {
-    pub fn new() -> DocStore {
-        DocStore { docs: Vec::<(String, DocType)>::new() }
+impl XFlowSource for ModelDocument {
+    fn get_xflow_document(&self, id: &Uuid) -> Result<Option<XFlowDocument>, DocStoreError> {
+        Ok(self.get_xflow(id).cloned())
+    }
+
+    fn describe(&self) -> String {
+        format!("model '{}'", self.id)
     }
+}
 
-    pub fn add(&self, doc: DocType) {}
+impl<B: DocStoreBackend + fmt::Debug + Sync> XFlowSource for DocStore<B> {
+    fn get_xflow_document(&self, id: &Uuid) -> Result<Option<XFlowDocument>, DocStoreError> {
+        self.get::<XFlow>(id)
+    }
+
+    fn describe(&self) -> String {
+        "the document store".to_owned()
+    }
+}
+
+/// Convenience lookups for the document types used by the runtime
+impl<B: DocStoreBackend> DocStore<B> {
+    pub fn get_model(&self, id: &Uuid) -> Result<Option<ModelDocument>, DocStoreError> {
+        match self.get::<ModelRefs>(id)? {
+            Some(refs) => self.resolve_model(refs).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_config(&self, id: &Uuid) -> Result<Option<ModelConfigDocument>, DocStoreError> {
+        self.get(id)
+    }
+
+    pub fn get_domain(&self, id: &Uuid) -> Result<Option<DomainDocument>, DocStoreError> {
+        self.get(id)
+    }
+
+    pub fn get_xflow(&self, id: &Uuid) -> Result<Option<XFlowDocument>, DocStoreError> {
+        self.get(id)
+    }
+
+    pub fn get_page(&self, id: &Uuid) -> Result<Option<PageDocument>, DocStoreError> {
+        self.get(id)
+    }
+
+    pub fn get_translation(&self, id: &Uuid) -> Result<Option<TranslationDocument>, DocStoreError> {
+        self.get(id)
+    }
 }
@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::runtime::dispatcher::Capability;
+use crate::runtime::xfstate::XFState;
+use crate::structure::xflow::{XFlowNodeType, XFlowRecord, XFlowValue, XFlowValueType};
+
+/// An execution limit of an `XFlowRunner` that has been exceeded
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionLimit {
+    /// Maximum number of node visits for the whole flow
+    MaxSteps(usize),
+    /// Maximum wall-clock time for the whole flow
+    MaxDuration(Duration),
+    /// Maximum number of visits to a single node
+    MaxNodeVisits(usize),
+}
+
+impl fmt::Display for ExecutionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecutionLimit::MaxSteps(max) => write!(f, "maximum of {} steps", max),
+            ExecutionLimit::MaxDuration(max) => write!(f, "maximum duration of {:?}", max),
+            ExecutionLimit::MaxNodeVisits(max) => write!(f, "maximum of {} visits to a node", max),
+        }
+    }
+}
+
+/// Errors raised while running an `XFlow`. Every error raised while processing a node
+/// carries the node id and a snapshot of the `XFState` at the moment of failure.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// The flow could not be initialized, e.g. because it has no single entry node
+    Initialization { message: String },
+    /// The dispatcher has no receivers for some of the requirements or node actions of
+    /// the flow
+    MissingCapabilities { missing: Vec<Capability> },
+    /// An edge points to a node id that does not exist in the flow
+    NodeNotFound { node: i32, state: XFState },
+    /// No receiver is able to handle this node type and action
+    UnknownNode {
+        node: i32,
+        nodetype: XFlowNodeType,
+        action: String,
+        state: XFState,
+    },
+    /// A node has no outgoing edges and is not a terminal node
+    NoOutgoingEdge { node: i32, state: XFState },
+    /// A Flox expression failed to parse or evaluate
+    FloxEvaluation {
+        node: i32,
+        expression: String,
+        position: usize,
+        message: String,
+        state: XFState,
+    },
+    /// A variable required by a node or by the flow is not in the state
+    MissingVariable {
+        node: Option<i32>,
+        name: String,
+        state: XFState,
+    },
+    /// No branch matched the state at a branching node
+    NoMatchingBranch { node: i32, state: XFState },
+    /// More than one branch matched the state at a branching node
+    AmbiguousBranch {
+        node: i32,
+        edges: Vec<(i32, i32)>,
+        state: XFState,
+    },
+    /// An output variable has a different type than its definition
+    OutputTypeMismatch {
+        name: String,
+        expected: XFlowValueType,
+        found: XFlowValueType,
+        state: XFState,
+    },
+    /// Branches of a fork changed the same variable to different values
+    ForkConflict {
+        node: i32,
+        name: String,
+        state: XFState,
+    },
+    /// A receiver failed to process a node
+    Dispatch {
+        node: i32,
+        message: String,
+        state: XFState,
+    },
+    /// A flow called from a `call` node failed
+    CallFailed {
+        node: i32,
+        xflowid: Uuid,
+        source: Box<RuntimeError>,
+        state: XFState,
+    },
+    /// The flow exceeded one of its execution limits and timed out
+    LimitExceeded {
+        node: i32,
+        limit: ExecutionLimit,
+        state: XFState,
+    },
+    /// A checkpoint could not be read or does not match the flow it is resumed against
+    InvalidCheckpoint { message: String },
+    /// Output was requested before the flow finished
+    NotFinished,
+}
+
+impl RuntimeError {
+    /// Return the id of the node at which the error occurred, if any
+    pub fn node_id(&self) -> Option<i32> {
+        match *self {
+            RuntimeError::NodeNotFound { node, .. } |
+            RuntimeError::UnknownNode { node, .. } |
+            RuntimeError::NoOutgoingEdge { node, .. } |
+            RuntimeError::FloxEvaluation { node, .. } |
+            RuntimeError::NoMatchingBranch { node, .. } |
+            RuntimeError::AmbiguousBranch { node, .. } |
+            RuntimeError::ForkConflict { node, .. } |
+            RuntimeError::Dispatch { node, .. } |
+            RuntimeError::CallFailed { node, .. } |
+            RuntimeError::LimitExceeded { node, .. } => Some(node),
+            RuntimeError::MissingVariable { node, .. } => node,
+            RuntimeError::Initialization { .. } |
+            RuntimeError::MissingCapabilities { .. } |
+            RuntimeError::OutputTypeMismatch { .. } |
+            RuntimeError::InvalidCheckpoint { .. } |
+            RuntimeError::NotFinished => None,
+        }
+    }
+
+    /// Return a short name for the kind of error, such as `flox_evaluation`
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            RuntimeError::Initialization { .. } => "initialization",
+            RuntimeError::MissingCapabilities { .. } => "missing_capabilities",
+            RuntimeError::NodeNotFound { .. } => "node_not_found",
+            RuntimeError::UnknownNode { .. } => "unknown_node",
+            RuntimeError::NoOutgoingEdge { .. } => "no_outgoing_edge",
+            RuntimeError::FloxEvaluation { .. } => "flox_evaluation",
+            RuntimeError::MissingVariable { .. } => "missing_variable",
+            RuntimeError::NoMatchingBranch { .. } => "no_matching_branch",
+            RuntimeError::AmbiguousBranch { .. } => "ambiguous_branch",
+            RuntimeError::OutputTypeMismatch { .. } => "output_type_mismatch",
+            RuntimeError::ForkConflict { .. } => "fork_conflict",
+            RuntimeError::Dispatch { .. } => "dispatch",
+            RuntimeError::CallFailed { .. } => "call_failed",
+            RuntimeError::LimitExceeded { .. } => "limit_exceeded",
+            RuntimeError::InvalidCheckpoint { .. } => "invalid_checkpoint",
+            RuntimeError::NotFinished => "not_finished",
+        }
+    }
+
+    /// Return the error as a record with the fields `node`, `kind` and `message`, to be
+    /// stored in the state of a flow that handles it
+    pub fn to_value(&self) -> XFlowValue {
+        let mut record = XFlowRecord::new();
+        if let Some(node) = self.node_id() {
+            record.insert("node".to_owned(), XFlowValue::Integer(i64::from(node)));
+        }
+        record.insert("kind".to_owned(), XFlowValue::String(self.kind().to_owned()));
+        record.insert("message".to_owned(), XFlowValue::String(self.to_string()));
+        XFlowValue::Record(record)
+    }
+
+    /// Return the snapshot of the state at the moment of failure, if any
+    pub fn state(&self) -> Option<&XFState> {
+        match *self {
+            RuntimeError::NodeNotFound { ref state, .. } |
+            RuntimeError::UnknownNode { ref state, .. } |
+            RuntimeError::NoOutgoingEdge { ref state, .. } |
+            RuntimeError::FloxEvaluation { ref state, .. } |
+            RuntimeError::MissingVariable { ref state, .. } |
+            RuntimeError::NoMatchingBranch { ref state, .. } |
+            RuntimeError::AmbiguousBranch { ref state, .. } |
+            RuntimeError::OutputTypeMismatch { ref state, .. } |
+            RuntimeError::ForkConflict { ref state, .. } |
+            RuntimeError::Dispatch { ref state, .. } |
+            RuntimeError::CallFailed { ref state, .. } |
+            RuntimeError::LimitExceeded { ref state, .. } => Some(state),
+            RuntimeError::Initialization { .. } |
+            RuntimeError::MissingCapabilities { .. } |
+            RuntimeError::InvalidCheckpoint { .. } |
+            RuntimeError::NotFinished => None,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuntimeError::Initialization { ref message } => {
+                write!(f, "Unable to initialize xflow : {}", message)
+            }
+            RuntimeError::MissingCapabilities { ref missing } => {
+                let missing: Vec<String> = missing.iter().map(|c| c.to_string()).collect();
+                write!(f, "No receivers registered for {}", missing.join(", "))
+            }
+            RuntimeError::NodeNotFound { node, .. } => write!(f, "Node {} not found", node),
+            RuntimeError::UnknownNode {
+                node,
+                ref nodetype,
+                ref action,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Node {} : no receiver found for {:?}/{}",
+                    node,
+                    nodetype,
+                    action
+                )
+            }
+            RuntimeError::NoOutgoingEdge { node, .. } => {
+                write!(f, "Node {} has no outgoing edge", node)
+            }
+            RuntimeError::FloxEvaluation {
+                node,
+                ref expression,
+                position,
+                ref message,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Node {} : error evaluating '{}' at position {} : {}",
+                    node,
+                    expression,
+                    position,
+                    message
+                )
+            }
+            RuntimeError::MissingVariable {
+                node: Some(node),
+                ref name,
+                ..
+            } => write!(f, "Node {} : variable '{}' not found in state", node, name),
+            RuntimeError::MissingVariable {
+                node: None,
+                ref name,
+                ..
+            } => write!(f, "Variable '{}' not found in state", name),
+            RuntimeError::NoMatchingBranch { node, .. } => {
+                write!(f, "Node {} : no branch matches the current state", node)
+            }
+            RuntimeError::AmbiguousBranch { node, ref edges, .. } => {
+                write!(
+                    f,
+                    "Node {} : multiple branches match the current state {:?}",
+                    node,
+                    edges
+                )
+            }
+            RuntimeError::OutputTypeMismatch {
+                ref name,
+                ref expected,
+                ref found,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Output variable '{}' is of type {:?}, expected {:?}",
+                    name,
+                    found,
+                    expected
+                )
+            }
+            RuntimeError::ForkConflict { node, ref name, .. } => {
+                write!(
+                    f,
+                    "Node {} : branches of the fork changed variable '{}' to different values",
+                    node,
+                    name
+                )
+            }
+            RuntimeError::Dispatch {
+                node, ref message, ..
+            } => write!(f, "Node {} : {}", node, message),
+            RuntimeError::CallFailed {
+                node,
+                ref xflowid,
+                ref source,
+                ..
+            } => write!(f, "Node {} : call to xflow '{}' failed : {}", node, xflowid, source),
+            RuntimeError::LimitExceeded { node, ref limit, .. } => {
+                write!(f, "Node {} : timed out, exceeded {}", node, limit)
+            }
+            RuntimeError::InvalidCheckpoint { ref message } => {
+                write!(f, "Invalid checkpoint : {}", message)
+            }
+            RuntimeError::NotFinished => write!(f, "XFlow has not finished"),
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RuntimeError::CallFailed { ref source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
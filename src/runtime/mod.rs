@@ -2,3 +2,8 @@ pub mod xfrunner;
 pub mod actiondispatch;
 pub mod dispatcher;
 pub mod xfstate;
+pub mod error;
+pub mod checkpoint;
+pub mod trace;
+pub mod executor;
+pub mod docstore;
@@ -0,0 +1,132 @@
+use serde_json;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use uuid::Uuid;
+
+use crate::runtime::error::RuntimeError;
+use crate::runtime::xfrunner::XFlowStatus;
+use crate::runtime::xfstate::XFState;
+use crate::structure::xflow::{XFlowBranch, XFlowEdge, XFlowNodeType, XFlowVariable};
+
+/// A change to a single variable in `XFState`. A variable that was added has no `old`
+/// value, a variable that was removed has no `new` value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XFlowVariableChange {
+    pub name: String,
+    pub old: Option<XFlowVariable>,
+    pub new: Option<XFlowVariable>,
+}
+
+/// A single step taken by an `XFlowRunner`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XFlowTraceStep {
+    pub step: usize,
+    pub node: i32,
+    pub nodetype: XFlowNodeType,
+    pub action: String,
+    pub edge: XFlowEdge,
+    pub branch: Option<XFlowBranch>,
+    pub changes: Vec<XFlowVariableChange>,
+}
+
+/// The recorded steps of a flow run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XFlowTrace {
+    pub xflow_id: Uuid,
+    pub steps: Vec<XFlowTraceStep>,
+}
+
+/// Receives events from a running `XFlowRunner`
+pub trait XFlowObserver {
+    fn on_step(&mut self, step: &XFlowTraceStep);
+
+    fn on_error(&mut self, _error: &RuntimeError) {}
+
+    fn on_finish(&mut self, _status: &XFlowStatus) {}
+}
+
+impl<T: XFlowObserver> XFlowObserver for Rc<RefCell<T>> {
+    fn on_step(&mut self, step: &XFlowTraceStep) {
+        self.borrow_mut().on_step(step)
+    }
+
+    fn on_error(&mut self, error: &RuntimeError) {
+        self.borrow_mut().on_error(error)
+    }
+
+    fn on_finish(&mut self, status: &XFlowStatus) {
+        self.borrow_mut().on_finish(status)
+    }
+}
+
+/// Compute the changes between two states, ordered by variable name
+pub fn state_changes(before: &XFState, after: &XFState) -> Vec<XFlowVariableChange> {
+    let names: BTreeSet<&String> = before.store.keys().chain(after.store.keys()).collect();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name);
+            let new = after.get(name);
+            if old == new {
+                None
+            } else {
+                Some(XFlowVariableChange {
+                    name: name.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            }
+        })
+        .collect()
+}
+
+impl XFlowTrace {
+    pub fn new(xflow_id: Uuid) -> Self {
+        XFlowTrace {
+            xflow_id: xflow_id,
+            steps: Vec::<XFlowTraceStep>::new(),
+        }
+    }
+
+    /// Return the ids of the visited nodes, in order
+    pub fn path(&self) -> Vec<i32> {
+        self.steps.iter().map(|step| step.node).collect()
+    }
+
+    /// Replay the variable changes of all steps onto `state`
+    pub fn replay(&self, state: &mut XFState) {
+        for step in &self.steps {
+            for change in &step.changes {
+                match change.new {
+                    Some(ref xvar) => state.add(xvar),
+                    None => state.remove(&change.name),
+                }
+            }
+        }
+    }
+
+    /// Return an indented JSON representation of the trace
+    ///
+    /// partof: SPC-serialization-json
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self).unwrap()
+    }
+
+    /// Initialize a trace from a JSON string
+    ///
+    /// partof: SPC-serialization-json
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        match serde_json::from_str(s) {
+            Ok(res) => Ok(res),
+            Err(err) => Err(format!("{}", err)),
+        }
+    }
+}
+
+impl XFlowObserver for XFlowTrace {
+    fn on_step(&mut self, step: &XFlowTraceStep) {
+        self.steps.push(step.clone());
+    }
+}
@@ -1,8 +1,23 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use crate::structure::xflow::*;
+use crate::parser::flox;
 use super::xfstate::*;
 use crate::runtime::dispatcher::*;
+use crate::runtime::actiondispatch::dispatchable::{DispatchContext, DispatchFuture};
+use crate::runtime::error::{ExecutionLimit, RuntimeError};
+use crate::runtime::checkpoint::XFlowCheckpoint;
+use crate::runtime::trace::{state_changes, XFlowObserver, XFlowTrace, XFlowTraceStep};
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum XFlowStatus {
     Uninitialized,
     Initialized,
@@ -13,21 +28,419 @@ pub enum XFlowStatus {
     InvalidState,
 }
 
+/// Execution limits for an `XFlowRunner`. A runner that exceeds any of these moves to
+/// `XFlowStatus::TimedOut`. The steps and duration include those of the branches of
+/// forks and of the flows called by the flow. All limits are disabled by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XFlowLimits {
+    /// Maximum number of node visits for the whole flow
+    pub max_steps: Option<usize>,
+    /// Maximum wall-clock time, measured from the first step. It is also checked after
+    /// each dispatch, and asynchronous dispatches are cancelled when it passes.
+    pub max_duration: Option<Duration>,
+    /// Maximum number of visits to any single node
+    pub max_node_visits: Option<usize>,
+}
+
+impl Default for XFlowLimits {
+    fn default() -> Self {
+        XFlowLimits {
+            max_steps: None,
+            max_duration: None,
+            max_node_visits: None,
+        }
+    }
+}
+
+/// The steps and deadline of the limits of one runner, counted for the runner together
+/// with the branches of its forks and the flows it calls
+#[derive(Debug)]
+struct BudgetLimit {
+    steps: AtomicUsize,
+    max_steps: Option<usize>,
+    deadline: Option<(Instant, Duration)>,
+}
+
+/// What remains of the execution limits of a run: those of its runner and of the
+/// runners of the flows calling it. It is shared by the branches of forks and handed to
+/// called flows in their `DispatchContext`, so the limits hold for all of them together.
+#[derive(Debug, Clone, Default)]
+pub struct XFlowBudget {
+    limits: Vec<Arc<BudgetLimit>>,
+}
+
+impl XFlowBudget {
+    /// This budget with the limits of a runner that has taken `steps` steps so far
+    fn with_limits(&self, limits: &XFlowLimits, steps: usize) -> XFlowBudget {
+        let mut budget = self.clone();
+        if limits.max_steps.is_some() || limits.max_duration.is_some() {
+            budget.limits.push(Arc::new(BudgetLimit {
+                steps: AtomicUsize::new(steps),
+                max_steps: limits.max_steps,
+                deadline: limits.max_duration.and_then(|max| {
+                    Instant::now().checked_add(max).map(|deadline| (deadline, max))
+                }),
+            }));
+        }
+        budget
+    }
+
+    /// Count a step against every limit and return the first limit exceeded, if any
+    fn take_step(&self) -> Option<ExecutionLimit> {
+        for limit in &self.limits {
+            limit.steps.fetch_add(1, Ordering::SeqCst);
+        }
+        self.exceeded()
+    }
+
+    /// Return the first limit exceeded, if any
+    pub fn exceeded(&self) -> Option<ExecutionLimit> {
+        for limit in &self.limits {
+            if let Some(max) = limit.max_steps {
+                if limit.steps.load(Ordering::SeqCst) > max {
+                    return Some(ExecutionLimit::MaxSteps(max));
+                }
+            }
+        }
+        let now = Instant::now();
+        for limit in &self.limits {
+            if let Some((deadline, max)) = limit.deadline {
+                if now > deadline {
+                    return Some(ExecutionLimit::MaxDuration(max));
+                }
+            }
+        }
+        None
+    }
+
+    /// Return the time left until the earliest deadline, if there is one
+    pub fn remaining_time(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.limits
+            .iter()
+            .filter_map(|limit| limit.deadline)
+            .map(|(deadline, _)| deadline.saturating_duration_since(now))
+            .min()
+    }
+}
+
+/// The default number of branches of a fork that run at the same time
+pub const DEFAULT_FORK_THREADS: usize = 4;
+
+/// Move from the current node to the next one, through `edge` and, for branching
+/// nodes, the matching `branch`
+struct Transition<'a> {
+    node: &'a XFlowNode,
+    edge: XFlowEdge,
+    branch: Option<&'a XFlowBranch>,
+}
+
 pub struct XFlowRunner<'a> {
     pub status: XFlowStatus,
     xflow: &'a XFlowDocument,
     dispatcher: &'a Dispatcher<'a>,
+    /// How deep the flow is nested in flows calling it
+    depth: usize,
     state: XFState,
     current_node: Option<&'a XFlowNode>,
+    limits: XFlowLimits,
+    steps: usize,
+    visits: HashMap<i32, usize>,
+    /// The limits of the runner, once it has started, and of the flows calling it
+    budget: XFlowBudget,
+    started: bool,
+    trace: Option<XFlowTrace>,
+    observers: Vec<Box<dyn XFlowObserver>>,
+    conditions: HashMap<XFlowEdge, flox::Expr>,
+    expressions: Arc<HashMap<i32, flox::Expr>>,
+    fork_threads: usize,
+    /// An edge to follow on the next step instead of the edges out of the current node:
+    /// the error edge of a failed node, or the edge a branch of a fork starts with
+    next_edge: Option<XFlowEdge>,
+    /// For the runner of a branch of a fork, the join node the branch stops at
+    stop_at: Option<i32>,
+    /// The edge through which a branch of a fork reached its join node
+    stopped_at: Option<XFlowEdge>,
     pub output: Option<Vec<XFlowValue>>,
 }
 
+/// Everything needed to run a branch of a fork on another thread
+struct ForkBranch<'a> {
+    xflow: &'a XFlowDocument,
+    dispatcher: &'a Dispatcher<'a>,
+    depth: usize,
+    state: XFState,
+    conditions: HashMap<XFlowEdge, flox::Expr>,
+    expressions: Arc<HashMap<i32, flox::Expr>>,
+    limits: XFlowLimits,
+    fork_threads: usize,
+    steps: usize,
+    visits: HashMap<i32, usize>,
+    budget: XFlowBudget,
+    traced: bool,
+    fork: &'a XFlowNode,
+    edge: XFlowEdge,
+    join: i32,
+}
+
+/// The outcome of running a branch of a fork
+struct ForkBranchResult {
+    edge: XFlowEdge,
+    result: Result<(), RuntimeError>,
+    status: XFlowStatus,
+    state: XFState,
+    steps: usize,
+    visits: HashMap<i32, usize>,
+    trace: Option<XFlowTrace>,
+    stopped_at: Option<XFlowEdge>,
+}
+
+impl<'a> ForkBranch<'a> {
+    fn run(self) -> ForkBranchResult {
+        let edge = self.edge;
+        let mut runner = self.into_runner();
+        let result = runner.run();
+        ForkBranchResult::new(edge, runner, result)
+    }
+
+    fn run_async(self) -> AsyncForkBranch<'a> {
+        AsyncForkBranch {
+            edge: self.edge,
+            runner: self.into_runner(),
+            pending: None,
+            result: None,
+        }
+    }
+
+    fn into_runner(self) -> XFlowRunner<'a> {
+        XFlowRunner {
+            status: XFlowStatus::Running,
+            xflow: self.xflow,
+            dispatcher: self.dispatcher,
+            depth: self.depth,
+            state: self.state,
+            current_node: Some(self.fork),
+            limits: self.limits,
+            steps: self.steps,
+            visits: self.visits,
+            budget: self.budget,
+            started: true,
+            trace: if self.traced {
+                Some(XFlowTrace::new(self.xflow.id))
+            } else {
+                None
+            },
+            observers: Vec::new(),
+            conditions: self.conditions,
+            expressions: self.expressions,
+            fork_threads: self.fork_threads,
+            next_edge: Some(self.edge),
+            stop_at: Some(self.join),
+            stopped_at: None,
+            output: None,
+        }
+    }
+}
+
+impl ForkBranchResult {
+    fn new(edge: XFlowEdge, runner: XFlowRunner, result: Result<(), RuntimeError>) -> ForkBranchResult {
+        ForkBranchResult {
+            edge: edge,
+            result: result,
+            status: runner.status,
+            state: runner.state,
+            steps: runner.steps,
+            visits: runner.visits,
+            trace: runner.trace,
+            stopped_at: runner.stopped_at,
+        }
+    }
+}
+
+/// A branch of a fork running on the executor of `XFlowRunner::run_async`
+struct AsyncForkBranch<'a> {
+    edge: XFlowEdge,
+    runner: XFlowRunner<'a>,
+    pending: Option<Pending<'a>>,
+    result: Option<Result<(), RuntimeError>>,
+}
+
+impl<'a> AsyncForkBranch<'a> {
+    /// Poll the branch, returning whether it is done
+    fn poll(&mut self, cx: &mut Context) -> bool {
+        if self.result.is_none() {
+            if let Poll::Ready(result) = self.runner.poll_run(&mut self.pending, cx) {
+                self.result = Some(result);
+            }
+        }
+        self.result.is_some()
+    }
+
+    fn finish(self) -> ForkBranchResult {
+        let result = self.result.unwrap_or(Err(RuntimeError::NotFinished));
+        ForkBranchResult::new(self.edge, self.runner, result)
+    }
+}
+
+/// Work an asynchronous run is waiting for
+enum Pending<'a> {
+    /// The dispatch of the node of `transition`, with the state before it when the steps
+    /// are observed
+    Node {
+        transition: Transition<'a>,
+        before: Option<XFState>,
+        future: DispatchFuture<'a>,
+    },
+    /// The branches of a fork
+    Fork {
+        fork: &'a XFlowNode,
+        params: &'a ForkParameters,
+        branches: Vec<AsyncForkBranch<'a>>,
+    },
+}
+
+struct TimerState {
+    deadline: Instant,
+    waker: Option<Waker>,
+    stopped: bool,
+}
+
+/// Wakes an asynchronous run at a deadline. A single thread waits for the deadline, and
+/// stops early when the timer is dropped with the run.
+struct DeadlineTimer {
+    state: Arc<(Mutex<TimerState>, Condvar)>,
+}
+
+impl DeadlineTimer {
+    fn start(deadline: Instant, waker: Waker) -> DeadlineTimer {
+        let state = Arc::new((
+            Mutex::new(TimerState {
+                deadline: deadline,
+                waker: Some(waker),
+                stopped: false,
+            }),
+            Condvar::new(),
+        ));
+        let shared = state.clone();
+        thread::spawn(move || {
+            let (ref lock, ref changed) = *shared;
+            let mut timer = lock.lock().unwrap();
+            while !timer.stopped {
+                let now = Instant::now();
+                if now >= timer.deadline {
+                    if let Some(waker) = timer.waker.take() {
+                        waker.wake();
+                    }
+                    return;
+                }
+                let wait = timer.deadline - now;
+                timer = changed.wait_timeout(timer, wait).unwrap().0;
+            }
+        });
+        DeadlineTimer { state: state }
+    }
+
+    /// Wake `waker` instead, at `deadline` if that is earlier
+    fn update(&self, deadline: Instant, waker: &Waker) {
+        let (ref lock, ref changed) = *self.state;
+        let mut timer = lock.lock().unwrap();
+        let replace = match timer.waker {
+            Some(ref current) => !current.will_wake(waker),
+            None => false,
+        };
+        if replace {
+            timer.waker = Some(waker.clone());
+        }
+        if deadline < timer.deadline {
+            timer.deadline = deadline;
+            changed.notify_one();
+        }
+    }
+}
+
+impl Drop for DeadlineTimer {
+    fn drop(&mut self) {
+        let (ref lock, ref changed) = *self.state;
+        if let Ok(mut timer) = lock.lock() {
+            timer.stopped = true;
+            changed.notify_one();
+        }
+    }
+}
+
+/// The future returned by `XFlowRunner::run_async`, which resolves when the flow
+/// finishes or fails. Dropping it while a node or fork is pending aborts the runner, as
+/// the flow cannot continue in the middle of a step.
+pub struct RunAsync<'r, 'a: 'r> {
+    runner: &'r mut XFlowRunner<'a>,
+    pending: Option<Pending<'a>>,
+    timer: Option<DeadlineTimer>,
+}
+
+impl<'r, 'a> Future for RunAsync<'r, 'a> {
+    type Output = Result<(), RuntimeError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let res = this.runner.poll_run(&mut this.pending, cx);
+        match res {
+            Poll::Pending => this.runner.wake_at_deadline(&mut this.timer, cx),
+            Poll::Ready(_) => this.timer = None,
+        }
+        res
+    }
+}
+
+impl<'r, 'a> Drop for RunAsync<'r, 'a> {
+    fn drop(&mut self) {
+        if self.pending.take().is_some() {
+            warn!(
+                "Asynchronous run of xflow '{}' dropped at node {:?}",
+                self.runner.xflow.id,
+                self.runner.current_node.map(|node| node.id)
+            );
+            self.runner.status = XFlowStatus::Aborted;
+        }
+    }
+}
+
+/// The future returned by `XFlowRunner::into_run_async`, which owns the runner and hands
+/// it back with the result of the run, for receivers such as `Call` that start a flow in
+/// the middle of a dispatch
+pub struct OwnedRunAsync<'a> {
+    runner: Option<XFlowRunner<'a>>,
+    pending: Option<Pending<'a>>,
+    timer: Option<DeadlineTimer>,
+}
+
+impl<'a> Future for OwnedRunAsync<'a> {
+    type Output = (XFlowRunner<'a>, Result<(), RuntimeError>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let runner = match this.runner {
+            Some(ref mut runner) => runner,
+            None => panic!("OwnedRunAsync polled after it completed"),
+        };
+        match runner.poll_run(&mut this.pending, cx) {
+            Poll::Ready(res) => {
+                this.timer = None;
+                Poll::Ready((this.runner.take().unwrap(), res))
+            }
+            Poll::Pending => {
+                runner.wake_at_deadline(&mut this.timer, cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
 impl<'a> XFlowRunner<'a> {
     pub fn new(
         xflow: &'a XFlowDocument,
         dispatcher: &'a Dispatcher<'a>,
-        input: &'a XFState,
-    ) -> Result<XFlowRunner<'a>, String> {
+        input: &XFState,
+    ) -> Result<XFlowRunner<'a>, RuntimeError> {
 
         let mut state = XFState::default();
 
@@ -35,12 +448,15 @@ impl<'a> XFlowRunner<'a> {
             match input.get(&xvardef.name) {
                 Some(xvar) => state.add(xvar),
                 None => {
-                    let err = format!(
+                    error!(
                         "Missing required xvar in input parameters : {}",
                         xvardef.name
                     );
-                    error!("{}", err);
-                    return Err(err);
+                    return Err(RuntimeError::MissingVariable {
+                        node: None,
+                        name: xvardef.name.clone(),
+                        state: input.clone(),
+                    });
                 }
             }
         }
@@ -49,21 +465,176 @@ impl<'a> XFlowRunner<'a> {
             state.add(xvar);
         }
 
+        check_capabilities(xflow, dispatcher, true)?;
+        let conditions = compile_conditions(xflow, &state)?;
+        let expressions = compile_expressions(xflow, &state)?;
+
         match xflow.body.get_entry_node() {
             Ok(node) => {
                 Ok(XFlowRunner {
                     status: XFlowStatus::Initialized,
                     xflow: xflow,
                     dispatcher: dispatcher,
+                    depth: 0,
                     state: state,
                     current_node: Some(node),
+                    limits: XFlowLimits::default(),
+                    steps: 0,
+                    visits: HashMap::new(),
+                    budget: XFlowBudget::default(),
+                    started: false,
+                    trace: None,
+                    observers: Vec::new(),
+                    conditions: conditions,
+                    expressions: Arc::new(expressions),
+                    fork_threads: DEFAULT_FORK_THREADS,
+                    next_edge: None,
+                    stop_at: None,
+                    stopped_at: None,
                     output: None,
                 })
             }
-            _ => Err("Unable to init XFlowRunner".to_owned()),
+            Err(err) => Err(RuntimeError::Initialization {
+                message: format!("Unable to init XFlowRunner : {:?}", err),
+            }),
         }
     }
 
+    /// Resume a runner from a checkpoint that was taken while running `xflow`. The
+    /// checkpoint is rejected if it was taken from a different flow or flow version.
+    pub fn resume(
+        xflow: &'a XFlowDocument,
+        dispatcher: &'a Dispatcher<'a>,
+        checkpoint: &XFlowCheckpoint,
+    ) -> Result<XFlowRunner<'a>, RuntimeError> {
+        if checkpoint.xflow_id != xflow.id {
+            return Err(RuntimeError::InvalidCheckpoint {
+                message: format!(
+                    "Checkpoint was taken from xflow '{}', not '{}'",
+                    checkpoint.xflow_id,
+                    xflow.id
+                ),
+            });
+        }
+
+        if checkpoint.xflow_version != xflow.version {
+            return Err(RuntimeError::InvalidCheckpoint {
+                message: format!(
+                    "Checkpoint was taken from version {} of xflow '{}', current version is {}",
+                    checkpoint.xflow_version,
+                    xflow.id,
+                    xflow.version
+                ),
+            });
+        }
+
+        let current_node = match checkpoint.current_node {
+            Some(id) => {
+                match xflow.body.get_node_id(id) {
+                    Some(node) => Some(node),
+                    None => {
+                        return Err(RuntimeError::InvalidCheckpoint {
+                            message: format!("Node {} not found in xflow '{}'", id, xflow.id),
+                        })
+                    }
+                }
+            }
+            None => None,
+        };
+
+        check_capabilities(xflow, dispatcher, true)?;
+        let conditions = compile_conditions(xflow, &checkpoint.state)?;
+        let expressions = compile_expressions(xflow, &checkpoint.state)?;
+
+        Ok(XFlowRunner {
+            status: checkpoint.status.clone(),
+            xflow: xflow,
+            dispatcher: dispatcher,
+            depth: 0,
+            state: checkpoint.state.clone(),
+            current_node: current_node,
+            limits: XFlowLimits::default(),
+            steps: checkpoint.steps,
+            visits: checkpoint.visits.clone(),
+            budget: XFlowBudget::default(),
+            started: false,
+            trace: None,
+            observers: Vec::new(),
+            conditions: conditions,
+            expressions: Arc::new(expressions),
+            fork_threads: DEFAULT_FORK_THREADS,
+            next_edge: checkpoint.next_edge,
+            stop_at: None,
+            stopped_at: None,
+            output: None,
+        })
+    }
+
+    /// Take a serializable snapshot of the runner, to be resumed with `XFlowRunner::resume`
+    pub fn checkpoint(&self) -> XFlowCheckpoint {
+        XFlowCheckpoint {
+            xflow_id: self.xflow.id,
+            xflow_version: self.xflow.version,
+            current_node: self.current_node.map(|node| node.id),
+            next_edge: self.next_edge,
+            status: self.status.clone(),
+            steps: self.steps,
+            visits: self.visits.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    /// Set the execution limits for this runner
+    pub fn with_limits(mut self, limits: XFlowLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run within `budget`, what remains of the limits of the flows calling this one,
+    /// on top of the limits of this runner
+    pub fn with_budget(mut self, budget: XFlowBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Set how deep the flow is nested in flows calling it, for a runner started by a
+    /// receiver such as `Call`
+    pub fn with_call_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Set the number of branches of a fork that run at the same time
+    pub fn with_fork_threads(mut self, fork_threads: usize) -> Self {
+        self.fork_threads = fork_threads.max(1);
+        self
+    }
+
+    /// Record a trace of every step taken by this runner
+    pub fn with_tracing(mut self) -> Self {
+        self.trace = Some(XFlowTrace::new(self.xflow.id));
+        self
+    }
+
+    /// Return the trace recorded so far, if tracing is enabled
+    pub fn get_trace(&self) -> Option<&XFlowTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Register an observer that is notified of every step, error and finish
+    pub fn add_observer<T: XFlowObserver + 'static>(&mut self, observer: T) {
+        self.observers.push(Box::new(observer));
+    }
+
+    pub fn get_limits(&self) -> &XFlowLimits {
+        &self.limits
+    }
+
+    /// Number of nodes dispatched so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
     pub fn can_run(&self) -> bool {
         match self.status {
             XFlowStatus::Initialized | XFlowStatus::Running => true,
@@ -84,104 +655,744 @@ impl<'a> XFlowRunner<'a> {
         self.status == XFlowStatus::Finished
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        // XXX: Clean up error handling
+    /// Run the flow until it finishes, stopping at and returning the first error. The
+    /// flow fails before running any node when a node has only an asynchronous receiver.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        if self.can_run() {
+            check_capabilities(self.xflow, self.dispatcher, false)?;
+        }
         while self.can_run() {
-            match self.step() {
-                Ok(()) => (),
-                Err(err) => {
-                    error!("{}", err);
-                }
-            }
+            self.step()?;
         }
-        if self.is_completed_ok() {
-            Ok(())
-        } else {
-            let msg = "Unhandled error has occurred while running flow".to_owned();
-            Err(msg)
+        Ok(())
+    }
+
+    /// Move to the next node and dispatch it. If the current node is a terminal node the
+    /// flow is marked as finished.
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        let res = self.advance();
+        self.notify(&res);
+        res
+    }
+
+    /// Run the flow like `run`, but dispatch nodes through `Dispatcher::dispatch_async`,
+    /// so receivers waiting for I/O do not block the executor. The branches of a fork run
+    /// concurrently on the executor instead of on threads.
+    pub fn run_async<'r>(&'r mut self) -> RunAsync<'r, 'a> {
+        RunAsync {
+            runner: self,
+            pending: None,
+            timer: None,
         }
     }
 
-    pub fn step(&mut self) -> Result<(), String> {
-        self.next_node();
-        self.run_node()
+    /// Run the flow like `run_async`, in a future that owns the runner
+    pub fn into_run_async(self) -> OwnedRunAsync<'a> {
+        OwnedRunAsync {
+            runner: Some(self),
+            pending: None,
+            timer: None,
+        }
     }
 
-    fn run_node(&mut self) -> Result<(), String> {
-        let st = &mut self.state;
-        if let Some(node) = self.current_node {
-            self.status = XFlowStatus::Running;
-            match self.dispatcher.dispatch(node, st) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(err),
+    fn notify(&mut self, res: &Result<(), RuntimeError>) {
+        match *res {
+            Ok(()) => {
+                if self.status == XFlowStatus::Finished {
+                    for observer in &mut self.observers {
+                        observer.on_finish(&self.status);
+                    }
+                }
+            }
+            Err(ref err) => {
+                for observer in &mut self.observers {
+                    observer.on_error(err);
+                }
             }
-        } else {
-            self.status = XFlowStatus::Finished;
-            Ok(())
         }
     }
 
-    fn next_node(&mut self) -> () {
-        if let Some(current_node) = self.current_node {
-            let edges = self.xflow.body.get_out_edges(current_node);
-            match edges.len() {
-                0 => {
-                    self.status = XFlowStatus::InvalidState;
-                    self.current_node = None;
+    fn advance(&mut self) -> Result<(), RuntimeError> {
+        let next = match self.fork_to_run() {
+            Some((fork, params)) => self.run_fork(fork, params),
+            None => self.next_transition(),
+        };
+
+        match self.enter(next)? {
+            Some(transition) => {
+                let before = self.observed_state();
+                let res = self.run_node(transition.node);
+                self.leave(&transition, before, res)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Poll the asynchronous run of the flow, continuing with the dispatch or fork in
+    /// `pending` when there is one
+    fn poll_run(
+        &mut self,
+        pending: &mut Option<Pending<'a>>,
+        cx: &mut Context,
+    ) -> Poll<Result<(), RuntimeError>> {
+        loop {
+            let res = match pending.take() {
+                Some(Pending::Node {
+                    transition,
+                    before,
+                    mut future,
+                }) => {
+                    match future.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            if let Some(limit) = self.budget.exceeded() {
+                                // The dispatch is cancelled by dropping its future
+                                Err(self.timed_out(transition.node, limit))
+                            } else {
+                                *pending = Some(Pending::Node {
+                                    transition: transition,
+                                    before: before,
+                                    future: future,
+                                });
+                                return Poll::Pending;
+                            }
+                        }
+                        Poll::Ready((state, res)) => {
+                            self.state = state;
+                            let res = self.dispatched(transition.node, res);
+                            self.leave(&transition, before, res)
+                        }
+                    }
                 }
-                1 => {
-                    if let Some(edge) = edges.first() {
-                        self.current_node = self.xflow.body.get_node_id(edge.1);
-                    } else {
-                        self.status = XFlowStatus::InvalidState;
-                        self.current_node = None;
+                Some(Pending::Fork {
+                    fork,
+                    params,
+                    mut branches,
+                }) => {
+                    let mut done = true;
+                    for branch in &mut branches {
+                        done &= branch.poll(cx);
+                    }
+                    if !done {
+                        *pending = Some(Pending::Fork {
+                            fork: fork,
+                            params: params,
+                            branches: branches,
+                        });
+                        return Poll::Pending;
                     }
+                    let results = branches.into_iter().map(AsyncForkBranch::finish).collect();
+                    let next = self.finish_fork(fork, params, results);
+                    self.enter(next).map(|next| self.start_async(pending, next))
                 }
-                _ => {
-
-                    // XXX This branch matching is sloppy - it should happen on the node.parameters
-                    // settings
-                    //
-
-                    let branches: Vec<&XFlowBranch> = self.xflow
-                        .body
-                        .get_out_branches(current_node.id)
-                        .iter()
-                        .filter({
-                            |branch| {
-                                let xv = self.state.get(&branch.xvar.name);
-                                if let Some(xvar) = xv {
-                                    *xvar == branch.xvar
-                                } else {
-                                    false
+                None => {
+                    if !self.can_run() {
+                        return Poll::Ready(Ok(()));
+                    }
+                    match self.fork_to_run() {
+                        Some((fork, params)) => {
+                            match self.fork_branches(fork, params) {
+                                Ok(branches) => {
+                                    *pending = Some(Pending::Fork {
+                                        fork: fork,
+                                        params: params,
+                                        branches: branches
+                                            .into_iter()
+                                            .map(ForkBranch::run_async)
+                                            .collect(),
+                                    });
+                                    continue;
+                                }
+                                Err(err) => {
+                                    let next = Err(err);
+                                    self.enter(next).map(|_| ())
                                 }
-                            }
-                        })
-                        .cloned()
-                        .collect();
-                    match branches.len() {
-                        1 => {
-                            if let Some(branch) = branches.first() {
-                                self.current_node = self.xflow.body.get_node_id(branch.edge.1);
-                            } else {
-                                self.status = XFlowStatus::InvalidState;
-                                self.current_node = None;
                             }
                         }
-                        _ => {
-                            self.status = XFlowStatus::InvalidState;
-                            self.current_node = None;
+                        None => {
+                            let next = self.next_transition();
+                            self.enter(next).map(|next| self.start_async(pending, next))
                         }
                     }
                 }
+            };
+
+            // A step ends once no dispatch is pending
+            if pending.is_none() {
+                self.notify(&res);
+            }
+            if let Err(err) = res {
+                return Poll::Ready(Err(err));
+            }
+        }
+    }
+
+    /// Start the asynchronous dispatch of the node `next` moves to, if any. The dispatch
+    /// works on a copy of the state, so the runner keeps its state until the dispatch
+    /// completes.
+    fn start_async(&mut self, pending: &mut Option<Pending<'a>>, next: Option<Transition<'a>>) {
+        if let Some(transition) = next {
+            let before = self.observed_state();
+            self.status = XFlowStatus::Running;
+            let state = self.state.clone();
+            *pending = Some(Pending::Node {
+                future: self.dispatcher.dispatch_async_in(transition.node, state, &self.context()),
+                transition: transition,
+                before: before,
+            });
+        }
+    }
+
+    /// Keep `timer` waking the task of `cx` at the earliest deadline of the run, so a
+    /// dispatch still pending then is cancelled
+    fn wake_at_deadline(&self, timer: &mut Option<DeadlineTimer>, cx: &mut Context) {
+        if let Some(remaining) = self.budget.remaining_time() {
+            let deadline = Instant::now() + remaining;
+            match *timer {
+                Some(ref timer) => timer.update(deadline, cx.waker()),
+                None => *timer = Some(DeadlineTimer::start(deadline, cx.waker().clone())),
+            }
+        }
+    }
+
+    /// Return the current node and its parameters when it is a fork whose branches have
+    /// to run next
+    fn fork_to_run(&self) -> Option<(&'a XFlowNode, &'a ForkParameters)> {
+        match self.current_node {
+            Some(node) if self.next_edge.is_none() => {
+                match node.parameters {
+                    XFlowNodeParameters::Fork(ref params) => Some((node, params)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Find the transition to the next node, following a pending error edge first
+    fn next_transition(&mut self) -> Result<Option<Transition<'a>>, RuntimeError> {
+        match self.next_edge.take() {
+            Some(edge) => {
+                self.get_node(edge.1).map(|node| {
+                    Some(Transition {
+                        node: node,
+                        edge: edge,
+                        branch: None,
+                    })
+                })
+            }
+            None => self.next_node(),
+        }
+    }
+
+    /// Move to the node of `next` and check the execution limits. Returns the transition
+    /// when its node has to be dispatched, and `None` when the flow or branch finished.
+    fn enter(
+        &mut self,
+        next: Result<Option<Transition<'a>>, RuntimeError>,
+    ) -> Result<Option<Transition<'a>>, RuntimeError> {
+        match next {
+            Ok(Some(ref transition)) if Some(transition.node.id) == self.stop_at => {
+                // A branch of a fork ends where it reaches its join node
+                self.stopped_at = Some(transition.edge);
+                self.status = XFlowStatus::Finished;
+                self.current_node = None;
+                Ok(None)
+            }
+            Ok(Some(transition)) => {
+                self.current_node = Some(transition.node);
+                self.check_limits(transition.node)?;
+                Ok(Some(transition))
+            }
+            Ok(None) => {
+                self.status = XFlowStatus::Finished;
+                self.current_node = None;
+                Ok(None)
+            }
+            Err(err) => {
+                error!("{}", err);
+                if !self.is_completed() {
+                    self.status = XFlowStatus::InvalidState;
+                }
+                self.current_node = None;
+                Err(err)
+            }
+        }
+    }
+
+    /// A copy of the state before a node is dispatched, when the steps are observed
+    fn observed_state(&self) -> Option<XFState> {
+        if self.is_observed() {
+            Some(self.state.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Handle the result of dispatching the node of `transition`, and record the step
+    /// when the steps are observed
+    fn leave(
+        &mut self,
+        transition: &Transition<'a>,
+        before: Option<XFState>,
+        res: Result<(), RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let node = transition.node;
+        res.or_else(|err| self.catch_error(node, err))?;
+
+        if let Some(before) = before {
+            let step = XFlowTraceStep {
+                step: self.steps,
+                node: node.id,
+                nodetype: node.nodetype.clone(),
+                action: node.action.clone(),
+                edge: transition.edge,
+                branch: transition.branch.cloned(),
+                changes: state_changes(&before, &self.state),
+            };
+            self.record_step(&step);
+        }
+        Ok(())
+    }
+
+    /// Run the branches of `fork` on a pool of `fork_threads` workers, each taking the
+    /// next waiting branch as soon as it is done with one, merge their changes to the
+    /// state and move to the join node
+    fn run_fork(
+        &mut self,
+        fork: &'a XFlowNode,
+        params: &ForkParameters,
+    ) -> Result<Option<Transition<'a>>, RuntimeError> {
+        let branches = self.fork_branches(fork, params)?;
+        let count = branches.len();
+        let queue = Mutex::new(branches.into_iter().enumerate().collect::<VecDeque<_>>());
+
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..self.fork_threads.min(count) {
+                let queue = &queue;
+                let sender = sender.clone();
+                scope.spawn(move || loop {
+                    let next = queue.lock().unwrap().pop_front();
+                    let (index, branch) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let edge = branch.edge;
+                    let res = panic::catch_unwind(AssertUnwindSafe(|| branch.run()));
+                    let _ = sender.send((index, edge, res));
+                });
+            }
+        });
+        drop(sender);
+
+        // The branches are merged in the order of their edges, whichever finished first
+        let mut finished: Vec<_> = receiver.into_iter().collect();
+        finished.sort_by_key(|&(index, _, _)| index);
+
+        let mut results = Vec::<ForkBranchResult>::with_capacity(count);
+        for (_, edge, res) in finished {
+            match res {
+                Ok(res) => results.push(res),
+                Err(_) => {
+                    self.status = XFlowStatus::Aborted;
+                    return Err(RuntimeError::Dispatch {
+                        node: fork.id,
+                        message: format!("Branch {:?} of the fork panicked", edge),
+                        state: self.state.clone(),
+                    });
+                }
+            }
+        }
+
+        self.finish_fork(fork, params, results)
+    }
+
+    /// Prepare a branch of `fork` for every edge out of it
+    fn fork_branches(
+        &self,
+        fork: &'a XFlowNode,
+        params: &ForkParameters,
+    ) -> Result<Vec<ForkBranch<'a>>, RuntimeError> {
+        let join = self.get_node(params.join)?;
+        Ok(
+            self.xflow
+                .body
+                .get_out_edges(fork)
+                .into_iter()
+                .map(|edge| {
+                    ForkBranch {
+                        xflow: self.xflow,
+                        dispatcher: self.dispatcher,
+                        depth: self.depth,
+                        state: self.state.clone(),
+                        conditions: self.conditions.clone(),
+                        expressions: self.expressions.clone(),
+                        limits: self.limits.clone(),
+                        fork_threads: self.fork_threads,
+                        steps: self.steps,
+                        visits: self.visits.clone(),
+                        budget: self.budget.clone(),
+                        traced: self.is_observed(),
+                        fork: fork,
+                        edge: *edge,
+                        join: join.id,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Check that every branch of `fork` reached the join node, then merge their results
+    /// and move to the join node
+    fn finish_fork(
+        &mut self,
+        fork: &'a XFlowNode,
+        params: &ForkParameters,
+        results: Vec<ForkBranchResult>,
+    ) -> Result<Option<Transition<'a>>, RuntimeError> {
+        let join = self.get_node(params.join)?;
+        for res in &results {
+            if let Err(ref err) = res.result {
+                self.status = res.status.clone();
+                return Err(err.clone());
+            }
+            if res.stopped_at.is_none() {
+                self.status = XFlowStatus::InvalidState;
+                return Err(RuntimeError::Dispatch {
+                    node: fork.id,
+                    message: format!(
+                        "Branch {:?} of the fork ended without reaching join node {}",
+                        res.edge,
+                        join.id
+                    ),
+                    state: res.state.clone(),
+                });
+            }
+        }
+
+        self.merge_fork(fork, params, results).map(|edge| {
+            Some(Transition {
+                node: join,
+                edge: edge,
+                branch: None,
+            })
+        })
+    }
+
+    /// Apply the changes of the branches of a fork to the state, in the order of their
+    /// edges, and count their steps. Returns the edge into the join node of the last
+    /// branch.
+    fn merge_fork(
+        &mut self,
+        fork: &XFlowNode,
+        params: &ForkParameters,
+        results: Vec<ForkBranchResult>,
+    ) -> Result<XFlowEdge, RuntimeError> {
+        let base = self.state.clone();
+        let mut changed = HashMap::<String, Option<XFlowVariable>>::new();
+
+        for res in &results {
+            for change in state_changes(&base, &res.state) {
+                if let Some(previous) = changed.get(&change.name) {
+                    if *previous != change.new && params.conflicts == XFlowMergePolicy::Fail {
+                        self.status = XFlowStatus::Aborted;
+                        return Err(RuntimeError::ForkConflict {
+                            node: fork.id,
+                            name: change.name.clone(),
+                            state: base.clone(),
+                        });
+                    }
+                }
+                match change.new {
+                    Some(ref xvar) => self.state.add(xvar),
+                    None => self.state.remove(&change.name),
+                }
+                changed.insert(change.name, change.new);
+            }
+        }
+
+        let steps = self.steps;
+        let visits = self.visits.clone();
+        let mut join_edge = (fork.id, fork.id);
+        let mut number = steps;
+        for res in results {
+            self.steps += res.steps - steps;
+            for (id, count) in res.visits {
+                *self.visits.entry(id).or_insert(0) += count - visits.get(&id).cloned().unwrap_or(0);
+            }
+            if let Some(trace) = res.trace {
+                // Number the steps of all branches one after the other
+                for mut step in trace.steps {
+                    number += 1;
+                    step.step = number;
+                    self.record_step(&step);
+                }
+            }
+            if let Some(edge) = res.stopped_at {
+                join_edge = edge;
+            }
+        }
+
+        Ok(join_edge)
+    }
+
+    fn record_step(&mut self, step: &XFlowTraceStep) {
+        if let Some(ref mut trace) = self.trace {
+            trace.on_step(step);
+        }
+        for observer in &mut self.observers {
+            observer.on_step(step);
+        }
+    }
+
+    fn is_observed(&self) -> bool {
+        self.trace.is_some() || !self.observers.is_empty()
+    }
+
+    /// Count a step into `node` and check the execution limits. The limits of the runner
+    /// are added to its budget on the first step.
+    fn check_limits(&mut self, node: &XFlowNode) -> Result<(), RuntimeError> {
+        if !self.started {
+            self.started = true;
+            self.budget = self.budget.with_limits(&self.limits, self.steps);
+        }
+        self.steps += 1;
+        let visits = {
+            let visits = self.visits.entry(node.id).or_insert(0);
+            *visits += 1;
+            *visits
+        };
+
+        let visits_exceeded = match self.limits.max_node_visits {
+            Some(max) if visits > max => Some(ExecutionLimit::MaxNodeVisits(max)),
+            _ => None,
+        };
+
+        match self.budget.take_step().or(visits_exceeded) {
+            Some(limit) => Err(self.timed_out(node, limit)),
+            None => Ok(()),
+        }
+    }
+
+    /// Mark the flow as timed out at `node` and return the error for `limit`
+    fn timed_out(&mut self, node: &XFlowNode, limit: ExecutionLimit) -> RuntimeError {
+        error!("XFlow '{}' timed out at node {} : {}", self.xflow.id, node.id, limit);
+        self.status = XFlowStatus::TimedOut;
+        RuntimeError::LimitExceeded {
+            node: node.id,
+            limit: limit,
+            state: self.state.clone(),
+        }
+    }
+
+    fn run_node(&mut self, node: &XFlowNode) -> Result<(), RuntimeError> {
+        self.status = XFlowStatus::Running;
+        let context = self.context();
+        let res = self.dispatcher.dispatch_in(node, &mut self.state, &context);
+        self.dispatched(node, res)
+    }
+
+    /// The context in which the nodes of the flow are dispatched
+    fn context(&self) -> DispatchContext<'a> {
+        DispatchContext {
+            dispatcher: self.dispatcher,
+            depth: self.depth,
+            budget: self.budget.clone(),
+            expressions: self.expressions.clone(),
+        }
+    }
+
+    /// Mark the flow as aborted when dispatching `node` failed, and as timed out when the
+    /// dispatch, such as a slow receiver or a called flow, used up the budget
+    fn dispatched(&mut self, node: &XFlowNode, res: Result<(), RuntimeError>) -> Result<(), RuntimeError> {
+        match (res, self.budget.exceeded()) {
+            (Ok(()), None) => Ok(()),
+            (Ok(()), Some(limit)) => Err(self.timed_out(node, limit)),
+            (Err(err), exceeded) => {
+                self.status = if exceeded.is_some() {
+                    XFlowStatus::TimedOut
+                } else {
+                    XFlowStatus::Aborted
+                };
+                Err(err)
+            }
+        }
+    }
+
+    /// Handle the failure of `node` by storing it in the error variable and following the
+    /// error edge of the node, or else the error handler of the flow, on the next step.
+    /// The error is returned when the flow does not handle it. Branches of a fork only
+    /// follow error edges, as the error handler lies outside of the branch.
+    fn catch_error(&mut self, node: &XFlowNode, err: RuntimeError) -> Result<(), RuntimeError> {
+        if self.status == XFlowStatus::TimedOut {
+            // Error edges cannot extend a run past its limits
+            return Err(err);
+        }
+        let edge = match self.xflow.body.get_error_edge(node.id) {
+            Some(edge) => *edge,
+            None => {
+                match self.xflow.body.error_handler {
+                    Some(handler) if handler != node.id && self.stop_at.is_none() => {
+                        (node.id, handler)
+                    }
+                    _ => return Err(err),
+                }
+            }
+        };
+
+        warn!("Node {} failed, continuing with node {} : {}", node.id, edge.1, err);
+        self.state.add(&XFlowVariable {
+            name: ERROR_VARIABLE.to_owned(),
+            vtype: XFlowValueType::Record,
+            value: err.to_value(),
+        });
+        self.status = XFlowStatus::Running;
+        self.next_edge = Some(edge);
+        Ok(())
+    }
+
+    fn next_node(&self) -> Result<Option<Transition<'a>>, RuntimeError> {
+        let current_node = match self.current_node {
+            Some(node) => node,
+            None => {
+                return Err(RuntimeError::Initialization {
+                    message: "XFlowRunner has no current node".to_owned(),
+                })
+            }
+        };
+
+        if let XFlowNodeParameters::Foreach(ref params) = current_node.parameters {
+            return self.next_foreach_node(current_node, params);
+        }
+
+        let edges = self.xflow.body.get_out_edges(current_node);
+        match edges.len() {
+            0 => {
+                if current_node.nodetype == XFlowNodeType::Flow && current_node.action == "end" {
+                    Ok(None)
+                } else {
+                    Err(RuntimeError::NoOutgoingEdge {
+                        node: current_node.id,
+                        state: self.state.clone(),
+                    })
+                }
+            }
+            1 => {
+                let node = self.get_node(edges[0].1)?;
+                Ok(Some(Transition {
+                    node: node,
+                    edge: *edges[0],
+                    branch: None,
+                }))
+            }
+            _ => {
+                let mut branches = Vec::<&XFlowBranch>::new();
+                let mut defaults = Vec::<&XFlowBranch>::new();
+                for branch in self.xflow.body.get_out_branches(current_node.id) {
+                    if branch.default {
+                        defaults.push(branch);
+                    } else if self.branch_matches(current_node, branch)? {
+                        branches.push(branch);
+                    }
+                }
+
+                if branches.is_empty() {
+                    branches = defaults;
+                }
+
+                match branches.len() {
+                    0 => Err(RuntimeError::NoMatchingBranch {
+                        node: current_node.id,
+                        state: self.state.clone(),
+                    }),
+                    1 => {
+                        let branch = branches[0];
+                        let node = self.get_node(branch.edge.1)?;
+                        Ok(Some(Transition {
+                            node: node,
+                            edge: branch.edge,
+                            branch: Some(branch),
+                        }))
+                    }
+                    _ => Err(RuntimeError::AmbiguousBranch {
+                        node: current_node.id,
+                        edges: branches.iter().map(|branch| branch.edge).collect(),
+                        state: self.state.clone(),
+                    }),
+                }
             }
+        }
+    }
+
+    /// Follow the edge into the loop body while the `foreach` loop of `node` has an
+    /// element, the other edge once it is done
+    fn next_foreach_node(
+        &self,
+        node: &XFlowNode,
+        params: &ForeachParameters,
+    ) -> Result<Option<Transition<'a>>, RuntimeError> {
+        let in_loop = self.state.has(&params.index);
+        let edge = self.xflow
+            .body
+            .get_out_edges(node)
+            .into_iter()
+            .find(|edge| (edge.1 == params.body) == in_loop);
+
+        match edge {
+            Some(edge) => {
+                Ok(Some(Transition {
+                    node: self.get_node(edge.1)?,
+                    edge: *edge,
+                    branch: None,
+                }))
+            }
+            None => Err(RuntimeError::NoOutgoingEdge {
+                node: node.id,
+                state: self.state.clone(),
+            }),
+        }
+    }
+
+    /// Check whether `branch` can be taken from `node`. A Flox condition has to evaluate
+    /// to a boolean, an `xvar` has to be equal to the variable in the state.
+    fn branch_matches(&self, node: &XFlowNode, branch: &XFlowBranch) -> Result<bool, RuntimeError> {
+        if let Some(ref condition) = branch.condition {
+            let expr = &self.conditions[&branch.edge];
+            match expr.evaluate(&self.state) {
+                Ok(XFlowValue::Boolean(b)) => Ok(b),
+                Ok(res) => Err(RuntimeError::FloxEvaluation {
+                    node: node.id,
+                    expression: condition.clone(),
+                    position: 0,
+                    message: format!("Branch condition is not boolean, found {:?}", res),
+                    state: self.state.clone(),
+                }),
+                Err(err) => Err(RuntimeError::FloxEvaluation {
+                    node: node.id,
+                    expression: condition.clone(),
+                    position: err.offset,
+                    message: err.message,
+                    state: self.state.clone(),
+                }),
+            }
+        } else if let Some(ref xvar) = branch.xvar {
+            Ok(self.state.get(&xvar.name) == Some(xvar))
         } else {
-            self.status = XFlowStatus::InvalidState;
-            self.current_node = None;
+            Ok(false)
+        }
+    }
+
+    fn get_node(&self, id: i32) -> Result<&'a XFlowNode, RuntimeError> {
+        match self.xflow.body.get_node_id(id) {
+            Some(node) => Ok(node),
+            None => Err(RuntimeError::NodeNotFound {
+                node: id,
+                state: self.state.clone(),
+            }),
         }
     }
 
-    pub fn get_output(self) -> Result<XFState, String> {
+    pub fn get_output(self) -> Result<XFState, RuntimeError> {
         if self.status == XFlowStatus::Finished {
             let mut state = XFState::default();
             for xvar_out in &self.xflow.body.variables.output {
@@ -189,26 +1400,106 @@ impl<'a> XFlowRunner<'a> {
                     if xvar_local.vtype == xvar_out.vtype {
                         state.add(xvar_local);
                     } else {
-                        let msg = format!(
-                            "Output var '{}' has a different type than its local \
-                                           one",
+                        error!(
+                            "Output var '{}' has a different type than its local one",
                             &xvar_out.name
                         );
-
-                        error!("{}", msg);
-                        return Err(msg);
+                        return Err(RuntimeError::OutputTypeMismatch {
+                            name: xvar_out.name.clone(),
+                            expected: xvar_out.vtype.clone(),
+                            found: xvar_local.vtype.clone(),
+                            state: self.state.clone(),
+                        });
                     }
                 } else {
-                    let msg = format!("Required var '{:?}' not found in state!", &xvar_out.name);
-                    error!("{}", msg);
-                    return Err(msg);
+                    error!("Required var '{:?}' not found in state!", &xvar_out.name);
+                    return Err(RuntimeError::MissingVariable {
+                        node: None,
+                        name: xvar_out.name.clone(),
+                        state: self.state.clone(),
+                    });
                 }
             }
             Ok(state)
         } else {
-            let msg = "Called before xflow has finished!".to_owned();
-            error!("{}", msg);
-            Err(msg)
+            error!("Called before xflow has finished!");
+            Err(RuntimeError::NotFinished)
+        }
+    }
+}
+
+/// Check that `dispatcher` can run every node of `xflow` before the flow starts, with its
+/// asynchronous receivers too when `asynchronous` is set
+fn check_capabilities(
+    xflow: &XFlowDocument,
+    dispatcher: &Dispatcher,
+    asynchronous: bool,
+) -> Result<(), RuntimeError> {
+    let missing = if asynchronous {
+        dispatcher.missing_async_capabilities(&xflow.body)
+    } else {
+        dispatcher.missing_capabilities(&xflow.body)
+    };
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        error!("XFlow '{}' has unsatisfied requirements : {:?}", xflow.id, missing);
+        Err(RuntimeError::MissingCapabilities { missing: missing })
+    }
+}
+
+/// Parse the conditions of all branches of `xflow` once, so they can be evaluated on
+/// every visit of their branching node
+fn compile_conditions(
+    xflow: &XFlowDocument,
+    state: &XFState,
+) -> Result<HashMap<XFlowEdge, flox::Expr>, RuntimeError> {
+    let mut conditions = HashMap::new();
+    for branch in &xflow.body.branches {
+        if let Some(ref condition) = branch.condition {
+            match flox::compile(condition) {
+                Ok(expr) => {
+                    conditions.insert(branch.edge, expr);
+                }
+                Err(err) => {
+                    return Err(RuntimeError::FloxEvaluation {
+                        node: branch.edge.0,
+                        expression: condition.clone(),
+                        position: err.offset,
+                        message: err.message,
+                        state: state.clone(),
+                    })
+                }
+            }
+        }
+    }
+    Ok(conditions)
+}
+
+/// Parse the expressions of all Flox nodes of `xflow` once, so they can be evaluated on
+/// every visit of their node
+fn compile_expressions(
+    xflow: &XFlowDocument,
+    state: &XFState,
+) -> Result<HashMap<i32, flox::Expr>, RuntimeError> {
+    let mut expressions = HashMap::new();
+    for node in &xflow.body.nodes {
+        if let XFlowNodeParameters::Flox(ref params) = node.parameters {
+            match flox::compile(&params.expression) {
+                Ok(expr) => {
+                    expressions.insert(node.id, expr);
+                }
+                Err(err) => {
+                    return Err(RuntimeError::FloxEvaluation {
+                        node: node.id,
+                        expression: params.expression.clone(),
+                        position: err.offset,
+                        message: err.message,
+                        state: state.clone(),
+                    })
+                }
+            }
         }
     }
+    Ok(expressions)
 }
@@ -1,10 +1,10 @@
 use std::collections::HashMap;
 use std::fmt;
-use crate::structure::xflow::XFlowVariable;
+use crate::structure::xflow::{XFlowValue, XFlowVariable};
 
 pub type XFStore = HashMap<String, XFlowVariable>;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct XFState {
     pub store: XFStore,
 }
@@ -41,6 +41,22 @@ impl XFState {
     pub fn remove(&mut self, name: &str) {
         self.store.remove(name);
     }
+
+    /// Get a value by a dotted path of a variable name followed by record field names
+    /// and list indices, as in `order.items.0.price`
+    pub fn get_path(&self, path: &str) -> Option<&XFlowValue> {
+        let segments: Vec<&str> = path.split('.').collect();
+        self.store.get(segments[0])?.value.get_path(&segments[1..])
+    }
+
+    /// Set a value by a dotted path, as in `order.total`. The variable has to exist.
+    pub fn set_path(&mut self, path: &str, value: XFlowValue) -> Result<(), String> {
+        let segments: Vec<&str> = path.split('.').collect();
+        match self.store.get_mut(segments[0]) {
+            Some(xvar) => xvar.value.set_path(&segments[1..], value),
+            None => Err(format!("Variable '{}' not found in state", segments[0])),
+        }
+    }
 }
 
 impl Default for XFState {
@@ -1,8 +1,13 @@
+use std::error::Error;
+use std::fmt;
+
 use serde;
 use serde_json;
 use serde_yaml;
 use uuid::Uuid;
 
+use crate::structure::diff::{self, DocumentDiff, PatchError};
+use crate::structure::migration::{DocumentBody, MigrationError, Migrations};
 use crate::structure::translation::TranslationDocument;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -17,17 +22,175 @@ pub struct Document<T> {
 
 pub type DocumentList<T> = Vec<Document<T>>;
 
+/// The serialization format of the documents in a project directory
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "yaml")]
+    Yaml,
+}
+
+impl DocumentFormat {
+    pub fn is_json(&self) -> bool {
+        *self == DocumentFormat::Json
+    }
+
+    /// The file extension of documents in this format
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            DocumentFormat::Json => "json",
+            DocumentFormat::Yaml => "yaml",
+        }
+    }
+
+    /// The format of documents with file extension `ext`
+    pub fn from_extension(ext: &str) -> Option<DocumentFormat> {
+        match ext {
+            "json" => Some(DocumentFormat::Json),
+            "yaml" | "yml" => Some(DocumentFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// All file extensions of documents, in order of preference
+    pub fn extensions() -> &'static [&'static str] {
+        &["json", "yaml", "yml"]
+    }
+}
+
+impl Default for DocumentFormat {
+    fn default() -> Self {
+        DocumentFormat::Json
+    }
+}
+
+/// How the documents in a project directory are named
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FileLayout {
+    /// Every document is named by its id
+    #[serde(rename = "id")]
+    Id,
+    /// Every document is named by its slugified name, with a sub-folder for every
+    /// `/`-separated part of its name before the last one
+    #[serde(rename = "name")]
+    Name,
+}
+
+impl FileLayout {
+    pub fn is_id(&self) -> bool {
+        *self == FileLayout::Id
+    }
+}
+
+impl Default for FileLayout {
+    fn default() -> Self {
+        FileLayout::Id
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct DocumentReference {
     pub id: Uuid,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ModelLoadError {
     UnParseable(String),
     BadStructure(String),
+    /// Reading or writing `path` failed
+    Io { path: String, message: String },
+    /// The file at `path` is not a valid document, at `line` and `column`
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// A file required for a model is missing
+    MissingFile(String),
+    /// The documents in `path` and `other` have the same id
+    DuplicateId {
+        path: String,
+        other: String,
+        id: Uuid,
+    },
+    /// The content of the document at `path` does not have the hash it was saved with
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+    /// Migrating the document at `path` to the current version of its doctype failed
+    Migration { path: String, error: MigrationError },
+    /// Several files failed to load or save
+    Multiple(Vec<ModelLoadError>),
 }
 
+impl ModelLoadError {
+    /// Combine the errors of several files into one, `None` when there are none
+    pub fn collect(mut errors: Vec<ModelLoadError>) -> Option<ModelLoadError> {
+        match errors.len() {
+            0 => None,
+            1 => errors.pop(),
+            _ => Some(ModelLoadError::Multiple(errors)),
+        }
+    }
+
+    /// Return the errors this error consists of
+    pub fn errors(&self) -> Vec<&ModelLoadError> {
+        match *self {
+            ModelLoadError::Multiple(ref errors) => errors.iter().flat_map(|e| e.errors()).collect(),
+            _ => vec![self],
+        }
+    }
+}
+
+impl fmt::Display for ModelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModelLoadError::UnParseable(ref message) => write!(f, "Unparseable : {}", message),
+            ModelLoadError::BadStructure(ref message) => write!(f, "Bad structure : {}", message),
+            ModelLoadError::Io {
+                ref path,
+                ref message,
+            } => write!(f, "{} : {}", path, message),
+            ModelLoadError::Parse {
+                ref path,
+                line,
+                column,
+                ref message,
+            } => write!(f, "{}:{}:{} : {}", path, line, column, message),
+            ModelLoadError::MissingFile(ref path) => write!(f, "{} : required file is missing", path),
+            ModelLoadError::DuplicateId {
+                ref path,
+                ref other,
+                ref id,
+            } => write!(f, "{} : id {} is also used by {}", path, id, other),
+            ModelLoadError::ChecksumMismatch {
+                ref path,
+                ref expected,
+                ref found,
+            } => write!(f, "{} : checksum {} does not match {}", path, found, expected),
+            ModelLoadError::Migration {
+                ref path,
+                ref error,
+            } => write!(f, "{} : {}", path, error),
+            ModelLoadError::Multiple(ref errors) => {
+                for (idx, err) in errors.iter().enumerate() {
+                    if idx > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ModelLoadError {}
+
 impl<T> Document<T>
 where
     T: serde::Serialize + serde::de::DeserializeOwned + Eq + Default,
@@ -67,6 +230,61 @@ where
         format!("document {}", self.id)
     }
 
+    /// Replace the body of the Document, incrementing its version when the new body
+    /// differs. Returns whether the body changed.
+    pub fn set_body(&mut self, body: T) -> bool {
+        if self.body == body {
+            return false;
+        }
+        self.body = body;
+        self.version += 1;
+        true
+    }
+
+    /// Set the version of the Document to follow `previous`, the same version when
+    /// only the versions differ and the next one otherwise. Returns whether the
+    /// Document changed.
+    pub fn bump_version_from(&mut self, previous: &Self) -> bool {
+        let changed = self.id != previous.id || self.name != previous.name ||
+            self.doctype != previous.doctype ||
+            self.doctype_version != previous.doctype_version ||
+            self.body != previous.body;
+        self.version = if changed {
+            previous.version + 1
+        } else {
+            previous.version
+        };
+        changed
+    }
+
+    /// Return the structural changes from this version of the Document to `other`
+    pub fn diff(&self, other: &Self) -> DocumentDiff {
+        let old = serde_json::to_value(self).unwrap();
+        let new = serde_json::to_value(other).unwrap();
+        DocumentDiff {
+            from_version: self.version,
+            to_version: other.version,
+            changes: diff::diff_values(&old, &new),
+        }
+    }
+
+    /// Return the Document with `diff` applied, if `diff` was computed from this version
+    pub fn apply_diff(&self, diff: &DocumentDiff) -> Result<Self, PatchError> {
+        if diff.from_version != self.version {
+            return Err(PatchError::Conflict {
+                path: "/version".to_owned(),
+                expected: Some(diff.from_version.into()),
+                found: Some(self.version.into()),
+            });
+        }
+        let mut value = serde_json::to_value(self).unwrap();
+        diff::apply_changes(&mut value, &diff.changes)?;
+        match serde_json::from_value(value) {
+            Ok(res) => Ok(res),
+            Err(err) => Err(PatchError::BadStructure(format!("{}", err))),
+        }
+    }
+
     /// Return a summary of the Document
     ///
     pub fn summary(&self) -> String {
@@ -140,16 +358,17 @@ where
 
 }
 
+/// A new document, at the current version of its doctype
 impl<T> Default for Document<T>
 where
-    T: Default,
+    T: Default + DocumentBody,
 {
     fn default() -> Self {
         Self {
             id: Uuid::new_v4(),
             name: "default".to_owned(),
             doctype: "".to_owned(),
-            doctype_version: 1,
+            doctype_version: Migrations::builtin().current_version(T::doctype()),
             version: 1,
             body: <T>::default(),
         }
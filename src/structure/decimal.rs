@@ -0,0 +1,413 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// The largest number of digits after the decimal point a `Decimal` can hold
+pub const MAX_SCALE: u32 = 28;
+
+/// The number of digits after the decimal point kept when a division does not terminate
+pub const DIVISION_SCALE: u32 = 20;
+
+/// The largest exponent a `Decimal` can be raised to
+pub const MAX_EXPONENT: u32 = 1024;
+
+/// How to round a `Decimal` to fewer digits
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to the nearest neighbour, ties to the even neighbour (banker's rounding)
+    #[serde(rename = "half_even")]
+    HalfEven,
+    /// Round to the nearest neighbour, ties away from zero
+    #[serde(rename = "half_up")]
+    HalfUp,
+    /// Round towards zero
+    #[serde(rename = "down")]
+    Down,
+    /// Round away from zero
+    #[serde(rename = "up")]
+    Up,
+    /// Round towards negative infinity
+    #[serde(rename = "floor")]
+    Floor,
+    /// Round towards positive infinity
+    #[serde(rename = "ceiling")]
+    Ceiling,
+}
+
+/// An exact decimal number, `mantissa * 10^-scale`. Arithmetic is exact, except for
+/// divisions that do not terminate, which are rounded to `DIVISION_SCALE` digits.
+///
+/// Decimals serialize to strings, as in `"12.50"`, to keep their exact value.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+fn pow10(exp: u32) -> Option<i128> {
+    10i128.checked_pow(exp)
+}
+
+impl Decimal {
+    /// Constructs a new `Decimal` with value `mantissa * 10^-scale`, rounded half-even
+    /// to `MAX_SCALE` digits after the decimal point
+    ///
+    /// # Example
+    /// ```
+    /// use gears::structure::decimal::Decimal;
+    /// let price = Decimal::new(1250, 2);
+    /// assert_eq!(price.to_string(), "12.50");
+    /// ```
+    pub fn new(mantissa: i128, scale: u32) -> Self {
+        if scale > MAX_SCALE {
+            Decimal {
+                mantissa: mantissa,
+                scale: scale,
+            }.round(MAX_SCALE, RoundingMode::HalfEven)
+        } else {
+            Decimal {
+                mantissa: mantissa,
+                scale: scale,
+            }
+        }
+    }
+
+    pub fn from_i64(i: i64) -> Self {
+        Decimal::new(i128::from(i), 0)
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    /// Remove trailing zeros after the decimal point
+    pub fn normalize(&self) -> Decimal {
+        let mut res = *self;
+        while res.scale > 0 && res.mantissa % 10 == 0 {
+            res.mantissa /= 10;
+            res.scale -= 1;
+        }
+        res
+    }
+
+    /// Return the same value with at least `scale` digits after the decimal point
+    fn rescale(&self, scale: u32) -> Option<Decimal> {
+        if scale <= self.scale {
+            return Some(*self);
+        }
+        let factor = pow10(scale - self.scale)?;
+        Some(Decimal {
+            mantissa: self.mantissa.checked_mul(factor)?,
+            scale: scale,
+        })
+    }
+
+    /// Round to `scale` digits after the decimal point
+    ///
+    /// # Example
+    /// ```
+    /// use gears::structure::decimal::{Decimal, RoundingMode};
+    /// let d: Decimal = "2.345".parse().unwrap();
+    /// assert_eq!(d.round(2, RoundingMode::HalfEven).to_string(), "2.34");
+    /// assert_eq!(d.round(2, RoundingMode::HalfUp).to_string(), "2.35");
+    /// ```
+    pub fn round(&self, scale: u32, mode: RoundingMode) -> Decimal {
+        if scale >= self.scale {
+            return *self;
+        }
+
+        // A divisor too large for an i128 is larger than any mantissa, so the value
+        // rounds to less than half a unit
+        let (quotient, remainder, half) = match pow10(self.scale - scale) {
+            Some(divisor) => {
+                let remainder = self.mantissa % divisor;
+                let half = remainder.abs().cmp(&(divisor - remainder.abs()));
+                (self.mantissa / divisor, remainder, half)
+            }
+            None => (0, self.mantissa, Ordering::Less),
+        };
+        let sign = if self.mantissa < 0 { -1 } else { 1 };
+
+        let adjust = if remainder == 0 {
+            0
+        } else {
+            match mode {
+                RoundingMode::Down => 0,
+                RoundingMode::Up => sign,
+                RoundingMode::Floor => if sign < 0 { -1 } else { 0 },
+                RoundingMode::Ceiling => if sign > 0 { 1 } else { 0 },
+                RoundingMode::HalfUp => if half != Ordering::Less { sign } else { 0 },
+                RoundingMode::HalfEven => {
+                    match half {
+                        Ordering::Greater => sign,
+                        Ordering::Equal if quotient % 2 != 0 => sign,
+                        _ => 0,
+                    }
+                }
+            }
+        };
+
+        Decimal {
+            mantissa: quotient + adjust,
+            scale: scale,
+        }
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.rescale(scale)?, other.rescale(scale)?);
+        Some(Decimal::new(a.mantissa.checked_add(b.mantissa)?, scale))
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.rescale(scale)?, other.rescale(scale)?);
+        Some(Decimal::new(a.mantissa.checked_sub(b.mantissa)?, scale))
+    }
+
+    pub fn checked_neg(&self) -> Option<Decimal> {
+        Some(Decimal {
+            mantissa: self.mantissa.checked_neg()?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        let a = self.normalize();
+        let b = other.normalize();
+        Some(Decimal::new(
+            a.mantissa.checked_mul(b.mantissa)?,
+            a.scale + b.scale,
+        ))
+    }
+
+    /// Divide, rounding half-even to `DIVISION_SCALE` digits when the result does not
+    /// terminate. Returns `None` on division by zero or overflow.
+    pub fn checked_div(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+
+        let a = self.normalize();
+        let b = other.normalize();
+
+        // Compute a / b at `scale` digits, lowering the scale until the dividend fits
+        let mut scale = DIVISION_SCALE.max(a.scale);
+        loop {
+            let shift = scale + b.scale - a.scale;
+            let dividend = pow10(shift).and_then(|factor| a.mantissa.checked_mul(factor));
+            if let Some(dividend) = dividend {
+                let mut quotient = dividend / b.mantissa;
+                let remainder = (dividend % b.mantissa).abs();
+                let divisor = b.mantissa.abs();
+                let sign = if (dividend < 0) != (b.mantissa < 0) { -1 } else { 1 };
+
+                match remainder.cmp(&(divisor - remainder)) {
+                    Ordering::Greater => quotient += sign,
+                    Ordering::Equal if quotient % 2 != 0 => quotient += sign,
+                    _ => {}
+                }
+
+                return Some(Decimal::new(quotient, scale).normalize());
+            }
+            if scale <= a.scale {
+                return None;
+            }
+            scale -= 1;
+        }
+    }
+
+    /// Raise to a non-negative integer power, by squaring. Returns `None` on overflow
+    /// or when `exp` is larger than `MAX_EXPONENT`.
+    pub fn checked_pow(&self, exp: u32) -> Option<Decimal> {
+        if exp > MAX_EXPONENT {
+            return None;
+        }
+
+        let mut res = Decimal::from_i64(1);
+        let mut base = *self;
+        let mut exp = exp;
+        while exp > 0 {
+            if exp % 2 == 1 {
+                res = res.checked_mul(&base)?;
+            }
+            exp /= 2;
+            if exp > 0 {
+                base = base.checked_mul(&base)?;
+            }
+        }
+        Some(res)
+    }
+
+    /// Convert to an integer, if the decimal has no fractional part
+    pub fn to_i64(&self) -> Option<i64> {
+        let d = self.normalize();
+        if d.scale == 0 && d.mantissa >= i128::from(i64::min_value()) &&
+            d.mantissa <= i128::from(i64::max_value())
+        {
+            Some(d.mantissa as i64)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Decimal::from_i64(i)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        // Compare the integer parts first, so that rescaling can not overflow
+        let (pa, pb) = (pow10(self.scale).unwrap(), pow10(other.scale).unwrap());
+        let (ia, ib) = (self.mantissa / pa, other.mantissa / pb);
+        if ia != ib {
+            return ia.cmp(&ib);
+        }
+
+        let scale = self.scale.max(other.scale);
+        let fa = (self.mantissa % pa) * pow10(scale - self.scale).unwrap();
+        let fb = (other.mantissa % pb) * pow10(scale - other.scale).unwrap();
+        fa.cmp(&fb)
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let d = self.normalize();
+        d.mantissa.hash(state);
+        d.scale.hash(state);
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+
+        if scale == 0 {
+            write!(f, "{}{}", sign, digits)
+        } else if digits.len() > scale {
+            let (int, frac) = digits.split_at(digits.len() - scale);
+            write!(f, "{}{}.{}", sign, int, frac)
+        } else {
+            write!(f, "{}0.{}{}", sign, "0".repeat(scale - digits.len()), digits)
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = if s.starts_with('-') {
+            (true, &s[1..])
+        } else {
+            (false, s)
+        };
+
+        let (int, frac) = match unsigned.find('.') {
+            Some(pos) => (&unsigned[..pos], &unsigned[pos + 1..]),
+            None => (unsigned, ""),
+        };
+
+        if int.is_empty() || !int.chars().all(|c| c.is_ascii_digit()) ||
+            !frac.chars().all(|c| c.is_ascii_digit()) ||
+            (unsigned.contains('.') && frac.is_empty())
+        {
+            return Err(format!("Invalid decimal '{}'", s));
+        }
+
+        if frac.len() > MAX_SCALE as usize {
+            return Err(format!(
+                "Decimal '{}' has more than {} digits after the decimal point",
+                s,
+                MAX_SCALE
+            ));
+        }
+
+        let mantissa = match format!("{}{}", int, frac).parse::<i128>() {
+            Ok(mantissa) => mantissa,
+            Err(_) => return Err(format!("Decimal '{}' is out of range", s)),
+        };
+
+        Ok(Decimal::new(
+            if negative { -mantissa } else { mantissa },
+            frac.len() as u32,
+        ))
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct DecimalVisitor;
+
+impl<'de> Visitor<'de> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal number or a string holding a decimal number")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from_i64(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+        Ok(Decimal::new(i128::from(v), 0))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+        // The shortest representation that round-trips, as written in the document
+        format!("{}", v).parse().map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D>(deserializer: D) -> Result<Decimal, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use serde_json::{self, Map, Value};
+
+/// A change to the value at `path`, a JSON pointer into the serialized document. `old`
+/// is `None` for added values and `new` is `None` for removed values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (&None, &Some(ref new)) => write!(f, "+ {} : {}", self.path, new),
+            (&Some(ref old), &None) => write!(f, "- {} : {}", self.path, old),
+            (&Some(ref old), &Some(ref new)) => write!(f, "~ {} : {} -> {}", self.path, old, new),
+            (&None, &None) => write!(f, "  {}", self.path),
+        }
+    }
+}
+
+/// The changes between two versions of a `Document`, in the order they are applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentDiff {
+    pub from_version: i64,
+    pub to_version: i64,
+    pub changes: Vec<Change>,
+}
+
+impl DocumentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Return an indented JSON representation of the DocumentDiff
+    ///
+    /// partof: SPC-serialization-json
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self).unwrap()
+    }
+}
+
+impl fmt::Display for DocumentDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version {} -> {}", self.from_version, self.to_version)?;
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors raised when applying a `DocumentDiff` as a patch
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+    /// The parent of a changed path does not exist in the patched document
+    PathNotFound(String),
+    /// The value at a changed path is not the old value of the change
+    Conflict {
+        path: String,
+        expected: Option<Value>,
+        found: Option<Value>,
+    },
+    /// The patched value is not a valid document
+    BadStructure(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::PathNotFound(ref path) => write!(f, "Path '{}' not found", path),
+            PatchError::Conflict {
+                ref path,
+                ref expected,
+                ref found,
+            } => {
+                write!(
+                    f,
+                    "Conflict at '{}' : expected {:?}, found {:?}",
+                    path,
+                    expected,
+                    found
+                )
+            }
+            PatchError::BadStructure(ref message) => {
+                write!(f, "Patched document is invalid : {}", message)
+            }
+        }
+    }
+}
+
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Return the changes turning `old` into `new`. Objects are compared by key and arrays
+/// by index, with removed array elements listed from the last one down so the changes
+/// can be applied in order.
+pub fn diff_values(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::<Change>::new();
+    diff_at("", old, new, &mut changes);
+    changes
+}
+
+fn diff_at(path: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) -> () {
+    match (old, new) {
+        (&Value::Object(ref old_map), &Value::Object(ref new_map)) => {
+            for (key, old_value) in old_map {
+                let child = format!("{}/{}", path, escape(key));
+                match new_map.get(key) {
+                    Some(new_value) => diff_at(&child, old_value, new_value, changes),
+                    None => {
+                        changes.push(Change {
+                            path: child,
+                            old: Some(old_value.clone()),
+                            new: None,
+                        })
+                    }
+                }
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    changes.push(Change {
+                        path: format!("{}/{}", path, escape(key)),
+                        old: None,
+                        new: Some(new_value.clone()),
+                    });
+                }
+            }
+        }
+        (&Value::Array(ref old_items), &Value::Array(ref new_items)) => {
+            for (idx, (old_value, new_value)) in old_items.iter().zip(new_items.iter()).enumerate() {
+                diff_at(&format!("{}/{}", path, idx), old_value, new_value, changes);
+            }
+            for idx in (new_items.len()..old_items.len()).rev() {
+                changes.push(Change {
+                    path: format!("{}/{}", path, idx),
+                    old: Some(old_items[idx].clone()),
+                    new: None,
+                });
+            }
+            for (idx, new_value) in new_items.iter().enumerate().skip(old_items.len()) {
+                changes.push(Change {
+                    path: format!("{}/{}", path, idx),
+                    old: None,
+                    new: Some(new_value.clone()),
+                });
+            }
+        }
+        _ => {
+            if old != new {
+                changes.push(Change {
+                    path: path.to_owned(),
+                    old: Some(old.clone()),
+                    new: Some(new.clone()),
+                });
+            }
+        }
+    }
+}
+
+/// Apply `changes` to `value` in order. Every change is checked against the current
+/// value at its path, so a patch only applies to the version it was computed from.
+pub fn apply_changes(value: &mut Value, changes: &[Change]) -> Result<(), PatchError> {
+    for change in changes {
+        apply_change(value, change)?;
+    }
+    Ok(())
+}
+
+fn conflict(change: &Change, found: Option<&Value>) -> PatchError {
+    PatchError::Conflict {
+        path: change.path.clone(),
+        expected: change.old.clone(),
+        found: found.cloned(),
+    }
+}
+
+fn apply_change(value: &mut Value, change: &Change) -> Result<(), PatchError> {
+    if change.path.is_empty() {
+        if change.old.as_ref() != Some(value) {
+            return Err(conflict(change, Some(value)));
+        }
+        return match change.new {
+            Some(ref new) => {
+                *value = new.clone();
+                Ok(())
+            }
+            None => Err(PatchError::PathNotFound(change.path.clone())),
+        };
+    }
+
+    let (parent_path, last) = match change.path.rfind('/') {
+        Some(pos) => (&change.path[..pos], unescape(&change.path[pos + 1..])),
+        None => return Err(PatchError::PathNotFound(change.path.clone())),
+    };
+    let parent = match value.pointer_mut(parent_path) {
+        Some(parent) => parent,
+        None => return Err(PatchError::PathNotFound(change.path.clone())),
+    };
+
+    match *parent {
+        Value::Object(ref mut map) => apply_to_object(map, &last, change),
+        Value::Array(ref mut items) => {
+            match last.parse::<usize>() {
+                Ok(idx) => apply_to_array(items, idx, change),
+                Err(_) => Err(PatchError::PathNotFound(change.path.clone())),
+            }
+        }
+        _ => Err(PatchError::PathNotFound(change.path.clone())),
+    }
+}
+
+fn apply_to_object(map: &mut Map<String, Value>, key: &str, change: &Change) -> Result<(), PatchError> {
+    if map.get(key) != change.old.as_ref() {
+        return Err(conflict(change, map.get(key)));
+    }
+    match change.new {
+        Some(ref new) => {
+            map.insert(key.to_owned(), new.clone());
+        }
+        None => {
+            map.remove(key);
+        }
+    }
+    Ok(())
+}
+
+fn apply_to_array(items: &mut Vec<Value>, idx: usize, change: &Change) -> Result<(), PatchError> {
+    match (&change.old, &change.new) {
+        (&None, &Some(ref new)) => {
+            if idx != items.len() {
+                return Err(conflict(change, items.get(idx)));
+            }
+            items.push(new.clone());
+        }
+        (&Some(_), &None) => {
+            if items.get(idx) != change.old.as_ref() || idx + 1 != items.len() {
+                return Err(conflict(change, items.get(idx)));
+            }
+            items.pop();
+        }
+        (&Some(_), &Some(ref new)) => {
+            if items.get(idx) != change.old.as_ref() {
+                return Err(conflict(change, items.get(idx)));
+            }
+            items[idx] = new.clone();
+        }
+        (&None, &None) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,469 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde;
+use serde_json::{self, Map, Value};
+
+use crate::structure::common::{Document, DocumentHeader, ModelLoadError};
+use crate::structure::model::ModelDocument;
+use crate::util::fs;
+
+/// A change made on both sides of a merge that could not be combined. The merge keeps
+/// `ours` at `path`, a JSON pointer into our version of `document`, a file in the
+/// `util::fs` layout of the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeConflict {
+    pub document: String,
+    pub path: String,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Conflict in {} at '{}'", self.document, self.path)
+    }
+}
+
+/// The outcome of a three-way merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged<T> {
+    pub merged: T,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl<T> Merged<T> {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+struct Merger {
+    document: String,
+    conflicts: Vec<MergeConflict>,
+}
+
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// The field identifying the elements of a list, `id` (xflow nodes, documents) when
+/// every element has one, `name` (entities, attributes, variables) otherwise. Elements
+/// are only matched by this key, so renaming an element is merged as removing it and
+/// adding another one.
+fn list_key(lists: &[&[Value]]) -> Option<&'static str> {
+    for field in &["id", "name"] {
+        let keyed = lists.iter().all(|items| {
+            let mut keys = HashSet::<String>::new();
+            items.iter().all(|item| match item.get(field) {
+                Some(key) if item.is_object() => keys.insert(key.to_string()),
+                _ => false,
+            })
+        });
+        if keyed {
+            return Some(field);
+        }
+    }
+    None
+}
+
+fn find_keyed<'v>(items: &'v [Value], field: &str, key: &Value) -> Option<(usize, &'v Value)> {
+    items.iter().enumerate().find(
+        |&(_, item)| item.get(field) == Some(key),
+    )
+}
+
+impl Merger {
+    fn new(document: &str) -> Self {
+        Merger {
+            document: document.to_owned(),
+            conflicts: Vec::<MergeConflict>::new(),
+        }
+    }
+
+    fn conflict(
+        &mut self,
+        path: &str,
+        base: Option<&Value>,
+        ours: Option<&Value>,
+        theirs: Option<&Value>,
+    ) -> Option<Value> {
+        self.conflicts.push(MergeConflict {
+            document: self.document.clone(),
+            path: path.to_owned(),
+            base: base.cloned(),
+            ours: ours.cloned(),
+            theirs: theirs.cloned(),
+        });
+        ours.cloned()
+    }
+
+    fn merge(
+        &mut self,
+        path: &str,
+        base: Option<&Value>,
+        ours: Option<&Value>,
+        theirs: Option<&Value>,
+    ) -> Option<Value> {
+        if ours == theirs || theirs == base {
+            return ours.cloned();
+        }
+        if ours == base {
+            return theirs.cloned();
+        }
+
+        match (ours, theirs) {
+            (Some(&Value::Object(ref o)), Some(&Value::Object(ref t))) => {
+                let b = base.and_then(|b| b.as_object());
+                Some(Value::Object(self.merge_objects(path, b, o, t)))
+            }
+            (Some(&Value::Array(ref o)), Some(&Value::Array(ref t))) => {
+                let b = base.and_then(|b| b.as_array());
+                match self.merge_arrays(path, b, o, t) {
+                    Some(items) => Some(Value::Array(items)),
+                    None => self.conflict(path, base, ours, theirs),
+                }
+            }
+            _ => self.conflict(path, base, ours, theirs),
+        }
+    }
+
+    fn merge_objects(
+        &mut self,
+        path: &str,
+        base: Option<&Map<String, Value>>,
+        ours: &Map<String, Value>,
+        theirs: &Map<String, Value>,
+    ) -> Map<String, Value> {
+        let mut keys: Vec<&String> = ours.keys().collect();
+        keys.extend(theirs.keys().filter(|key| !ours.contains_key(*key)));
+
+        let mut merged = Map::new();
+        for key in keys {
+            let child = format!("{}/{}", path, escape(key));
+            let base_value = base.and_then(|b| b.get(key));
+            if let Some(value) = self.merge(&child, base_value, ours.get(key), theirs.get(key)) {
+                merged.insert(key.clone(), value);
+            }
+        }
+        merged
+    }
+
+    /// Merge lists by the key of their elements, or as sets when the elements are not
+    /// objects. Returns `None` for lists that can only be merged as a whole.
+    fn merge_arrays(
+        &mut self,
+        path: &str,
+        base: Option<&Vec<Value>>,
+        ours: &[Value],
+        theirs: &[Value],
+    ) -> Option<Vec<Value>> {
+        let base: &[Value] = match base {
+            Some(base) => base,
+            None => &[],
+        };
+
+        if let Some(field) = list_key(&[base, ours, theirs]) {
+            let mut keys: Vec<&Value> = ours.iter().filter_map(|item| item.get(field)).collect();
+            for item in theirs {
+                let key = &item[field];
+                if find_keyed(ours, field, key).is_none() {
+                    keys.push(key);
+                }
+            }
+
+            let mut merged = Vec::<Value>::new();
+            for key in keys {
+                let o = find_keyed(ours, field, key);
+                let t = find_keyed(theirs, field, key);
+                let idx = o.or(t).map(|(idx, _)| idx).unwrap_or(0);
+                let child = format!("{}/{}", path, idx);
+                let b = find_keyed(base, field, key).map(|(_, item)| item);
+                if let Some(value) = self.merge(&child, b, o.map(|(_, item)| item), t.map(|(_, item)| item)) {
+                    merged.push(value);
+                }
+            }
+            Some(merged)
+        } else if ours.iter().chain(theirs.iter()).all(|item| !item.is_object()) {
+            let mut merged: Vec<Value> = ours.iter()
+                .filter(|item| !base.contains(item) || theirs.contains(item))
+                .cloned()
+                .collect();
+            for item in theirs {
+                if !base.contains(item) && !merged.contains(item) {
+                    merged.push(item.clone());
+                }
+            }
+            Some(merged)
+        } else {
+            None
+        }
+    }
+}
+
+fn to_value<T: serde::Serialize>(v: &T) -> Value {
+    serde_json::to_value(v).unwrap()
+}
+
+fn from_value<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, ModelLoadError> {
+    match serde_json::from_value(value) {
+        Ok(res) => Ok(res),
+        Err(err) => Err(ModelLoadError::BadStructure(format!("{}", err))),
+    }
+}
+
+fn without_version(mut value: Value) -> Value {
+    if let Some(map) = value.as_object_mut() {
+        map.remove("version");
+    }
+    value
+}
+
+/// Merge the serialized versions of a document, ignoring their versions
+fn merge_document_values(
+    merger: &mut Merger,
+    base: Option<Value>,
+    ours: Option<Value>,
+    theirs: Option<Value>,
+) -> Option<Value> {
+    let base = base.map(without_version);
+    let ours = ours.map(without_version);
+    let theirs = theirs.map(without_version);
+    merger.merge("", base.as_ref(), ours.as_ref(), theirs.as_ref())
+}
+
+/// The version of `merged`, a merge without version of `ours` and `theirs`: the latest
+/// of both, or the next one when the merge differs from both of them
+fn merged_version(merged: &Value, ours: Value, theirs: Value, versions: (i64, i64)) -> i64 {
+    let latest = ::std::cmp::max(versions.0, versions.1);
+    if without_version(ours) == *merged || without_version(theirs) == *merged {
+        latest
+    } else {
+        latest + 1
+    }
+}
+
+fn merge_optional_document<T>(
+    document: &str,
+    base: Option<&Document<T>>,
+    ours: Option<&Document<T>>,
+    theirs: Option<&Document<T>>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Result<Option<Document<T>>, ModelLoadError>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + Eq + Default,
+{
+    let mut merger = Merger::new(document);
+    let merged = merge_document_values(
+        &mut merger,
+        base.map(to_value),
+        ours.map(to_value),
+        theirs.map(to_value),
+    );
+    conflicts.extend(merger.conflicts);
+
+    let mut merged = match merged {
+        Some(merged) => merged,
+        None => return Ok(None),
+    };
+    let version = match (ours, theirs) {
+        (Some(o), Some(t)) => merged_version(&merged, to_value(o), to_value(t), (o.version, t.version)),
+        (Some(o), None) => o.version,
+        (None, Some(t)) => t.version,
+        (None, None) => 1,
+    };
+    if let Some(map) = merged.as_object_mut() {
+        map.insert("version".to_owned(), version.into());
+    }
+    from_value(merged).map(Some)
+}
+
+/// Three-way merge of two versions of a document changed from a common `base`.
+/// Objects are merged by field, lists by the `id` or `name` of their elements and lists
+/// of plain values as sets. Changes made on one side only are taken, changes made
+/// differently on both sides are reported as conflicts, for which ours is kept.
+///
+/// An element renamed on one side and changed on the other is reported as a conflict,
+/// as it was removed on one side. An element renamed differently on both sides is not:
+/// the merge keeps it under both new names.
+pub fn merge_documents<T>(
+    document: &str,
+    base: &Document<T>,
+    ours: &Document<T>,
+    theirs: &Document<T>,
+) -> Result<Merged<Document<T>>, ModelLoadError>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + Eq + Default,
+{
+    let mut conflicts = Vec::<MergeConflict>::new();
+    match merge_optional_document(document, Some(base), Some(ours), Some(theirs), &mut conflicts)? {
+        Some(merged) => Ok(Merged {
+            merged: merged,
+            conflicts: conflicts,
+        }),
+        None => Err(ModelLoadError::BadStructure(
+            format!("Merge of {} is empty", document),
+        )),
+    }
+}
+
+/// Merge lists of documents identified by `key`. `filenames` returns the files a list of
+/// documents is written to; conflicts are reported against the file of the merged
+/// document, or against the file the document would have next to the merged ones when
+/// the merge removes it.
+fn merge_document_lists<'d, T, K, F>(
+    base: &'d [Document<T>],
+    ours: &'d [Document<T>],
+    theirs: &'d [Document<T>],
+    key: K,
+    filenames: F,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Result<Vec<Document<T>>, ModelLoadError>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + Eq + Default + Clone,
+    K: Fn(&Document<T>) -> String,
+    F: Fn(&[Document<T>]) -> Vec<String>,
+{
+    let mut keys: Vec<String> = ours.iter().map(&key).collect();
+    for doc in theirs {
+        let k = key(doc);
+        if !keys.contains(&k) {
+            keys.push(k);
+        }
+    }
+
+    let find = |docs: &'d [Document<T>], k: &str| -> Option<&'d Document<T>> {
+        docs.iter().find(|doc| key(doc) == k)
+    };
+    // The conflicts of every document, with the index of the merged document or the
+    // document the merge removes
+    let mut merged = Vec::<Document<T>>::new();
+    let mut doc_conflicts = Vec::<(Result<usize, &'d Document<T>>, Vec<MergeConflict>)>::new();
+    for k in keys {
+        let b = find(base, &k);
+        let o = find(ours, &k);
+        let t = find(theirs, &k);
+        let mut found = Vec::<MergeConflict>::new();
+        let doc = match merge_optional_document(&k, b, o, t, &mut found)? {
+            Some(doc) => {
+                merged.push(doc);
+                Ok(merged.len() - 1)
+            }
+            None => Err(o.or(t).or(b).unwrap()),
+        };
+        if !found.is_empty() {
+            doc_conflicts.push((doc, found));
+        }
+    }
+
+    let names = filenames(&merged);
+    for (doc, found) in doc_conflicts {
+        let name = match doc {
+            Ok(index) => names[index].clone(),
+            Err(removed) => {
+                let mut docs = merged.clone();
+                docs.push(removed.clone());
+                filenames(&docs).pop().unwrap_or_default()
+            }
+        };
+        conflicts.extend(found.into_iter().map(|conflict| MergeConflict {
+            document: name.clone(),
+            ..conflict
+        }));
+    }
+    Ok(merged)
+}
+
+/// Three-way merge of a `ModelDocument` and all of its documents. Documents are matched
+/// by id, translations by locale, and conflicts are reported against the files that
+/// `util::fs::model_to_fs` writes the merged model to, in the format and file layout
+/// of its merged config.
+pub fn merge_models(
+    base: &ModelDocument,
+    ours: &ModelDocument,
+    theirs: &ModelDocument,
+) -> Result<Merged<ModelDocument>, ModelLoadError> {
+    let mut conflicts = Vec::<MergeConflict>::new();
+
+    let config = merge_documents("config", &base.body.config, &ours.body.config, &theirs.body.config)?;
+    let format = config.merged.body.format;
+    let layout = config.merged.body.layout;
+    let file = |name: &str| format!("{}.{}", name, format.extension());
+
+    let mut merger = Merger::new(&file("model"));
+    let header = merge_document_values(
+        &mut merger,
+        Some(to_value(&base.get_header())),
+        Some(to_value(&ours.get_header())),
+        Some(to_value(&theirs.get_header())),
+    );
+    conflicts.extend(merger.conflicts);
+    let mut header = header.unwrap_or_else(|| to_value(&ours.get_header()));
+    if let Some(map) = header.as_object_mut() {
+        map.insert("version".to_owned(), ours.version.into());
+    }
+    let header: DocumentHeader = from_value(header)?;
+
+    let mut model = ModelDocument::new_from_header(&header);
+    conflicts.extend(config.conflicts.into_iter().map(|conflict| MergeConflict {
+        document: file("config"),
+        ..conflict
+    }));
+    model.body.config = config.merged;
+    model.body.domain = merge_documents(&file("domain"), &base.body.domain, &ours.body.domain, &theirs.body.domain)
+        .map(|res| {
+            conflicts.extend(res.conflicts);
+            res.merged
+        })?;
+    model.body.xflows = merge_document_lists(
+        &base.body.xflows,
+        &ours.body.xflows,
+        &theirs.body.xflows,
+        |doc| doc.id.to_string(),
+        |docs| {
+            fs::doc_file_names(docs, layout)
+                .iter()
+                .map(|name| file(&format!("xflows/{}", name)))
+                .collect()
+        },
+        &mut conflicts,
+    )?;
+    model.body.pages = merge_document_lists(
+        &base.body.pages,
+        &ours.body.pages,
+        &theirs.body.pages,
+        |doc| doc.id.to_string(),
+        |docs| {
+            fs::doc_file_names(docs, layout)
+                .iter()
+                .map(|name| file(&format!("pages/{}", name)))
+                .collect()
+        },
+        &mut conflicts,
+    )?;
+    model.body.translations = merge_document_lists(
+        &base.body.translations,
+        &ours.body.translations,
+        &theirs.body.translations,
+        |doc| doc.body.locale.clone(),
+        |docs| {
+            docs.iter()
+                .map(|doc| file(&format!("translations/{}", doc.body.locale)))
+                .collect()
+        },
+        &mut conflicts,
+    )?;
+
+    model.version = merged_version(
+        &without_version(to_value(&model)),
+        to_value(ours),
+        to_value(theirs),
+        (ours.version, theirs.version),
+    );
+
+    Ok(Merged {
+        merged: model,
+        conflicts: conflicts,
+    })
+}
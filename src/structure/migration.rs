@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde_json::Value;
+
+use super::domain::Domain;
+use super::model::{Model, ModelConfig};
+use super::page::Page;
+use super::translation::Translation;
+use super::xflow::XFlow;
+
+/// The `doctype_version` of documents of a doctype without migrations
+pub const BASE_DOCTYPE_VERSION: i64 = 1;
+
+/// The body of the documents of a doctype, named as the doctype is in `Migrations`
+pub trait DocumentBody {
+    fn doctype() -> &'static str;
+}
+
+impl DocumentBody for Model {
+    fn doctype() -> &'static str {
+        "model"
+    }
+}
+
+impl DocumentBody for ModelConfig {
+    fn doctype() -> &'static str {
+        "config"
+    }
+}
+
+impl DocumentBody for Domain {
+    fn doctype() -> &'static str {
+        "domain"
+    }
+}
+
+impl DocumentBody for XFlow {
+    fn doctype() -> &'static str {
+        "xflow"
+    }
+}
+
+impl DocumentBody for Page {
+    fn doctype() -> &'static str {
+        "page"
+    }
+}
+
+impl DocumentBody for Translation {
+    fn doctype() -> &'static str {
+        "translation"
+    }
+}
+
+/// Rewrite the raw JSON of a document from one `doctype_version` to the next
+pub type Upgrade = Box<dyn Fn(&mut Value) -> Result<(), String> + Send + Sync>;
+
+struct Migration {
+    description: String,
+    upgrade: Upgrade,
+}
+
+/// A migration applied, or to be applied, to a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationStep {
+    pub doctype: String,
+    pub from_version: i64,
+    pub to_version: i64,
+    pub description: String,
+}
+
+impl fmt::Display for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} -> {} : {}",
+            self.doctype,
+            self.from_version,
+            self.to_version,
+            self.description
+        )
+    }
+}
+
+/// The migrations applied, or to be applied, to the document in file `document`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentMigration {
+    pub document: String,
+    pub steps: Vec<MigrationStep>,
+}
+
+/// The migrations of all documents of a model that are not at the current version of
+/// their doctype
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub documents: Vec<DocumentMigration>,
+}
+
+impl MigrationReport {
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for migration in &self.documents {
+            writeln!(f, "{}", migration.document)?;
+            for step in &migration.steps {
+                writeln!(f, "  {}", step)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors raised when migrating a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MigrationError {
+    /// The document has no integer `doctype_version`
+    MissingVersion(String),
+    /// The document is newer than the current version of its doctype
+    TooNew {
+        doctype: String,
+        version: i64,
+        current: i64,
+    },
+    /// There is no migration from `version` of the doctype
+    MissingStep { doctype: String, version: i64 },
+    /// The migration from `version` of the doctype failed
+    Failed {
+        doctype: String,
+        version: i64,
+        message: String,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrationError::MissingVersion(ref doctype) => {
+                write!(f, "{} document has no doctype_version", doctype)
+            }
+            MigrationError::TooNew {
+                ref doctype,
+                version,
+                current,
+            } => {
+                write!(
+                    f,
+                    "{} version {} is newer than the supported version {}",
+                    doctype,
+                    version,
+                    current
+                )
+            }
+            MigrationError::MissingStep {
+                ref doctype,
+                version,
+            } => write!(f, "No migration for {} version {}", doctype, version),
+            MigrationError::Failed {
+                ref doctype,
+                version,
+                ref message,
+            } => write!(f, "Migration of {} version {} failed : {}", doctype, version, message),
+        }
+    }
+}
+
+/// A registry of the migrations of every doctype, each rewriting documents from one
+/// `doctype_version` to the next before they are deserialized
+///
+/// ```
+/// use gears::structure::migration::Migrations;
+///
+/// let mut migrations = Migrations::new();
+/// migrations.register("page", 1, "Rename 'title' to 'name'", |value| {
+///     let title = value["body"].as_object_mut().and_then(|body| body.remove("title"));
+///     if let Some(title) = title {
+///         value["body"]["name"] = title;
+///     }
+///     Ok(())
+/// });
+/// assert_eq!(migrations.current_version("page"), 2);
+/// ```
+#[derive(Default)]
+pub struct Migrations {
+    doctypes: HashMap<String, BTreeMap<i64, Migration>>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut doctypes: Vec<&String> = self.doctypes.keys().collect();
+        doctypes.sort();
+        write!(f, "Migrations {{ ")?;
+        for doctype in doctypes {
+            write!(f, "{}: {} ", doctype, self.current_version(doctype))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl Migrations {
+    /// An empty registry, in which every doctype is at `BASE_DOCTYPE_VERSION`
+    pub fn new() -> Self {
+        Migrations::default()
+    }
+
+    /// The migrations of the document structures of this version of gears, used when
+    /// loading models with `util::fs::model_from_fs` and `util::bundle::model_from_bundle`,
+    /// and to stamp new documents with the current version of their doctype. A
+    /// structural change to a doctype registers the migration from its previous version
+    /// here.
+    pub fn builtin() -> Self {
+        Migrations::new()
+    }
+
+    /// Register `upgrade` as the migration of `doctype` documents from `from_version`
+    /// to the next version, replacing an earlier one
+    pub fn register<F>(&mut self, doctype: &str, from_version: i64, description: &str, upgrade: F) -> &mut Self
+    where
+        F: Fn(&mut Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.doctypes.entry(doctype.to_owned()).or_default().insert(
+            from_version,
+            Migration {
+                description: description.to_owned(),
+                upgrade: Box::new(upgrade),
+            },
+        );
+        self
+    }
+
+    /// The version of `doctype` documents after all its migrations
+    pub fn current_version(&self, doctype: &str) -> i64 {
+        self.doctypes
+            .get(doctype)
+            .and_then(|steps| steps.keys().next_back())
+            .map(|version| version + 1)
+            .unwrap_or(BASE_DOCTYPE_VERSION)
+    }
+
+    fn version_of(doctype: &str, value: &Value) -> Result<i64, MigrationError> {
+        match value.get("doctype_version").and_then(|version| version.as_i64()) {
+            Some(version) => Ok(version),
+            None => Err(MigrationError::MissingVersion(doctype.to_owned())),
+        }
+    }
+
+    fn step(&self, doctype: &str, version: i64) -> Result<(&Migration, MigrationStep), MigrationError> {
+        match self.doctypes.get(doctype).and_then(|steps| steps.get(&version)) {
+            Some(migration) => {
+                Ok((
+                    migration,
+                    MigrationStep {
+                        doctype: doctype.to_owned(),
+                        from_version: version,
+                        to_version: version + 1,
+                        description: migration.description.clone(),
+                    },
+                ))
+            }
+            None => {
+                Err(MigrationError::MissingStep {
+                    doctype: doctype.to_owned(),
+                    version: version,
+                })
+            }
+        }
+    }
+
+    /// Return the migrations that `migrate` would apply to `value`, a `doctype` document
+    pub fn plan(&self, doctype: &str, value: &Value) -> Result<Vec<MigrationStep>, MigrationError> {
+        let version = Migrations::version_of(doctype, value)?;
+        let current = self.current_version(doctype);
+        if version > current {
+            return Err(MigrationError::TooNew {
+                doctype: doctype.to_owned(),
+                version: version,
+                current: current,
+            });
+        }
+        (version..current)
+            .map(|version| self.step(doctype, version).map(|(_, step)| step))
+            .collect()
+    }
+
+    /// Migrate `value`, a `doctype` document, to the current version of its doctype and
+    /// return the migrations applied. Each migration is followed by setting the
+    /// `doctype_version` of the document to its next version.
+    pub fn migrate(&self, doctype: &str, value: &mut Value) -> Result<Vec<MigrationStep>, MigrationError> {
+        let steps = self.plan(doctype, value)?;
+        for step in &steps {
+            let (migration, _) = self.step(doctype, step.from_version)?;
+            let res = (migration.upgrade)(value).and_then(|_| match value.as_object_mut() {
+                Some(map) => {
+                    map.insert("doctype_version".to_owned(), step.to_version.into());
+                    Ok(())
+                }
+                None => Err("Migrated document is not an object".to_owned()),
+            });
+            if let Err(message) = res {
+                return Err(MigrationError::Failed {
+                    doctype: doctype.to_owned(),
+                    version: step.from_version,
+                    message: message,
+                });
+            }
+        }
+        Ok(steps)
+    }
+}
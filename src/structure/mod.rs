@@ -1,6 +1,10 @@
 pub mod common;
+pub mod diff;
+pub mod merge;
+pub mod migration;
 pub mod model;
 pub mod domain;
 pub mod page;
 pub mod xflow;
+pub mod decimal;
 pub mod translation;
@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
-use super::common::{Document, Translatable, I18NString};
+use super::common::{Document, DocumentFormat, FileLayout, Translatable, I18NString};
 use super::domain::DomainDocument;
 use super::xflow::{XFlowDocument, XFlowDocumentList};
 use super::page::{PageDocument, PageDocumentList};
@@ -232,6 +232,12 @@ pub struct ModelConfig {
     pub default_locale: String,
     pub active_locale: String,
     pub locales: Vec<String>,
+    /// Format of the documents written by `util::fs::model_to_fs`
+    #[serde(default, skip_serializing_if = "DocumentFormat::is_json")]
+    pub format: DocumentFormat,
+    /// Naming of the xflow and page files written by `util::fs::model_to_fs`
+    #[serde(default, skip_serializing_if = "FileLayout::is_id")]
+    pub layout: FileLayout,
 }
 
 impl Default for ModelConfig {
@@ -240,6 +246,8 @@ impl Default for ModelConfig {
             default_locale: "en_US".to_owned(),
             active_locale: "en_US".to_owned(),
             locales: Vec::<String>::new(),
+            format: DocumentFormat::default(),
+            layout: FileLayout::default(),
         }
     }
 }
@@ -1,12 +1,25 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use serde::de::{Deserialize, Deserializer, Error, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeStruct, Serializer};
+use serde_json;
+use uuid::Uuid;
 
 use super::common::{Document, DocumentList};
+use super::decimal::Decimal;
 
 pub type XFlowDocument = Document<XFlow>;
 pub type XFlowDocumentList = DocumentList<XFlow>;
 
 pub type XFlowEdge = (i32, i32);
 
+/// A `(from, to)` pair of variable names, used to pass variables between flows
+pub type XFlowVariableMatch = (String, String);
+
+/// Name of the record variable that holds the failure of a node while the flow handles
+/// it, with the fields `node`, `kind` and `message`
+pub const ERROR_VARIABLE: &str = "Error";
+
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 // partof: SPC-serialization-json
 pub struct XFlow {
@@ -15,6 +28,12 @@ pub struct XFlow {
     pub nodes: Vec<XFlowNode>,
     pub edges: Vec<XFlowEdge>,
     pub branches: Vec<XFlowBranch>,
+    /// Edges taken instead of the regular ones when the node they start at fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_edges: Vec<XFlowEdge>,
+    /// Id of the node that handles the failures of nodes without an error edge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_handler: Option<i32>,
 }
 
 #[derive(Debug)]
@@ -35,21 +54,222 @@ pub enum XFlowValueType {
     Integer,
     #[serde(rename = "boolean")]
     Boolean,
+    #[serde(rename = "decimal")]
+    Decimal,
+    /// A list of values of any type
+    #[serde(rename = "list")]
+    List,
+    /// A record of named values of any type, such as a domain entity instance
+    #[serde(rename = "record")]
+    Record,
+    /// A value whose type is only known at runtime, such as a list element
+    #[serde(rename = "any")]
+    Any,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
-#[serde(untagged)]
+impl XFlowValueType {
+    /// Check whether a value of type `other` can be stored in a variable of this type.
+    /// Integers are accepted where decimals are expected, and values of type `Any` are
+    /// accepted anywhere, as their type can only be checked at runtime.
+    pub fn accepts(&self, other: &XFlowValueType) -> bool {
+        self == other || *self == XFlowValueType::Any || *other == XFlowValueType::Any ||
+            (*self == XFlowValueType::Decimal && *other == XFlowValueType::Integer)
+    }
+}
+
+pub type XFlowRecord = BTreeMap<String, XFlowValue>;
+
+/// The key of the single-field object a decimal serializes to, as in
+/// `{"$decimal": "12.50"}`
+pub const DECIMAL_TAG: &str = "$decimal";
+
+/// A value of a variable. Decimals serialize to tagged strings, so that they keep their
+/// type inside lists and records, which do not declare the types of their elements.
+/// Variables declared as decimals hold their value as a plain string.
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum XFlowValue {
     String(String),
     Integer(i64),
     Boolean(bool),
+    List(Vec<XFlowValue>),
+    Record(XFlowRecord),
+    Decimal(Decimal),
+}
+
+impl Serialize for XFlowValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            XFlowValue::String(ref s) => serializer.serialize_str(s),
+            XFlowValue::Integer(i) => serializer.serialize_i64(i),
+            XFlowValue::Boolean(b) => serializer.serialize_bool(b),
+            XFlowValue::List(ref items) => items.serialize(serializer),
+            XFlowValue::Record(ref fields) => fields.serialize(serializer),
+            XFlowValue::Decimal(ref d) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(DECIMAL_TAG, d)?;
+                map.end()
+            }
+        }
+    }
+}
+
+struct XFlowValueVisitor;
+
+impl<'de> Visitor<'de> for XFlowValueVisitor {
+    type Value = XFlowValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string, number, boolean, list or record")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<XFlowValue, E> {
+        Ok(XFlowValue::Boolean(v))
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<XFlowValue, E> {
+        Ok(XFlowValue::Integer(v))
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<XFlowValue, E> {
+        if v <= i64::max_value() as u64 {
+            Ok(XFlowValue::Integer(v as i64))
+        } else {
+            Ok(XFlowValue::Decimal(Decimal::new(i128::from(v), 0)))
+        }
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<XFlowValue, E> {
+        // The shortest representation that round-trips, as written in the document
+        format!("{}", v).parse().map(XFlowValue::Decimal).map_err(E::custom)
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<XFlowValue, E> {
+        Ok(XFlowValue::String(v.to_owned()))
+    }
+
+    fn visit_string<E: Error>(self, v: String) -> Result<XFlowValue, E> {
+        Ok(XFlowValue::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<XFlowValue, A::Error> {
+        let mut items = Vec::<XFlowValue>::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(XFlowValue::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<XFlowValue, A::Error> {
+        let mut fields = XFlowRecord::new();
+        while let Some((name, value)) = map.next_entry::<String, XFlowValue>()? {
+            fields.insert(name, value);
+        }
+
+        if fields.len() == 1 {
+            if let Some(&XFlowValue::String(ref d)) = fields.get(DECIMAL_TAG) {
+                return d.parse().map(XFlowValue::Decimal).map_err(A::Error::custom);
+            }
+        }
+        Ok(XFlowValue::Record(fields))
+    }
+}
+
+impl<'de> Deserialize<'de> for XFlowValue {
+    fn deserialize<D>(deserializer: D) -> Result<XFlowValue, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(XFlowValueVisitor)
+    }
 }
 
 impl XFlowValue {
+    /// Convert the value to `vtype` where that can be done without loss. Decimals are
+    /// read from integers and from strings, as decimals serialize to strings.
+    pub fn coerce(self, vtype: &XFlowValueType) -> Result<XFlowValue, String> {
+        match (vtype, self) {
+            (&XFlowValueType::Decimal, XFlowValue::Integer(i)) => {
+                Ok(XFlowValue::Decimal(Decimal::from_i64(i)))
+            }
+            (&XFlowValueType::Decimal, XFlowValue::String(s)) => {
+                s.parse().map(XFlowValue::Decimal)
+            }
+            (_, value) => Ok(value),
+        }
+    }
+
+    /// The type of the value
+    pub fn vtype(&self) -> XFlowValueType {
+        match *self {
+            XFlowValue::String(_) => XFlowValueType::String,
+            XFlowValue::Integer(_) => XFlowValueType::Integer,
+            XFlowValue::Boolean(_) => XFlowValueType::Boolean,
+            XFlowValue::List(_) => XFlowValueType::List,
+            XFlowValue::Record(_) => XFlowValueType::Record,
+            XFlowValue::Decimal(_) => XFlowValueType::Decimal,
+        }
+    }
+
+    /// Get the value at `path` inside this value, a list of record field names and
+    /// list indices
+    pub fn get_path(&self, path: &[&str]) -> Option<&XFlowValue> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => {
+                match *self {
+                    XFlowValue::Record(ref fields) => fields.get(*first)?.get_path(rest),
+                    XFlowValue::List(ref items) => {
+                        items.get(first.parse::<usize>().ok()?)?.get_path(rest)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Set the value at `path` inside this value. Missing record fields are created,
+    /// list elements have to exist.
+    pub fn set_path(&mut self, path: &[&str], value: XFlowValue) -> Result<(), String> {
+        let (first, rest) = match path.split_first() {
+            None => {
+                *self = value;
+                return Ok(());
+            }
+            Some(split) => split,
+        };
+
+        match *self {
+            XFlowValue::Record(ref mut fields) => {
+                if rest.is_empty() {
+                    fields.insert((*first).to_owned(), value);
+                    Ok(())
+                } else {
+                    match fields.get_mut(*first) {
+                        Some(field) => field.set_path(rest, value),
+                        None => Err(format!("Field '{}' not found", first)),
+                    }
+                }
+            }
+            XFlowValue::List(ref mut items) => {
+                let len = items.len();
+                match first.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                    Some(item) => item.set_path(rest, value),
+                    None => Err(format!("Index '{}' out of range for a list of {}", first, len)),
+                }
+            }
+            ref other => Err(format!("Can not set '{}' in {:?}", first, other)),
+        }
+    }
+
     pub fn string_value(&self) -> String {
         match *self {
             XFlowValue::String(ref s) => s.clone(),
             XFlowValue::Integer(ref i) => i.to_string(),
+            XFlowValue::Decimal(ref d) => d.to_string(),
+            XFlowValue::List(_) | XFlowValue::Record(_) => serde_json::to_string(self).unwrap(),
             XFlowValue::Boolean(ref b) => {
                 if *b {
                     "true".to_owned()
@@ -72,13 +292,54 @@ pub struct XFlowVariableDefinition {
     pub vtype: XFlowValueType,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct XFlowVariable {
     pub name: String,
     pub vtype: XFlowValueType,
     pub value: XFlowValue,
 }
 
+impl Serialize for XFlowVariable {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("XFlowVariable", 3)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("vtype", &self.vtype)?;
+        match (&self.vtype, &self.value) {
+            // The declared type restores the decimal, see `XFlowValue::coerce`
+            (&XFlowValueType::Decimal, &XFlowValue::Decimal(ref d)) => {
+                state.serialize_field("value", d)?
+            }
+            (_, value) => state.serialize_field("value", value)?,
+        }
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct XFlowVariableFields {
+    name: String,
+    vtype: XFlowValueType,
+    value: XFlowValue,
+}
+
+impl<'de> Deserialize<'de> for XFlowVariable {
+    fn deserialize<D>(deserializer: D) -> Result<XFlowVariable, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let fields = XFlowVariableFields::deserialize(deserializer)?;
+        let value = fields.value.coerce(&fields.vtype).map_err(D::Error::custom)?;
+        Ok(XFlowVariable {
+            name: fields.name,
+            vtype: fields.vtype,
+            value: value,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct XFlowVariables {
     pub input: Vec<XFlowVariableDefinition>,
@@ -95,14 +356,57 @@ pub struct XFlowNode {
     pub parameters: XFlowNodeParameters,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Hash, Eq)]
+#[derive(Debug, PartialEq, Clone, Hash, Eq)]
 pub enum XFlowNodeType {
-    #[serde(rename = "flow")]
     Flow,
-    #[serde(rename = "flox")]
     Flox,
-    #[serde(rename = "call")]
     Call,
+    /// A node type provided by a receiver registered outside of this crate. It is
+    /// serialized as its name, which must not be one of the built in node types.
+    Custom(String),
+}
+
+impl XFlowNodeType {
+    /// The name of the node type, as used in documents
+    pub fn as_str(&self) -> &str {
+        match *self {
+            XFlowNodeType::Flow => "flow",
+            XFlowNodeType::Flox => "flox",
+            XFlowNodeType::Call => "call",
+            XFlowNodeType::Custom(ref name) => name,
+        }
+    }
+}
+
+impl fmt::Display for XFlowNodeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for XFlowNodeType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for XFlowNodeType {
+    fn deserialize<D>(deserializer: D) -> Result<XFlowNodeType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        Ok(match name.as_ref() {
+            "flow" => XFlowNodeType::Flow,
+            "flox" => XFlowNodeType::Flox,
+            "call" => XFlowNodeType::Call,
+            "" => return Err(D::Error::custom("Empty node type")),
+            _ => XFlowNodeType::Custom(name),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -113,6 +417,13 @@ pub enum XFlowNodeParameters {
     Flox(FloxParameters),
     #[serde(rename = "call")]
     Call(CallParameters),
+    #[serde(rename = "foreach")]
+    Foreach(ForeachParameters),
+    #[serde(rename = "fork")]
+    Fork(ForkParameters),
+    /// Parameters of a node of a custom node type, passed as is to its receiver
+    #[serde(rename = "custom")]
+    Custom(serde_json::Value),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -131,12 +442,93 @@ pub struct FloxParameters {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-pub struct CallParameters {}
+pub struct CallParameters {
+    /// Id of the `XFlowDocument` to call
+    pub xflowid: Uuid,
+    /// Variables in the calling flow matched to input variables of the called flow
+    pub inputmatch: Vec<XFlowVariableMatch>,
+    /// Output variables of the called flow matched to variables in the calling flow
+    pub outputmatch: Vec<XFlowVariableMatch>,
+}
+
+/// Parameters of a `flow` node with action `foreach`, which runs a loop body once per
+/// element of a list. The body starts at node `body` and has an edge back to the
+/// `foreach` node. The other edge out of the `foreach` node is taken once every element
+/// has been visited.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ForeachParameters {
+    /// Name of the list variable to iterate over
+    pub list: String,
+    /// Name of the variable holding the current element
+    pub element: String,
+    /// Name of the variable holding the index of the current element. It is removed from
+    /// the state when the loop ends.
+    pub index: String,
+    /// Variable to accumulate a result in, set to its initial value when the loop starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accumulator: Option<XFlowVariable>,
+    /// Id of the first node of the loop body
+    pub body: i32,
+}
 
+impl ForeachParameters {
+    /// The variables introduced by the loop, with their types
+    pub fn variable_types(&self) -> Vec<(String, XFlowValueType)> {
+        let mut types = vec![
+            (self.element.clone(), XFlowValueType::Any),
+            (self.index.clone(), XFlowValueType::Integer),
+        ];
+        if let Some(ref accumulator) = self.accumulator {
+            types.push((accumulator.name.clone(), accumulator.vtype.clone()));
+        }
+        types
+    }
+}
+
+/// How the changes that the branches of a fork make to the state are merged at the join
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XFlowMergePolicy {
+    /// Two branches changing the same variable to different values is an error
+    #[serde(rename = "fail")]
+    Fail,
+    /// The change made by the branch whose edge comes last in the edge list wins
+    #[serde(rename = "last_wins")]
+    LastWins,
+}
+
+impl Default for XFlowMergePolicy {
+    fn default() -> Self {
+        XFlowMergePolicy::Fail
+    }
+}
+
+/// Parameters of a `flow` node with action `fork`. Every edge out of the node starts a
+/// branch, and all branches run concurrently until they reach the `join` node, where
+/// their changes to the state are merged following `conflicts`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ForkParameters {
+    /// Id of the `join` node the branches end at
+    pub join: i32,
+    #[serde(default)]
+    pub conflicts: XFlowMergePolicy,
+}
+
+/// A conditional edge out of a branching node. A branch is taken when its Flox
+/// `condition` evaluates to `true`, or when the state holds a variable equal to `xvar`.
+/// A `default` branch is taken when no other branch of the node matches.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct XFlowBranch {
     pub edge: XFlowEdge,
-    pub xvar: XFlowVariable,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xvar: Option<XFlowVariable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub default: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 impl XFlow {
@@ -246,6 +638,17 @@ impl XFlow {
             .collect()
     }
 
+    /// Get the error edge out of the node with id `id`, if it has one
+    pub fn get_error_edge(&self, id: i32) -> Option<&XFlowEdge> {
+        self.error_edges.iter().find(|edge| edge.0 == id)
+    }
+
+    /// Check whether the flow handles failing nodes, through error edges or an error
+    /// handler
+    pub fn handles_errors(&self) -> bool {
+        !self.error_edges.is_empty() || self.error_handler.is_some()
+    }
+
     pub fn get_entry_node(&self) -> Result<&XFlowNode, XFlowError> {
         let res = self.get_nodes_by(&XFlowNodeType::Flow, "start");
         match res.len() {
@@ -303,8 +706,80 @@ impl XFlow {
             }
         }
 
+        for params in self.get_foreach_parameters() {
+            for (name, _) in params.variable_types() {
+                names.insert(name);
+            }
+        }
+
+        if self.handles_errors() {
+            names.insert(ERROR_VARIABLE.to_owned());
+        }
+
         names
     }
+
+    /// Return the nodes of the branches of `fork`, up to its join node, grouped by the
+    /// edge that starts the branch
+    pub fn get_fork_regions(&self, fork: &XFlowNode, join: i32) -> Vec<(XFlowEdge, Vec<i32>)> {
+        self.get_out_edges(fork)
+            .into_iter()
+            .map(|edge| {
+                let mut region = Vec::<i32>::new();
+                let mut pending = vec![edge.1];
+                while let Some(id) = pending.pop() {
+                    if id == join || id == fork.id || region.contains(&id) {
+                        continue;
+                    }
+                    region.push(id);
+                    pending.extend(self.edges.iter().filter(|e| e.0 == id).map(|e| e.1));
+                }
+                (*edge, region)
+            })
+            .collect()
+    }
+
+    /// Return the parameters of all `foreach` nodes
+    pub fn get_foreach_parameters(&self) -> Vec<&ForeachParameters> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node.parameters {
+                XFlowNodeParameters::Foreach(ref params) => Some(params),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Return the declared type of every variable of the flow, including the variables
+    /// of `foreach` loops and the error variable. A variable declared in more than one
+    /// scope takes its input, then local, then output type.
+    pub fn get_variable_types(&self) -> HashMap<String, XFlowValueType> {
+        let mut types = HashMap::<String, XFlowValueType>::new();
+
+        for xvar in &self.variables.input {
+            types.entry(xvar.name.clone()).or_insert_with(|| xvar.vtype.clone());
+        }
+
+        for xvar in &self.variables.local {
+            types.entry(xvar.name.clone()).or_insert_with(|| xvar.vtype.clone());
+        }
+
+        for xvar in &self.variables.output {
+            types.entry(xvar.name.clone()).or_insert_with(|| xvar.vtype.clone());
+        }
+
+        for params in self.get_foreach_parameters() {
+            for (name, vtype) in params.variable_types() {
+                types.entry(name).or_insert(vtype);
+            }
+        }
+
+        if self.handles_errors() {
+            types.entry(ERROR_VARIABLE.to_owned()).or_insert(XFlowValueType::Record);
+        }
+
+        types
+    }
 }
 
 impl Default for XFlow {
@@ -353,6 +828,8 @@ impl Default for XFlow {
             nodes: nodes,
             edges: edges,
             branches: Vec::<XFlowBranch>::new(),
+            error_edges: Vec::<XFlowEdge>::new(),
+            error_handler: None,
         }
     }
 }
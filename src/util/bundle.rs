@@ -0,0 +1,371 @@
+use crate::structure::common::{DocumentHeader, ModelLoadError};
+use crate::structure::migration::{DocumentMigration, MigrationReport, Migrations};
+use crate::structure::model::ModelDocument;
+use crate::util::fs::{read_json_file, write_file};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std;
+
+/// The version of the bundle format written by `model_to_bundle`
+pub const BUNDLE_FORMAT_VERSION: i64 = 1;
+
+/// A document in a bundle, with the path it has in a model directory and the SHA-256
+/// hash of its content
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub path: String,
+    pub id: Uuid,
+    pub doctype: String,
+    pub version: i64,
+    pub sha256: String,
+}
+
+/// The header of a model and the list of its documents in a bundle
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BundleManifest {
+    pub format_version: i64,
+    pub model: DocumentHeader,
+    pub entries: Vec<BundleEntry>,
+}
+
+/// A `ModelDocument` packed into a single JSON document, with its documents keyed by
+/// the path of their entry in the manifest
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ModelBundle {
+    pub manifest: BundleManifest,
+    pub documents: BTreeMap<String, Value>,
+}
+
+impl ModelBundle {
+    /// Return an indented JSON representation of the ModelBundle
+    ///
+    /// partof: SPC-serialization-json
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self).unwrap()
+    }
+}
+
+/// Return the SHA-256 hash of the compact JSON serialization of `value`, in hex. Object
+/// keys are serialized sorted, so the hash does not depend on how the document was
+/// written.
+pub fn content_hash(value: &Value) -> String {
+    let data = serde_json::to_string(value).unwrap();
+    Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn add_document<T: Serialize>(
+    bundle: &mut ModelBundle,
+    path: String,
+    header: DocumentHeader,
+    doc: &T,
+) -> () {
+    let value = serde_json::to_value(doc).unwrap();
+    bundle.manifest.entries.push(BundleEntry {
+        path: path.clone(),
+        id: header.id,
+        doctype: header.doctype,
+        version: header.version,
+        sha256: content_hash(&value),
+    });
+    bundle.documents.insert(path, value);
+}
+
+/// Pack `model` into a bundle. Documents are named as in the model directory written
+/// by `util::fs::model_to_fs` with the id file layout.
+pub fn model_to_bundle(model: &ModelDocument) -> ModelBundle {
+    let mut bundle = ModelBundle {
+        manifest: BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            model: model.get_header(),
+            entries: Vec::new(),
+        },
+        documents: BTreeMap::new(),
+    };
+
+    let config = &model.body.config;
+    add_document(&mut bundle, "config.json".to_owned(), config.get_header(), config);
+    let domain = &model.body.domain;
+    add_document(&mut bundle, "domain.json".to_owned(), domain.get_header(), domain);
+    for doc in &model.body.xflows {
+        add_document(&mut bundle, format!("xflows/{}.json", doc.id), doc.get_header(), doc);
+    }
+    for doc in &model.body.pages {
+        add_document(&mut bundle, format!("pages/{}.json", doc.id), doc.get_header(), doc);
+    }
+    for doc in &model.body.translations {
+        let path = format!("translations/{}.json", doc.body.locale);
+        add_document(&mut bundle, path, doc.get_header(), doc);
+    }
+
+    bundle
+}
+
+/// Migrate `value`, the `doctype` document at `path`, to the current version of its
+/// doctype, adding the migrations applied to `report`
+fn migrate_value(
+    path: &str,
+    doctype: &str,
+    value: &mut Value,
+    migrations: &Migrations,
+    report: &mut MigrationReport,
+) -> Result<(), ModelLoadError> {
+    let steps = migrations.migrate(doctype, value).map_err(|err| {
+        ModelLoadError::Migration {
+            path: path.to_owned(),
+            error: err,
+        }
+    })?;
+    if !steps.is_empty() {
+        debug!("Migrated {} : {:?}", path, steps);
+        report.documents.push(DocumentMigration {
+            document: path.to_owned(),
+            steps: steps,
+        });
+    }
+    Ok(())
+}
+
+/// Return the `doctype` document of `entry`, checked against the hash, id and version
+/// in the manifest and migrated with `migrations`, keeping the error in `errors` when
+/// it fails
+fn read_entry<T: DeserializeOwned>(
+    bundle: &ModelBundle,
+    entry: &BundleEntry,
+    doctype: &str,
+    migrations: &Migrations,
+    report: &mut MigrationReport,
+    errors: &mut Vec<ModelLoadError>,
+) -> Option<T> {
+    let value = match bundle.documents.get(&entry.path) {
+        Some(value) => value,
+        None => {
+            errors.push(ModelLoadError::MissingFile(entry.path.clone()));
+            return None;
+        }
+    };
+
+    let found = content_hash(value);
+    if found != entry.sha256 {
+        errors.push(ModelLoadError::ChecksumMismatch {
+            path: entry.path.clone(),
+            expected: entry.sha256.clone(),
+            found: found,
+        });
+        return None;
+    }
+
+    let header: DocumentHeader = match serde_json::from_value(value.clone()) {
+        Ok(header) => header,
+        Err(err) => {
+            errors.push(ModelLoadError::BadStructure(format!("{} : {}", entry.path, err)));
+            return None;
+        }
+    };
+    if header.id != entry.id || header.version != entry.version || header.doctype != entry.doctype {
+        errors.push(ModelLoadError::BadStructure(format!(
+            "{} : document {} version {} does not match the manifest",
+            entry.path,
+            header.id,
+            header.version
+        )));
+        return None;
+    }
+
+    let mut value = value.clone();
+    if let Err(err) = migrate_value(&entry.path, doctype, &mut value, migrations, report) {
+        errors.push(err);
+        return None;
+    }
+
+    match serde_json::from_value(value) {
+        Ok(doc) => Some(doc),
+        Err(err) => {
+            errors.push(ModelLoadError::BadStructure(format!("{} : {}", entry.path, err)));
+            None
+        }
+    }
+}
+
+/// Return the `doctype` documents of the entries under `dir`, keeping the errors of the
+/// documents that fail to load, or that have the id of a document loaded before, in
+/// `errors`
+fn read_entries<T: DeserializeOwned>(
+    bundle: &ModelBundle,
+    dir: &str,
+    doctype: &str,
+    migrations: &Migrations,
+    report: &mut MigrationReport,
+    errors: &mut Vec<ModelLoadError>,
+) -> Vec<T> {
+    let mut docs = Vec::<T>::new();
+    let mut seen = HashMap::<Uuid, &str>::new();
+    for entry in &bundle.manifest.entries {
+        if !entry.path.starts_with(dir) {
+            continue;
+        }
+        if let Some(other) = seen.get(&entry.id) {
+            errors.push(ModelLoadError::DuplicateId {
+                path: entry.path.clone(),
+                other: (*other).to_owned(),
+                id: entry.id,
+            });
+            continue;
+        }
+        if let Some(doc) = read_entry(bundle, entry, doctype, migrations, report, errors) {
+            seen.insert(entry.id, &entry.path);
+            docs.push(doc);
+        }
+    }
+    docs
+}
+
+/// Unpack the model in `bundle`. Every document is checked against its entry in the
+/// manifest, and the errors of all documents that fail to load are returned together,
+/// as for `util::fs::model_from_fs`. Documents of older versions of their doctype are
+/// migrated with the builtin migrations.
+pub fn model_from_bundle(bundle: &ModelBundle) -> Result<ModelDocument, ModelLoadError> {
+    let (model, report) = model_from_bundle_with_migrations(bundle, &Migrations::builtin())?;
+    if !report.is_empty() {
+        info!("Migrated documents of model in bundle :\n{}", report);
+    }
+    Ok(model)
+}
+
+/// Unpack the model in `bundle` as `model_from_bundle` does, migrating its documents
+/// with `migrations`. Returns the model and the migrations applied to its documents.
+pub fn model_from_bundle_with_migrations(
+    bundle: &ModelBundle,
+    migrations: &Migrations,
+) -> Result<(ModelDocument, MigrationReport), ModelLoadError> {
+    let manifest = &bundle.manifest;
+    if manifest.format_version != BUNDLE_FORMAT_VERSION {
+        return Err(ModelLoadError::BadStructure(format!(
+            "Unsupported bundle format version {}, expected {}",
+            manifest.format_version,
+            BUNDLE_FORMAT_VERSION
+        )));
+    }
+
+    let mut errors = Vec::<ModelLoadError>::new();
+    let mut report = MigrationReport::default();
+
+    let mut model_value = serde_json::to_value(&manifest.model).unwrap();
+    let model_header: Option<DocumentHeader> =
+        match migrate_value("manifest", "model", &mut model_value, migrations, &mut report) {
+            Ok(_) => {
+                match serde_json::from_value(model_value) {
+                    Ok(header) => Some(header),
+                    Err(err) => {
+                        errors.push(ModelLoadError::BadStructure(format!("manifest : {}", err)));
+                        None
+                    }
+                }
+            }
+            Err(err) => {
+                errors.push(err);
+                None
+            }
+        };
+
+    for path in bundle.documents.keys() {
+        if !manifest.entries.iter().any(|entry| &entry.path == path) {
+            errors.push(ModelLoadError::BadStructure(
+                format!("{} : document is not in the manifest", path),
+            ));
+        }
+    }
+
+    let find_entry = |path: &str, errors: &mut Vec<ModelLoadError>| {
+        let entry = manifest.entries.iter().find(|entry| entry.path == path);
+        if entry.is_none() {
+            errors.push(ModelLoadError::MissingFile(path.to_owned()));
+        }
+        entry
+    };
+    let config = find_entry("config.json", &mut errors).and_then(|entry| {
+        read_entry(bundle, entry, "config", migrations, &mut report, &mut errors)
+    });
+    let domain = find_entry("domain.json", &mut errors).and_then(|entry| {
+        read_entry(bundle, entry, "domain", migrations, &mut report, &mut errors)
+    });
+
+    let xflows = read_entries(bundle, "xflows/", "xflow", migrations, &mut report, &mut errors);
+    let pages = read_entries(bundle, "pages/", "page", migrations, &mut report, &mut errors);
+    let translations = read_entries(
+        bundle,
+        "translations/",
+        "translation",
+        migrations,
+        &mut report,
+        &mut errors,
+    );
+
+    match (model_header, config, domain) {
+        (Some(model_header), Some(config), Some(domain)) => {
+            let mut modeldoc = ModelDocument::new_from_header(&model_header);
+            modeldoc.body.config = config;
+            modeldoc.body.domain = domain;
+            modeldoc.body.xflows = xflows;
+            modeldoc.body.pages = pages;
+            modeldoc.body.translations = translations;
+            match ModelLoadError::collect(errors) {
+                Some(err) => Err(err),
+                None => Ok((modeldoc, report)),
+            }
+        }
+        _ => Err(ModelLoadError::collect(errors).unwrap()),
+    }
+}
+
+/// Write `model` as a bundle to file `path`. The bundle is written to a temporary file
+/// next to `path` first and then renamed, so `path` never holds a partial bundle.
+pub fn bundle_to_file(model: &ModelDocument, path: &str) -> Result<(), ModelLoadError> {
+    debug!(
+        "Writing model id:'{}', version:'{}' to bundle '{}'",
+        model.id,
+        model.version,
+        path
+    );
+
+    let tmp_path = format!("{}.tmp", path);
+    write_file(&tmp_path, &model_to_bundle(model).to_json())?;
+    match std::fs::rename(&tmp_path, path) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            Err(ModelLoadError::Io {
+                path: path.to_owned(),
+                message: format!("{}", err),
+            })
+        }
+    }
+}
+
+/// Read the model in the bundle file `path`
+pub fn bundle_from_file(path: &str) -> Result<ModelDocument, ModelLoadError> {
+    debug!("Reading model from bundle '{}'", path);
+
+    let data = read_json_file(Path::new(path))?;
+    let bundle: ModelBundle = match serde_json::from_str(&data) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            return Err(ModelLoadError::Parse {
+                path: path.to_owned(),
+                line: err.line(),
+                column: err.column(),
+                message: format!("{}", err),
+            })
+        }
+    };
+    model_from_bundle(&bundle)
+}
@@ -1,79 +1,432 @@
-use crate::structure::common::{DocumentHeader, ModelLoadError};
+use crate::structure::common::{Document, DocumentFormat, DocumentHeader, FileLayout, ModelLoadError};
 use crate::structure::model::{ModelDocument, ModelConfigDocument};
 use crate::structure::xflow::XFlowDocument;
 use crate::structure::page::PageDocument;
 use crate::structure::domain::DomainDocument;
 use crate::structure::translation::TranslationDocument;
+use crate::structure::merge::{merge_models, MergeConflict};
+use crate::structure::migration::{DocumentMigration, MigrationReport, Migrations};
 
 use crate::generation;
+use crate::util::naming::{name_to_path, uuid_to_label};
 
 use glob::glob_with;
 use glob::MatchOptions;
 
-use std::error::Error;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use serde_yaml;
+use uuid::Uuid;
+
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std;
 
-fn read_json_file(path: &Path) -> String {
-    let display = path.display();
+fn io_error(path: &Path, err: &io::Error) -> ModelLoadError {
+    if err.kind() == io::ErrorKind::NotFound {
+        ModelLoadError::MissingFile(format!("{}", path.display()))
+    } else {
+        ModelLoadError::Io {
+            path: format!("{}", path.display()),
+            message: format!("{}", err),
+        }
+    }
+}
 
+pub(crate) fn read_json_file(path: &Path) -> Result<String, ModelLoadError> {
     let mut file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display, Error::description(&why)),
+        Err(why) => return Err(io_error(path, &why)),
         Ok(file) => file,
     };
 
     let mut s = String::new();
     if let Err(why) = file.read_to_string(&mut s) {
-        panic!("couldn't read {}: {}", display, Error::description(&why));
+        return Err(io_error(path, &why));
+    };
+
+    Ok(s)
+}
+
+/// The format of the document in `path`, by its extension
+fn doc_format(path: &Path) -> Option<DocumentFormat> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(DocumentFormat::from_extension)
+}
+
+/// Read and deserialize the JSON or YAML document in `path`, by its extension
+fn read_doc<T: DeserializeOwned>(path: &Path) -> Result<T, ModelLoadError> {
+    let data = read_json_file(path)?;
+    parse_doc(path, &data)
+}
+
+/// Deserialize `data`, the JSON or YAML document in `path`, by its extension
+fn parse_doc<T: DeserializeOwned>(path: &Path, data: &str) -> Result<T, ModelLoadError> {
+    let parse_error = |line: usize, column: usize, message: String| {
+        ModelLoadError::Parse {
+            path: format!("{}", path.display()),
+            line: line,
+            column: column,
+            message: message,
+        }
+    };
+    match doc_format(path) {
+        Some(DocumentFormat::Yaml) => {
+            serde_yaml::from_str(data).map_err(|err| {
+                let (line, column) = match err.location() {
+                    Some(location) => (location.line(), location.column()),
+                    None => (0, 0),
+                };
+                parse_error(line, column, format!("{}", err))
+            })
+        }
+        _ => {
+            serde_json::from_str(data).map_err(|err| {
+                parse_error(err.line(), err.column(), format!("{}", err))
+            })
+        }
+    }
+}
+
+/// Read the `doctype` document in `path` and migrate it to the current version of its
+/// doctype, adding the migrations applied to `report`
+fn read_migrated_doc<T: DeserializeOwned>(
+    path: &Path,
+    doctype: &str,
+    migrations: &Migrations,
+    report: &mut MigrationReport,
+) -> Result<T, ModelLoadError> {
+    let data = read_json_file(path)?;
+    let mut value: Value = match parse_doc(path, &data) {
+        Ok(value) => value,
+        // The typed parse reports the first invalid value instead of the syntax error
+        Err(_) => return parse_doc(path, &data),
     };
+    let steps = match migrations.migrate(doctype, &mut value) {
+        Ok(steps) => steps,
+        Err(err) => {
+            return Err(ModelLoadError::Migration {
+                path: format!("{}", path.display()),
+                error: err,
+            })
+        }
+    };
+    if steps.is_empty() {
+        // Deserialize from the file itself, for errors with locations
+        return parse_doc(path, &data);
+    }
+
+    debug!("Migrated {:?} : {:?}", path, steps);
+    report.documents.push(DocumentMigration {
+        document: format!("{}", path.display()),
+        steps: steps,
+    });
+    serde_json::from_value(value).map_err(|err| {
+        ModelLoadError::BadStructure(format!("{} : {}", path.display(), err))
+    })
+}
+
+/// Return the document file named `name` in directory `path`, in any format
+fn find_doc_file(path: &str, name: &str) -> PathBuf {
+    let candidates: Vec<PathBuf> = DocumentFormat::extensions()
+        .iter()
+        .map(|ext| Path::new(path).join(format!("{}.{}", name, ext)))
+        .collect();
+    match candidates.iter().find(|candidate| candidate.is_file()) {
+        Some(found) => found.clone(),
+        None => candidates[0].clone(),
+    }
+}
 
-    s
+/// Serialize `doc` in `format`
+fn serialize_doc<T: Serialize>(doc: &T, format: DocumentFormat) -> String {
+    match format {
+        DocumentFormat::Json => serde_json::to_string_pretty(doc).unwrap(),
+        DocumentFormat::Yaml => serde_yaml::to_string(doc).unwrap(),
+    }
+}
+
+/// Write `doc` to the file named `name` in directory `path` in `format`, and remove the
+/// files of the same name in other formats
+fn write_doc<T: Serialize>(
+    path: &str,
+    name: &str,
+    doc: &T,
+    format: DocumentFormat,
+) -> Result<(), ModelLoadError> {
+    for ext in DocumentFormat::extensions() {
+        if *ext != format.extension() {
+            let other = Path::new(path).join(format!("{}.{}", name, ext));
+            if other.is_file() {
+                debug!("Removing '{:?}', written as {}", other, format.extension());
+                if let Err(err) = std::fs::remove_file(&other) {
+                    return Err(io_error(&other, &err));
+                }
+            }
+        }
+    }
+    let filename = format!("{}/{}.{}", path, name, format.extension());
+    write_file(&filename, &serialize_doc(doc, format))
 }
 
-fn write_file(filename: &str, data: &str) -> () {
+pub(crate) fn write_file(filename: &str, data: &str) -> Result<(), ModelLoadError> {
     let path = Path::new(filename);
     let display = path.display();
 
     let mut file = match File::create(&path) {
         Err(why) => {
-            error!("couldn't create {}: {}", display, why.description());
-            panic!("couldn't create {}: {}", display, why.description());
+            error!("couldn't create {}: {}", display, why);
+            return Err(io_error(path, &why));
         }
         Ok(file) => file,
     };
 
     match file.write_all(data.as_bytes()) {
         Err(why) => {
-            error!("couldn't write to {}: {}", display, why.description());
-            panic!("couldn't write to {}: {}", display, why.description());
+            error!("couldn't write to {}: {}", display, why);
+            Err(io_error(path, &why))
+        }
+        Ok(_) => {
+            debug!("successfully wrote to {}", display);
+            Ok(())
         }
-        Ok(_) => debug!("successfully wrote to {}", display),
     }
 }
 
-fn create_dir(path: &str) -> () {
+pub(crate) fn create_dir(path: &str) -> Result<(), ModelLoadError> {
     debug!("Creating directory '{:?}'", path);
     if !Path::new(path).exists() {
         match std::fs::create_dir(&path) {
             Ok(_) => {
                 debug!("Created directory '{:?}' : OK", path);
+                Ok(())
             }
-            Err(_) => {
+            Err(err) => {
                 error!("Error creating directory '{:?}'", path);
+                Err(io_error(Path::new(path), &err))
             }
-        };
-    } else {
+        }
+    } else if Path::new(path).is_dir() {
         debug!("Directory '{:?}' exists, not creating", path);
+        Ok(())
+    } else {
+        Err(ModelLoadError::Io {
+            path: path.to_owned(),
+            message: "exists and is not a directory".to_owned(),
+        })
+    }
+}
+
+/// Return the file names of `docs` in `layout`, relative to their directory and without
+/// extension. Documents whose names give the same file name are told apart by their
+/// id, except for the one with the lowest id.
+pub(crate) fn doc_file_names<T>(docs: &[Document<T>], layout: FileLayout) -> Vec<String> {
+    match layout {
+        FileLayout::Id => docs.iter().map(|doc| doc.id.to_string()).collect(),
+        FileLayout::Name => {
+            let names: Vec<String> = docs.iter().map(|doc| name_to_path(&doc.name)).collect();
+            let mut lowest_ids = HashMap::<&str, Uuid>::new();
+            for (doc, name) in docs.iter().zip(names.iter()) {
+                let lowest = lowest_ids.entry(name).or_insert(doc.id);
+                if doc.id < *lowest {
+                    *lowest = doc.id;
+                }
+            }
+            docs.iter()
+                .zip(names.iter())
+                .map(|(doc, name)| if lowest_ids[name.as_str()] == doc.id {
+                    name.clone()
+                } else {
+                    format!("{}-{}", name, uuid_to_label(&doc.id))
+                })
+                .collect()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DocumentId {
+    id: Uuid,
+}
+
+/// Return the paths of the document files in and below directory `path` by the id of
+/// their document. Files that fail to load are left out.
+fn doc_files_by_id(path: &str) -> HashMap<Uuid, Vec<PathBuf>> {
+    let mut files = HashMap::<Uuid, Vec<PathBuf>>::new();
+    if let Ok(paths) = list_files(&format!("{}/**/*", path)) {
+        for file in paths {
+            if let Ok(doc) = read_doc::<DocumentId>(&file) {
+                files.entry(doc.id).or_default().push(file);
+            }
+        }
+    }
+    files
+}
+
+/// Remove `file` and the directories containing it, up to `root`, that are left empty
+fn remove_doc_file(root: &Path, file: &Path) -> Result<(), ModelLoadError> {
+    debug!("Removing '{:?}'", file);
+    if let Err(err) = std::fs::remove_file(file) {
+        return Err(io_error(file, &err));
+    }
+    let mut dir = file.parent();
+    while let Some(current) = dir {
+        if current == root || std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+/// Write `docs` to directory `path` in `layout` and `format`. A document written to
+/// another file before, because it was renamed or the layout or format changed, is
+/// moved to its new file: once all documents are written, its old files are removed,
+/// unless another document was written to them.
+fn write_docs<T: Serialize>(
+    path: &str,
+    docs: &[Document<T>],
+    layout: FileLayout,
+    format: DocumentFormat,
+    errors: &mut Vec<ModelLoadError>,
+) -> () {
+    if keep_error(create_dir(path), errors).is_none() {
+        return;
+    }
+
+    let existing = doc_files_by_id(path);
+    let names = doc_file_names(docs, layout);
+    let targets: HashSet<PathBuf> = names
+        .iter()
+        .map(|name| Path::new(path).join(format!("{}.{}", name, format.extension())))
+        .collect();
+
+    let mut written = Vec::<&Document<T>>::new();
+    for (doc, name) in docs.iter().zip(names.iter()) {
+        let target = Path::new(path).join(format!("{}.{}", name, format.extension()));
+        if let Some(parent) = target.parent() {
+            if let Err(err) = std::fs::create_dir_all(parent) {
+                errors.push(io_error(parent, &err));
+                continue;
+            }
+        }
+        if keep_error(write_doc(path, name, doc, format), errors).is_some() {
+            written.push(doc);
+        }
+    }
+
+    for doc in written {
+        if let Some(files) = existing.get(&doc.id) {
+            // Files of the same name in other formats are removed by write_doc
+            for file in files.iter().filter(|file| !targets.contains(*file) && file.is_file()) {
+                keep_error(remove_doc_file(Path::new(path), file), errors);
+            }
+        }
+    }
+}
+
+/// Keep the error of `res`, if any, in `errors`
+fn keep_error<T>(res: Result<T, ModelLoadError>, errors: &mut Vec<ModelLoadError>) -> Option<T> {
+    match res {
+        Ok(value) => Some(value),
+        Err(err) => {
+            error!("{}", err);
+            errors.push(err);
+            None
+        }
+    }
+}
+
+/// Return `value`, or the collected `errors` when there are any
+fn collected<T>(value: T, errors: Vec<ModelLoadError>) -> Result<T, ModelLoadError> {
+    match ModelLoadError::collect(errors) {
+        Some(err) => Err(err),
+        None => Ok(value),
+    }
+}
+
+/// Return the paths of the document files matching `pattern`, sorted
+fn list_files(pattern: &str) -> Result<Vec<PathBuf>, ModelLoadError> {
+    let glob_options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    let coll = match glob_with(pattern, &glob_options) {
+        Ok(coll) => coll,
+        Err(err) => {
+            return Err(ModelLoadError::Io {
+                path: pattern.to_owned(),
+                message: format!("{}", err),
+            })
+        }
+    };
+
+    let mut paths = Vec::<PathBuf>::new();
+    for item in coll {
+        match item {
+            Ok(path) => {
+                if path.is_dir() {
+                    continue;
+                } else if doc_format(&path).is_some() {
+                    paths.push(path);
+                } else {
+                    warn!("Ignoring '{:?}', not a JSON or YAML document", path);
+                }
+            }
+            Err(err) => {
+                return Err(ModelLoadError::Io {
+                    path: format!("{}", err.path().display()),
+                    message: format!("{}", err.error()),
+                })
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Load the documents in the files matching `pattern` with `read`, keeping the errors
+/// of the files that fail to load, or that have the id of a document loaded before, in
+/// `errors`
+fn read_docs<T, F, R>(pattern: &str, id: F, mut read: R, errors: &mut Vec<ModelLoadError>) -> Vec<T>
+where
+    F: Fn(&T) -> Uuid,
+    R: FnMut(&Path) -> Result<T, ModelLoadError>,
+{
+    let mut docs = Vec::<T>::new();
+    let paths = match keep_error(list_files(pattern), errors) {
+        Some(paths) => paths,
+        None => return docs,
+    };
+
+    let mut seen = HashMap::<Uuid, PathBuf>::new();
+    for path in paths {
+        debug!("model_from_fs : Deserializing document from {:?}", path);
+        if let Some(doc) = keep_error(read(&path), errors) {
+            if let Some(other) = seen.get(&id(&doc)) {
+                errors.push(ModelLoadError::DuplicateId {
+                    path: format!("{}", path.display()),
+                    other: format!("{}", other.display()),
+                    id: id(&doc),
+                });
+                continue;
+            }
+            seen.insert(id(&doc), path);
+            docs.push(doc);
+        }
     }
+    docs
 }
 
 pub fn build_dotfiles(model: &ModelDocument, path: &str) -> Result<(), ModelLoadError> {
     // partof: #SPC-artifact-generation-model
 
-    // XXX Error handling, assumption checking
-
     debug!(
         "Building dotfiles id:'{}' assets, model version:'{}' in directory '{}'",
         model.id,
@@ -81,30 +434,29 @@ pub fn build_dotfiles(model: &ModelDocument, path: &str) -> Result<(), ModelLoad
         path
     );
 
-    create_dir(&path);
+    create_dir(&path)?;
     let xflow_path = format!("{path}/xflows", path = path);
-    create_dir(&xflow_path);
+    create_dir(&xflow_path)?;
 
+    let mut errors = Vec::<ModelLoadError>::new();
     for xflow in &model.body.xflows {
         let doc = generation::xflow_to_dot::output(&xflow);
 
         let filename = format!("{path}/{id}.dot", path = xflow_path, id = xflow.id);
-        write_file(&filename, &doc);
+        keep_error(write_file(&filename, &doc), &mut errors);
     }
 
     let doc = generation::domain_to_dot::output(&model.body.domain);
 
     let filename = format!("{path}/domain.dot", path = path);
-    write_file(&filename, &doc);
+    keep_error(write_file(&filename, &doc), &mut errors);
 
-    Ok(())
+    collected((), errors)
 }
 
 pub fn build_to_react_app(model: &ModelDocument, path: &str) -> Result<(), ModelLoadError> {
     // partof: SPC-artifact-generation-model
 
-    // XXX Error handling, assumption checking
-
     debug!(
         "Building id:'{}' assets, model version:'{}' in directory '{}'",
         model.id,
@@ -112,17 +464,18 @@ pub fn build_to_react_app(model: &ModelDocument, path: &str) -> Result<(), Model
         path
     );
 
-    create_dir(&path);
+    create_dir(&path)?;
     let xflow_path = format!("{path}/xflows", path = path);
-    create_dir(&xflow_path);
+    create_dir(&xflow_path)?;
     let component_path = format!("{path}/components", path = path);
-    create_dir(&component_path);
+    create_dir(&component_path)?;
 
+    let mut errors = Vec::<ModelLoadError>::new();
     for page in &model.body.pages {
         let doc = generation::page_to_react_component::output_html(&page);
 
         let filename = format!("{path}/{id}.js", path = component_path, id = page.id);
-        write_file(&filename, &doc);
+        keep_error(write_file(&filename, &doc), &mut errors);
 
     }
 
@@ -130,179 +483,225 @@ pub fn build_to_react_app(model: &ModelDocument, path: &str) -> Result<(), Model
         let doc = generation::xflow_to_es5::output(&xflow);
 
         let filename = format!("{path}/{id}.js", path = xflow_path, id = xflow.id);
-        write_file(&filename, &doc);
+        keep_error(write_file(&filename, &doc), &mut errors);
     }
 
-    Ok(())
+    collected((), errors)
 }
 
+/// Write the model to directory `path`, in the document format and file layout set in
+/// its config
 pub fn model_to_fs(model: &ModelDocument, path: &str) -> Result<(), ModelLoadError> {
     // partof: #SPC-serialization-fs
 
-    // XXX Error handling, assumption checking
-
+    let format = model.body.config.body.format;
     debug!(
-        "Writing model id:'{}', version:'{}' to directory '{}'",
+        "Writing model id:'{}', version:'{}' to directory '{}' as {}",
         model.id,
         model.version,
-        path
+        path,
+        format.extension()
     );
 
-    let model_header_doc_filename = format!("{}/model.json", path);
-    write_file(&model_header_doc_filename, &model.get_header().to_json());
-
-    let model_config_doc_filename = format!("{}/config.json", path);
-    write_file(&model_config_doc_filename, &model.body.config.to_json());
+    let mut errors = Vec::<ModelLoadError>::new();
 
-    let doc_filename = format!("{}/domain.json", path);
-    write_file(&doc_filename, &model.body.domain.to_json());
+    keep_error(write_doc(path, "model", &model.get_header(), format), &mut errors);
+    keep_error(write_doc(path, "config", &model.body.config, format), &mut errors);
+    keep_error(write_doc(path, "domain", &model.body.domain, format), &mut errors);
 
+    let layout = model.body.config.body.layout;
     let xflows_path_name = format!("{}/xflows", path);
-    create_dir(&xflows_path_name);
-
-    for doc in &model.body.xflows {
-        let doc_filename = format!("{}/{}.json", xflows_path_name, doc.id);
-        write_file(&doc_filename, &doc.to_json());
-    }
+    write_docs(&xflows_path_name, &model.body.xflows, layout, format, &mut errors);
 
     let pages_path_name = format!("{}/pages", path);
-    create_dir(&pages_path_name);
-
-    for doc in &model.body.pages {
-        let doc_filename = format!("{}/{}.json", pages_path_name, doc.id);
-        write_file(&doc_filename, &doc.to_json());
-    }
+    write_docs(&pages_path_name, &model.body.pages, layout, format, &mut errors);
 
     let translations_path_name = format!("{}/translations", path);
-    create_dir(&translations_path_name);
-
-    for doc in &model.body.translations {
-        let doc_filename = format!("{}/{}.json", translations_path_name, doc.body.locale);
-        write_file(&doc_filename, &doc.to_json());
+    if keep_error(create_dir(&translations_path_name), &mut errors).is_some() {
+        for doc in &model.body.translations {
+            let name = &doc.body.locale;
+            keep_error(write_doc(&translations_path_name, name, doc, format), &mut errors);
+        }
     }
 
-    Ok(())
+    collected((), errors)
 }
 
+/// Read the model in directory `path`, from JSON and YAML documents picked by their
+/// extension. Every file is read, and the errors of all files that fail to load are
+/// returned together. Documents of older versions of their doctype are migrated with
+/// the builtin migrations.
 pub fn model_from_fs(path: &str) -> Result<ModelDocument, ModelLoadError> {
     // partof: SPC-serialization-fs
 
-    // XXX Error handling, assumption checking
+    let (model, report) = model_from_fs_with_migrations(path, &Migrations::builtin())?;
+    if !report.is_empty() {
+        info!("Migrated documents of model in '{}' :\n{}", path, report);
+    }
+    Ok(model)
+}
+
+/// Read the model in directory `path` as `model_from_fs` does, migrating its documents
+/// with `migrations`. Returns the model and the migrations applied to its documents.
+pub fn model_from_fs_with_migrations(
+    path: &str,
+    migrations: &Migrations,
+) -> Result<(ModelDocument, MigrationReport), ModelLoadError> {
+    // partof: SPC-serialization-fs
 
     debug!("Reading model from directory '{}'", path);
-    let model_header_filename = format!("{}/model.json", path);
-    let model_header_path = Path::new(&model_header_filename);
-    let model_header_json = read_json_file(model_header_path);
-    debug!("model_from_fs : Deserializing model header JSON from {}", model_header_filename);
-    let model_header: DocumentHeader = match DocumentHeader::from_json(&model_header_json) {
-        Ok(res) => res,
-        Err(err) => return Err(err)
-    };
+    if !Path::new(path).is_dir() {
+        return Err(ModelLoadError::MissingFile(path.to_owned()));
+    }
 
-    let mut modeldoc = ModelDocument::new_from_header(&model_header);
+    let mut errors = Vec::<ModelLoadError>::new();
 
-    let model_config_filename = format!("{}/config.json", path);
-    let model_config_path = Path::new(&model_config_filename);
-    let model_config_json = read_json_file(model_config_path);
-    debug!("model_from_fs : Deserializing model JSON from {}", model_config_filename);
-    let model_config: ModelConfigDocument = match ModelConfigDocument::from_json(&model_config_json) {
-        Ok(res) => res,
-        Err(err) => return Err(err)
-    };
+    let model_header_filename = find_doc_file(path, "model");
+    debug!("model_from_fs : Deserializing model header from {:?}", model_header_filename);
+    let mut report = MigrationReport::default();
+    let model_header: Option<DocumentHeader> = keep_error(
+        read_migrated_doc(&model_header_filename, "model", migrations, &mut report),
+        &mut errors,
+    );
 
-    modeldoc.body.config = model_config;
+    let model_config_filename = find_doc_file(path, "config");
+    debug!("model_from_fs : Deserializing model config from {:?}", model_config_filename);
+    let model_config: Option<ModelConfigDocument> = keep_error(
+        read_migrated_doc(&model_config_filename, "config", migrations, &mut report),
+        &mut errors,
+    );
 
-    let domain_filename = format!("{}/domain.json", path);
-    let domain_path = Path::new(&domain_filename);
-    let json = read_json_file(domain_path);
-    debug!("model_from_fs : Deserializing domain JSON from {}", domain_filename);
-    let domain: DomainDocument = match DomainDocument::from_json(&json) {
-        Ok(res) => res,
-        Err(err) => return Err(err)
-    };
+    let domain_filename = find_doc_file(path, "domain");
+    debug!("model_from_fs : Deserializing domain from {:?}", domain_filename);
+    let domain: Option<DomainDocument> = keep_error(
+        read_migrated_doc(&domain_filename, "domain", migrations, &mut report),
+        &mut errors,
+    );
 
-    modeldoc.body.domain = domain;
+    let xflows = read_docs::<XFlowDocument, _, _>(
+        &format!("{}/xflows/**/*", path),
+        |doc| doc.id,
+        |file| read_migrated_doc(file, "xflow", migrations, &mut report),
+        &mut errors,
+    );
+    let pages = read_docs::<PageDocument, _, _>(
+        &format!("{}/pages/**/*", path),
+        |doc| doc.id,
+        |file| read_migrated_doc(file, "page", migrations, &mut report),
+        &mut errors,
+    );
+    let translations = read_docs::<TranslationDocument, _, _>(
+        &format!("{}/translations/*", path),
+        |doc| doc.id,
+        |file| read_migrated_doc(file, "translation", migrations, &mut report),
+        &mut errors,
+    );
 
+    match (model_header, model_config, domain) {
+        (Some(model_header), Some(model_config), Some(domain)) => {
+            let mut modeldoc = ModelDocument::new_from_header(&model_header);
+            modeldoc.body.config = model_config;
+            modeldoc.body.domain = domain;
+            modeldoc.body.xflows = xflows;
+            modeldoc.body.pages = pages;
+            modeldoc.body.translations = translations;
+            collected((modeldoc, report), errors)
+        }
+        _ => Err(ModelLoadError::collect(errors).unwrap()),
+    }
+}
 
-    let glob_options = MatchOptions {
-        case_sensitive: true,
-        require_literal_separator: false,
-        require_literal_leading_dot: false,
-    };
+/// Three-way merge of the model directories `ours` and `theirs`, both changed from
+/// `base`, into directory `path`, which can be `ours`. Documents that are not in the
+/// merged model are removed from `path` once the merged model is written, so a failed
+/// write leaves them in place. Returns the conflicts of the merge, for which the merged
+/// model keeps our changes.
+pub fn merge_model_fs(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    path: &str,
+) -> Result<Vec<MergeConflict>, ModelLoadError> {
+    // partof: SPC-serialization-fs
 
-    let xflow_files_path = format!("{}/xflows/*", path);
-    if let Ok(coll) = glob_with(&xflow_files_path, &glob_options) {
-        for item in coll {
-            if let Ok(path) = item {
-                let json = read_json_file(&path);
-                debug!("model_from_fs : Deserializing xflow JSON from {:?}", path);
-                let xflow_doc: XFlowDocument = match XFlowDocument::from_json(&json) {
-                    Ok(res) => res,
-                    Err(err) => return Err(err)
-                };
-                modeldoc.body.xflows.push(xflow_doc);
-            } else {
-                warn!("Unable to load doc from '{:?}'", item);
-            }
-        }
-    } else {
-        error!("model_from_fs : unable to read from path : {}", xflow_files_path);
-        panic!(format!("model_from_fs : unable to read from path : {}", xflow_files_path));
+    debug!(
+        "Merging model directories base '{}', ours '{}' and theirs '{}' into '{}'",
+        base,
+        ours,
+        theirs,
+        path
+    );
+
+    let base = model_from_fs(base)?;
+    let ours = model_from_fs(ours)?;
+    let theirs = model_from_fs(theirs)?;
+
+    let res = merge_models(&base, &ours, &theirs)?;
+    for conflict in &res.conflicts {
+        warn!("{}", conflict);
     }
 
-    let page_files_path = format!("{}/pages/*", path);
-    if let Ok(coll) = glob_with(&page_files_path, &glob_options) {
-        for item in coll {
-            if let Ok(path) = item {
-                let json = read_json_file(&path);
-                debug!("model_from_fs : Deserializing page JSON from {:?}", path);
-                let page_doc: PageDocument = match PageDocument::from_json(&json) {
-                    Ok(res) => res,
-                    Err(err) => return Err(err)
-                };
-                modeldoc.body.pages.push(page_doc);
-            } else {
-                warn!("Unable to load doc from '{:?}'", item);
+    create_dir(path)?;
+    model_to_fs(&res.merged, path)?;
+
+    let merged = &res.merged.body;
+    let kept: [(&str, HashSet<Uuid>); 3] = [
+        ("xflows", merged.xflows.iter().map(|doc| doc.id).collect()),
+        ("pages", merged.pages.iter().map(|doc| doc.id).collect()),
+        ("translations", merged.translations.iter().map(|doc| doc.id).collect()),
+    ];
+    let mut errors = Vec::<ModelLoadError>::new();
+    for &(dir, ref ids) in &kept {
+        let dir_path = format!("{}/{}", path, dir);
+        for (id, files) in doc_files_by_id(&dir_path) {
+            if !ids.contains(&id) {
+                for file in files {
+                    keep_error(remove_doc_file(Path::new(&dir_path), &file), &mut errors);
+                }
             }
         }
-    } else {
-        error!("model_from_fs : unable to read from path : {}", page_files_path);
     }
+    collected(res.conflicts, errors)
+}
 
-    let translation_files_path = format!("{}/translations/*", path);
-    if let Ok(coll) =  glob_with(&translation_files_path, &glob_options) {
-        for item in coll {
-            if let Ok(path) = item {
-                let json = read_json_file(&path);
-                debug!("model_from_fs : Deserializing translation JSON from {:?}", path);
-                let translation_doc: TranslationDocument = match TranslationDocument::from_json(&json) {
-                    Ok(res) => res,
-                    Err(err) => return Err(err)
-                };
-                modeldoc.body.translations.push(translation_doc);
-            } else {
-                warn!("Unable to load doc from '{:?}'", item);
-            }
-        }
-    } else {
-        error!("model_from_fs : unable to read from path : {}", translation_files_path);
+/// Migrate the documents of the model in directory `path` with `migrations` and return
+/// the migrations applied. The migrated model is written back to `path`, unless
+/// `dry_run` is set, in which case `path` is left as it is.
+pub fn migrate_model_fs(
+    path: &str,
+    migrations: &Migrations,
+    dry_run: bool,
+) -> Result<MigrationReport, ModelLoadError> {
+    // partof: SPC-serialization-fs
+
+    debug!("Migrating model in directory '{}', dry run : {}", path, dry_run);
+    let (model, report) = model_from_fs_with_migrations(path, migrations)?;
+    if !dry_run && !report.is_empty() {
+        model_to_fs(&model, path)?;
     }
+    Ok(report)
+}
+
+/// Rewrite the model in directory `path` with all of its documents in `format`, and
+/// set `format` as the document format of the model
+pub fn convert_model_fs(path: &str, format: DocumentFormat) -> Result<(), ModelLoadError> {
+    // partof: SPC-serialization-fs
 
-    Ok(modeldoc)
+    debug!("Converting model in directory '{}' to {}", path, format.extension());
+    let mut model = model_from_fs(path)?;
+    model.body.config.body.format = format;
+    model_to_fs(&model, path)
 }
 
 pub fn init_new_model_dir(path: &str) -> Result<(), ModelLoadError> {
-    create_dir(path);
+    create_dir(path)?;
     let mut model = ModelDocument::default();
     let default_locale = model.body.config.body.default_locale.clone();
-    model.add_locale(&default_locale);
+    let _ = model.add_locale(&default_locale);
     model.pad_all_translations();
     model_to_fs(&model, &path)
 }
 
 pub fn is_model_dir(path: &str) -> bool {
-    match model_from_fs(path) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+    model_from_fs(path).is_ok()
 }
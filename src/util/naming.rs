@@ -10,3 +10,37 @@ pub fn label_to_uuid(id: &str) -> Result<Uuid, String> {
         Err(_) => Err("Invalid uuid form".to_owned())
     }
 }
+
+/// Return `name` in lower case with every run of characters other than ASCII letters and
+/// digits replaced by a single `-`, for use in file names
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    if slug.is_empty() {
+        slug.push_str("document");
+    }
+    slug
+}
+
+/// Return the relative path of a document named `name`, with a sub-folder for every
+/// `/`-separated part of the name before the last one, and every part slugified
+pub fn name_to_path(name: &str) -> String {
+    let parts: Vec<String> = name.split('/')
+        .filter(|part| !part.trim().is_empty())
+        .map(slugify)
+        .collect();
+    if parts.is_empty() {
+        slugify(name)
+    } else {
+        parts.join("/")
+    }
+}
@@ -1,5 +1,6 @@
 use crate::validation::common::{ValidationError, ValidationErrors};
 use crate::structure::model::ModelDocument;
+use crate::structure::xflow::{XFlowNodeParameters, XFlowNodeType};
 
 use uuid::Uuid;
 
@@ -20,6 +21,9 @@ impl Validation {
         errors.extend(Validation::all_doc_collections_have_correct_unique_keys(
             &model,
         ));
+        errors.extend(Validation::all_xflow_call_references_point_to_existing_xflows(
+            &model,
+        ));
 
         errors
     }
@@ -85,6 +89,34 @@ impl Validation {
         errors
     }
 
+    pub fn all_xflow_call_references_point_to_existing_xflows(
+        model: &ModelDocument,
+    ) -> Vec<ValidationError> {
+        debug!("all_xflow_call_references_point_to_existing_xflows");
+        let mut errors = Vec::<ValidationError>::new();
+
+        for xflow in &model.body.xflows {
+            for node in xflow.body.get_nodes_of_type(&XFlowNodeType::Call) {
+                if let XFlowNodeParameters::Call(ref params) = node.parameters {
+                    if !model.has_xflow(&params.xflowid) {
+                        let message = format!(
+                            "XFlow: Call node '{}' references xflow id '{}', which does not exist in this model",
+                            node.id,
+                            params.xflowid
+                        );
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: message,
+                            paths: vec![format!("/xflow/{id}/nodes/{node}", id = xflow.id, node = node.id)],
+                        });
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
     pub fn all_doc_collections_have_correct_unique_keys(
         model: &ModelDocument,
     ) -> Vec<ValidationError> {
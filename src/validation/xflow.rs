@@ -1,6 +1,8 @@
 use crate::structure::xflow::*;
 use crate::parser::flox;
+use crate::parser::flox::{BinaryOp, ExprKind, UnaryOp};
 use std::collections::{HashSet, HashMap};
+use std::i64;
 
 use crate::validation::common::ValidationError;
 
@@ -22,6 +24,14 @@ impl Validation {
         errors.extend(Validation::all_return_values_exist(doc));
         errors.extend(Validation::no_variable_redefinition(doc));
         errors.extend(Validation::all_flox_variables_exist(doc));
+        errors.extend(Validation::all_call_variables_exist(doc));
+        errors.extend(Validation::all_flox_expressions_type_check(doc));
+        errors.extend(Validation::all_foreach_nodes_are_well_formed(doc));
+        errors.extend(Validation::all_forks_have_matching_joins(doc));
+        errors.extend(Validation::error_handling_is_well_formed(doc));
+        errors.extend(Validation::all_branches_are_well_formed(doc));
+        errors.extend(Validation::branch_conditions_do_not_overlap(doc));
+        errors.extend(Validation::branch_conditions_cover_all_values(doc));
 
         errors
     }
@@ -229,6 +239,13 @@ impl Validation {
             locals.insert(&xvar.name, xvar);
         }
 
+        // The accumulator of a loop is a local variable once the loop has started
+        for params in doc.body.get_foreach_parameters() {
+            if let Some(ref xvar) = params.accumulator {
+                locals.entry(&xvar.name).or_insert(xvar);
+            }
+        }
+
         for xvar in &doc.body.variables.output {
             if !locals.contains_key(&xvar.name) && !inputs.contains_key(&xvar.name) {
                 errors.push(ValidationError {
@@ -305,8 +322,12 @@ impl Validation {
 
         for node in nodes {
             if let XFlowNodeParameters::Flox(ref flox_params) = node.parameters {
-                for flox_var in flox::extract_variable_names(&flox_params.expression).unwrap() {
-                    if !names_in_xflow.contains(flox_var) {
+                let flox_vars = match flox::compile(&flox_params.expression) {
+                    Ok(expr) => expr.variable_names().into_iter().map(|name| name.to_owned()).collect(),
+                    Err(_) => Vec::<String>::new(),
+                };
+                for flox_var in flox_vars {
+                    if !names_in_xflow.contains(&flox_var) {
                         errors.push(ValidationError {
                             code: 1,
                             message: format!(
@@ -324,6 +345,622 @@ impl Validation {
         errors
     }
 
+    pub fn all_call_variables_exist(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let nodes = doc.body.get_nodes_of_type(&XFlowNodeType::Call);
+        let names_in_xflow = doc.body.get_all_variable_names();
+
+        for node in nodes {
+            if let XFlowNodeParameters::Call(ref call_params) = node.parameters {
+                for &(ref from, _) in &call_params.inputmatch {
+                    if !names_in_xflow.contains(from) {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Call input references variable '{}' which is not defined \
+                                 in this flow",
+                                from
+                            ),
+                            paths: vec![format!("/nodes/{}", node.id)],
+                        });
+                    }
+                }
+                for &(_, ref to) in &call_params.outputmatch {
+                    if !names_in_xflow.contains(to) {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Call output references variable '{}' which is not defined \
+                                 in this flow",
+                                to
+                            ),
+                            paths: vec![format!("/nodes/{}", node.id)],
+                        });
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn all_flox_expressions_type_check(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let types = doc.body.get_variable_types();
+
+        for node in doc.body.get_nodes_of_type(&XFlowNodeType::Flox) {
+            if let XFlowNodeParameters::Flox(ref flox_params) = node.parameters {
+                let path = format!("/nodes/{}", node.id);
+                let expression = &flox_params.expression;
+
+                let checked = match flox::compile(expression) {
+                    Ok(expr) => expr.infer_type(&types).map_err(|err| (err.offset, err.message)),
+                    Err(err) => Err((err.offset, err.message)),
+                };
+
+                let vtype = match checked {
+                    Ok(vtype) => vtype,
+                    Err((offset, message)) => {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Flox expression '{}' is invalid at position {} - {}",
+                                expression,
+                                offset,
+                                message
+                            ),
+                            paths: vec![path],
+                        });
+                        continue;
+                    }
+                };
+
+                if !flox_params.returns.vtype.accepts(&vtype) {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Flox expression '{}' returns {:?}, but '{}' is declared as {:?}",
+                            expression,
+                            vtype,
+                            flox_params.returns.name,
+                            flox_params.returns.vtype
+                        ),
+                        paths: vec![path],
+                    });
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Check that `foreach` nodes iterate over a declared list, do not overwrite declared
+    /// variables, and have an edge into a loop body that leads back to the node and one
+    /// edge out of the loop
+    pub fn all_foreach_nodes_are_well_formed(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let mut types = HashMap::<&String, &XFlowValueType>::new();
+        for xvar in &doc.body.variables.input {
+            types.insert(&xvar.name, &xvar.vtype);
+        }
+        for xvar in &doc.body.variables.local {
+            types.insert(&xvar.name, &xvar.vtype);
+        }
+
+        for node in &doc.body.nodes {
+            let path = format!("/nodes/{}", node.id);
+            let params = match (node.action.as_ref(), &node.parameters) {
+                ("foreach", &XFlowNodeParameters::Foreach(ref params)) => params,
+                ("foreach", _) => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!("Foreach node '{}' has no foreach parameters", node.id),
+                        paths: vec![path],
+                    });
+                    continue;
+                }
+                (action, &XFlowNodeParameters::Foreach(_)) => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Node '{}' has foreach parameters, but action '{}'",
+                            node.id,
+                            action
+                        ),
+                        paths: vec![path],
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match types.get(&params.list) {
+                Some(vtype) if XFlowValueType::List.accepts(vtype) => {}
+                Some(vtype) => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Foreach node '{}' iterates over '{}', which is {:?}, not a list",
+                            node.id,
+                            params.list,
+                            vtype
+                        ),
+                        paths: vec![path.clone()],
+                    })
+                }
+                None => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Foreach node '{}' iterates over '{}', which is not an input or \
+                             local variable",
+                            node.id,
+                            params.list
+                        ),
+                        paths: vec![path.clone()],
+                    })
+                }
+            }
+
+            for name in &[&params.element, &params.index] {
+                if types.contains_key(name) {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Foreach node '{}' overwrites the declared variable '{}'",
+                            node.id,
+                            name
+                        ),
+                        paths: vec![path.clone()],
+                    });
+                }
+            }
+
+            let edges = doc.body.get_out_edges(node);
+            let into_body = edges.iter().filter(|edge| edge.1 == params.body).count();
+            if edges.len() != 2 || into_body != 1 {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "Foreach node '{}' needs one edge to its body node '{}' and one edge \
+                         out of the loop",
+                        node.id,
+                        params.body
+                    ),
+                    paths: vec![path.clone()],
+                });
+            } else if !Validation::leads_to(doc, params.body, node.id) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "The body of foreach node '{}' has no edge back to the node",
+                        node.id
+                    ),
+                    paths: vec![path.clone()],
+                });
+            }
+        }
+
+        errors
+    }
+
+    /// Check that every fork has at least two branches that each lead to its join node
+    /// without sharing nodes or ending the flow, and that every join belongs to one fork
+    pub fn all_forks_have_matching_joins(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+        let mut joins = HashMap::<i32, usize>::new();
+
+        for node in &doc.body.nodes {
+            if node.action == "join" {
+                joins.entry(node.id).or_insert(0);
+            }
+        }
+
+        for node in &doc.body.nodes {
+            let path = format!("/nodes/{}", node.id);
+            let params = match (node.action.as_ref(), &node.parameters) {
+                ("fork", &XFlowNodeParameters::Fork(ref params)) => params,
+                ("fork", _) => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!("Fork node '{}' has no fork parameters", node.id),
+                        paths: vec![path],
+                    });
+                    continue;
+                }
+                (action, &XFlowNodeParameters::Fork(_)) => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Node '{}' has fork parameters, but action '{}'",
+                            node.id,
+                            action
+                        ),
+                        paths: vec![path],
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match joins.get_mut(&params.join) {
+                Some(count) => *count += 1,
+                None => {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Fork node '{}' has no matching join node '{}'",
+                            node.id,
+                            params.join
+                        ),
+                        paths: vec![path],
+                    });
+                    continue;
+                }
+            }
+
+            let regions = doc.body.get_fork_regions(node, params.join);
+            if regions.len() < 2 {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!("Fork node '{}' has less than two branches", node.id),
+                    paths: vec![path.clone()],
+                });
+            }
+
+            let mut seen = HashSet::<i32>::new();
+            for &(ref edge, ref region) in &regions {
+                let reaches_join = edge.1 == params.join ||
+                    doc.body.edges.iter().any(|e| {
+                        e.1 == params.join && region.contains(&e.0)
+                    });
+                let ends = region.iter().any(|id| {
+                    !doc.body.edges.iter().any(|e| e.0 == *id)
+                });
+                if !reaches_join || ends {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "Branch {:?} of fork node '{}' does not always reach join node '{}'",
+                            edge,
+                            node.id,
+                            params.join
+                        ),
+                        paths: vec![path.clone()],
+                    });
+                }
+                for id in region {
+                    if !seen.insert(*id) {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branches of fork node '{}' share node '{}'",
+                                node.id,
+                                id
+                            ),
+                            paths: vec![path.clone()],
+                        });
+                    }
+                }
+            }
+        }
+
+        for (join, count) in joins {
+            if count != 1 {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "Join node '{}' matches {} fork nodes instead of one",
+                        join,
+                        count
+                    ),
+                    paths: vec![format!("/nodes/{}", join)],
+                });
+            }
+        }
+
+        errors
+    }
+
+    /// Check whether there is a path along the edges of the flow from node `from` to
+    /// node `to`
+    fn leads_to(doc: &XFlowDocument, from: i32, to: i32) -> bool {
+        let mut visited = HashSet::<i32>::new();
+        let mut pending = vec![from];
+
+        while let Some(id) = pending.pop() {
+            if id == to {
+                return true;
+            }
+            if visited.insert(id) {
+                pending.extend(doc.body.edges.iter().filter(|edge| edge.0 == id).map(|edge| edge.1));
+            }
+        }
+
+        false
+    }
+
+    /// Check that error edges and the error handler lead to existing nodes other than the
+    /// entry node, that no node has more than one error edge, and that the error variable
+    /// is not declared with another type than record
+    pub fn error_handling_is_well_formed(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let is_target = |id: i32| match doc.body.get_node_id(id) {
+            Some(node) => !(node.nodetype == XFlowNodeType::Flow && node.action == "start"),
+            None => false,
+        };
+
+        let mut failing = HashSet::<i32>::new();
+        for edge in &doc.body.error_edges {
+            let path = format!("/error_edges/{:?}", edge);
+            if doc.body.get_node_id(edge.0).is_none() {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!("Error edge {:?} has no connecting node {:?}", edge, edge.0),
+                    paths: vec![path.clone()],
+                });
+            }
+            if !is_target(edge.1) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "Error edge {:?} does not lead to a node that can handle the error",
+                        edge
+                    ),
+                    paths: vec![path.clone()],
+                });
+            }
+            if !failing.insert(edge.0) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!("XFlow node '{}' has more than one error edge", edge.0),
+                    paths: vec![path],
+                });
+            }
+        }
+
+        if let Some(handler) = doc.body.error_handler {
+            if !is_target(handler) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "XFlow error handler '{}' is not a node that can handle errors",
+                        handler
+                    ),
+                    paths: vec!["/error_handler".to_owned()],
+                });
+            }
+        }
+
+        if doc.body.handles_errors() {
+            let variables = &doc.body.variables;
+            let mistyped = variables
+                .input
+                .iter()
+                .map(|xvar| (&xvar.name, &xvar.vtype))
+                .chain(variables.local.iter().map(|xvar| (&xvar.name, &xvar.vtype)))
+                .chain(variables.output.iter().map(|xvar| (&xvar.name, &xvar.vtype)))
+                .any(|(name, vtype)| {
+                    name == ERROR_VARIABLE && *vtype != XFlowValueType::Record
+                });
+            if mistyped {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "XFlow variable '{}' holds errors and has to be a record",
+                        ERROR_VARIABLE
+                    ),
+                    paths: vec!["/variables".to_owned()],
+                });
+            }
+        }
+
+        errors
+    }
+
+    pub fn all_branches_are_well_formed(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let names_in_xflow = doc.body.get_all_variable_names();
+        let types = doc.body.get_variable_types();
+        let mut defaults = HashSet::<i32>::new();
+
+        for branch in &doc.body.branches {
+            let path = format!("/branches/{:?}", branch.edge);
+
+            if !doc.body.edges.contains(&branch.edge) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!("Branch {:?} has no matching edge", branch.edge),
+                    paths: vec![path.clone()],
+                });
+            }
+
+            if branch.default {
+                if !defaults.insert(branch.edge.0) {
+                    errors.push(ValidationError {
+                        code: 1,
+                        message: format!(
+                            "XFlow node '{}' has more than one default branch",
+                            branch.edge.0
+                        ),
+                        paths: vec![path.clone()],
+                    });
+                }
+            } else if branch.condition.is_none() && branch.xvar.is_none() {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "Branch {:?} has no condition and is not a default branch",
+                        branch.edge
+                    ),
+                    paths: vec![path.clone()],
+                });
+            }
+
+            if let Some(ref condition) = branch.condition {
+                let expr = match flox::compile(condition) {
+                    Ok(expr) => expr,
+                    Err(err) => {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branch condition '{}' is invalid at position {} - {}",
+                                condition,
+                                err.offset,
+                                err.message
+                            ),
+                            paths: vec![path.clone()],
+                        });
+                        continue;
+                    }
+                };
+
+                let missing: Vec<&str> = expr.variable_names()
+                    .into_iter()
+                    .filter(|flox_var| !names_in_xflow.contains(*flox_var))
+                    .collect();
+
+                if !missing.is_empty() {
+                    for flox_var in missing {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branch condition references variable '{}' which is not \
+                                 defined in this flow",
+                                flox_var
+                            ),
+                            paths: vec![path.clone()],
+                        });
+                    }
+                    continue;
+                }
+
+                match expr.infer_type(&types) {
+                    Ok(XFlowValueType::Boolean) => {}
+                    Ok(_) => {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branch condition '{}' is not a boolean expression",
+                                condition
+                            ),
+                            paths: vec![path.clone()],
+                        });
+                    }
+                    Err(err) => {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branch condition '{}' is invalid at position {} - {}",
+                                condition,
+                                err.offset,
+                                err.message
+                            ),
+                            paths: vec![path.clone()],
+                        });
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn branch_conditions_do_not_overlap(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let types = doc.body.get_variable_types();
+        for (_, branches) in Validation::branches_by_node(doc) {
+            for (i, a) in branches.iter().enumerate() {
+                for b in &branches[i + 1..] {
+                    let overlaps = match (&a.condition, &b.condition) {
+                        (&Some(ref ca), &Some(ref cb)) if same_condition(ca, cb) => true,
+                        _ => {
+                            let ca = BranchConstraint::from_branch(a, &types);
+                            let cb = BranchConstraint::from_branch(b, &types);
+                            match (ca, cb) {
+                                (Some(ca), Some(cb)) => ca.overlaps(&cb),
+                                _ => false,
+                            }
+                        }
+                    };
+
+                    if overlaps {
+                        errors.push(ValidationError {
+                            code: 1,
+                            message: format!(
+                                "Branches {:?} and {:?} can both match at the same time",
+                                a.edge,
+                                b.edge
+                            ),
+                            paths: vec![
+                                format!("/branches/{:?}", a.edge),
+                                format!("/branches/{:?}", b.edge),
+                            ],
+                        });
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    pub fn branch_conditions_cover_all_values(doc: &XFlowDocument) -> Vec<ValidationError> {
+        let mut errors = Vec::<ValidationError>::new();
+
+        let types = doc.body.get_variable_types();
+        let mut nodes: Vec<i32> = doc.body.branches.iter().map(|branch| branch.edge.0).collect();
+        nodes.sort();
+        nodes.dedup();
+
+        for node in nodes {
+            let branches = doc.body.get_out_branches(node);
+            if branches.iter().any(|branch| branch.default) {
+                continue;
+            }
+
+            // Coverage can only be decided when all branches constrain the same variable
+            let constraints: Vec<BranchConstraint> = branches
+                .iter()
+                .filter_map(|branch| BranchConstraint::from_branch(branch, &types))
+                .collect();
+            if constraints.len() != branches.len() ||
+                constraints.iter().any(|c| c.name != constraints[0].name)
+            {
+                continue;
+            }
+
+            if !BranchConstraint::covers_all(&constraints) {
+                errors.push(ValidationError {
+                    code: 1,
+                    message: format!(
+                        "Branches of XFlow node '{}' do not cover all values of '{}' and \
+                         there is no default branch",
+                        node,
+                        constraints[0].name
+                    ),
+                    paths: vec![format!("/nodes/{}", node)],
+                });
+            }
+        }
+
+        errors
+    }
+
+    /// Group the non-default branches of the flow by their source node
+    fn branches_by_node(doc: &XFlowDocument) -> HashMap<i32, Vec<&XFlowBranch>> {
+        let mut res = HashMap::<i32, Vec<&XFlowBranch>>::new();
+        for branch in doc.body.branches.iter().filter(|branch| !branch.default) {
+            res.entry(branch.edge.0).or_insert_with(Vec::new).push(branch);
+        }
+        res
+    }
 
     //     X  all_edges_have_nodes(flow),
     //     X  has_one_entry_node(flow),
@@ -334,6 +971,11 @@ impl Validation {
     //     X  all_nodes_have_at_least_one_edge(flow)
     //     X  no_variable_redefinition(flow)
     //     X  all_flox_variables_exist
+    //     X  all_call_variables_exist
+    //     X  all_flox_expressions_type_check
+    //     X  all_branches_are_well_formed
+    //     X  branch_conditions_do_not_overlap
+    //     X  branch_conditions_cover_all_values
 }
 
 impl Default for Validation {
@@ -341,3 +983,194 @@ impl Default for Validation {
         Validation { errors: Vec::<ValidationError>::new() }
     }
 }
+
+/// The values of a single variable for which a branch matches. Only branches matching
+/// on an `xvar`, or with conditions of the form `$Var`, `!$Var`, `$Var OP <integer>` and
+/// `$Var == <string>` can be analysed. Integer ranges are only analysed for variables
+/// declared as integers, a decimal variable has values between the integers.
+#[derive(Debug)]
+struct BranchConstraint {
+    name: String,
+    values: BranchValues,
+}
+
+#[derive(Debug)]
+enum BranchValues {
+    Boolean(Vec<bool>),
+    /// Inclusive integer ranges
+    Integer(Vec<(i64, i64)>),
+    String(String),
+}
+
+impl BranchConstraint {
+    fn from_branch(
+        branch: &XFlowBranch,
+        types: &HashMap<String, XFlowValueType>,
+    ) -> Option<BranchConstraint> {
+        let constraint = match (&branch.condition, &branch.xvar) {
+            (&Some(ref condition), _) => BranchConstraint::from_condition(condition),
+            (&None, &Some(ref xvar)) => {
+                let values = match xvar.value {
+                    XFlowValue::Boolean(b) => BranchValues::Boolean(vec![b]),
+                    XFlowValue::Integer(i) => BranchValues::Integer(vec![(i, i)]),
+                    XFlowValue::String(ref s) => BranchValues::String(s.clone()),
+                    XFlowValue::Decimal(_) |
+                    XFlowValue::List(_) |
+                    XFlowValue::Record(_) => return None,
+                };
+                Some(BranchConstraint {
+                    name: xvar.name.clone(),
+                    values: values,
+                })
+            }
+            (&None, &None) => None,
+        };
+
+        match constraint {
+            Some(BranchConstraint {
+                ref name,
+                values: BranchValues::Integer(_),
+            }) if types.get(name) != Some(&XFlowValueType::Integer) => None,
+            constraint => constraint,
+        }
+    }
+
+    fn from_condition(condition: &str) -> Option<BranchConstraint> {
+        let expr = match flox::compile(condition) {
+            Ok(expr) => expr,
+            Err(_) => return None,
+        };
+
+        let (op, l, r) = match expr.kind {
+            ExprKind::Variable(ref name) => {
+                return Some(BranchConstraint {
+                    name: name.clone(),
+                    values: BranchValues::Boolean(vec![true]),
+                })
+            }
+            ExprKind::Unary(UnaryOp::Not, ref e) => {
+                return match e.kind {
+                    ExprKind::Variable(ref name) => Some(BranchConstraint {
+                        name: name.clone(),
+                        values: BranchValues::Boolean(vec![false]),
+                    }),
+                    _ => None,
+                }
+            }
+            ExprKind::Binary(op, ref l, ref r) => (op, l, r),
+            _ => return None,
+        };
+
+        let (name, literal, op) = match (&l.kind, &r.kind) {
+            (&ExprKind::Variable(ref name), literal) => (name, literal, op),
+            (literal, &ExprKind::Variable(ref name)) => (name, literal, mirror_operator(op)),
+            _ => return None,
+        };
+
+        let values = match *literal {
+            ExprKind::Boolean(b) => {
+                match op {
+                    BinaryOp::Eq => BranchValues::Boolean(vec![b]),
+                    BinaryOp::Ne => BranchValues::Boolean(vec![!b]),
+                    _ => return None,
+                }
+            }
+            ExprKind::String(ref s) => {
+                match op {
+                    BinaryOp::Eq => BranchValues::String(s.clone()),
+                    _ => return None,
+                }
+            }
+            ExprKind::Integer(i) => {
+                BranchValues::Integer(match op {
+                    BinaryOp::Eq => vec![(i, i)],
+                    BinaryOp::Ne if i == i64::MIN => vec![(i + 1, i64::MAX)],
+                    BinaryOp::Ne if i == i64::MAX => vec![(i64::MIN, i - 1)],
+                    BinaryOp::Ne => vec![(i64::MIN, i - 1), (i + 1, i64::MAX)],
+                    BinaryOp::Gte => vec![(i, i64::MAX)],
+                    BinaryOp::Lte => vec![(i64::MIN, i)],
+                    BinaryOp::Gt if i == i64::MAX => vec![],
+                    BinaryOp::Gt => vec![(i + 1, i64::MAX)],
+                    BinaryOp::Lt if i == i64::MIN => vec![],
+                    BinaryOp::Lt => vec![(i64::MIN, i - 1)],
+                    _ => return None,
+                })
+            }
+            _ => return None,
+        };
+
+        Some(BranchConstraint {
+            name: name.clone(),
+            values: values,
+        })
+    }
+
+    fn overlaps(&self, other: &BranchConstraint) -> bool {
+        if self.name != other.name {
+            return false;
+        }
+
+        match (&self.values, &other.values) {
+            (&BranchValues::Boolean(ref a), &BranchValues::Boolean(ref b)) => {
+                a.iter().any(|v| b.contains(v))
+            }
+            (&BranchValues::Integer(ref a), &BranchValues::Integer(ref b)) => {
+                a.iter().any(|&(alo, ahi)| {
+                    b.iter().any(|&(blo, bhi)| alo <= bhi && blo <= ahi)
+                })
+            }
+            (&BranchValues::String(ref a), &BranchValues::String(ref b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn covers_all(constraints: &[BranchConstraint]) -> bool {
+        let mut booleans = Vec::<bool>::new();
+        let mut ranges = Vec::<(i64, i64)>::new();
+
+        for constraint in constraints {
+            match constraint.values {
+                BranchValues::Boolean(ref values) => booleans.extend(values),
+                BranchValues::Integer(ref values) => ranges.extend(values),
+                BranchValues::String(_) => return false,
+            }
+        }
+
+        match (booleans.is_empty(), ranges.is_empty()) {
+            (false, true) => booleans.contains(&true) && booleans.contains(&false),
+            (true, false) => {
+                ranges.sort();
+                let mut next = i64::MIN;
+                for (lo, hi) in ranges {
+                    if lo > next {
+                        return false;
+                    }
+                    if hi == i64::MAX {
+                        return true;
+                    }
+                    next = next.max(hi + 1);
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+fn mirror_operator(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Gte => BinaryOp::Lte,
+        BinaryOp::Lte => BinaryOp::Gte,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::Lt => BinaryOp::Gt,
+        op => op,
+    }
+}
+
+/// Check whether two conditions parse to the same expression
+fn same_condition(a: &str, b: &str) -> bool {
+    match (flox::compile(a), flox::compile(b)) {
+        (Ok(a), Ok(b)) => a.to_string() == b.to_string(),
+        _ => a.trim() == b.trim(),
+    }
+}
@@ -0,0 +1,218 @@
+extern crate env_logger;
+extern crate tempdir;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::structure::domain::Domain;
+use gears::structure::model::ModelDocument;
+use gears::runtime::docstore::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::actiondispatch;
+use gears::util::fs::model_from_fs;
+use tempdir::TempDir;
+
+mod common;
+use crate::common::load_doc;
+
+fn load_flows() -> Vec<XFlowDocument> {
+    vec![
+        load_doc::<XFlowDocument>("resource/docs/xflow/capability_flows/add_1.json"),
+        load_doc::<XFlowDocument>("resource/docs/xflow/capability_flows/xflow-call-xflow.json"),
+    ]
+}
+
+fn fill_store<B: DocStoreBackend>(store: &mut DocStore<B>) -> XFlowDocument {
+    let flows = load_flows();
+    for xflow in &flows {
+        store.add(xflow).unwrap();
+    }
+
+    let mut updated = flows[0].clone();
+    updated.version += 1;
+    updated.body.nodes.retain(|node| node.nodetype != XFlowNodeType::Flox);
+    store.add(&updated).unwrap();
+    updated
+}
+
+#[test]
+fn test_docstore_lookup_and_history() {
+    let _ = env_logger::try_init();
+    let mut store = DocStore::in_memory();
+    let latest = fill_store(&mut store);
+
+    assert_eq!(store.get::<XFlow>(&latest.id).unwrap(), Some(latest.clone()));
+    assert_eq!(store.get_by_name::<XFlow>("add_1").unwrap(), Some(latest.clone()));
+    assert_eq!(store.get_by_name::<XFlow>("unknown").unwrap(), None);
+    assert_eq!(store.get::<Domain>(&latest.id).unwrap(), None);
+
+    assert_eq!(store.versions::<XFlow>(&latest.id), vec![1, 2]);
+    let history = store.history::<XFlow>(&latest.id).unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history[0].body.nodes.len() > history[1].body.nodes.len());
+
+    let names: Vec<String> = store
+        .list(DocType::XFlow)
+        .iter()
+        .map(|header| header.name.clone())
+        .collect();
+    assert_eq!(names, vec!["add_1", "xflow_call_xflow"]);
+
+    store.remove::<XFlow>(&latest.id).unwrap();
+    assert_eq!(store.get::<XFlow>(&latest.id).unwrap(), None);
+    assert_eq!(store.list(DocType::XFlow).len(), 1);
+}
+
+#[test]
+fn test_docstore_conflicts_and_names() {
+    let _ = env_logger::try_init();
+    let mut store = DocStore::in_memory();
+    let latest = fill_store(&mut store);
+
+    // Storing a stored version again is allowed when nothing changed
+    assert_eq!(store.add(&latest), Ok(()));
+
+    let mut changed = latest.clone();
+    changed.body.nodes.clear();
+    assert_eq!(
+        store.add(&changed),
+        Err(DocStoreError::VersionConflict {
+            doctype: DocType::XFlow,
+            id: latest.id,
+            version: 2,
+            latest: 2,
+        })
+    );
+
+    let other = XFlowDocument {
+        name: "add_1".to_owned(),
+        ..XFlowDocument::default()
+    };
+    assert_eq!(store.add(&other), Ok(()));
+    match store.get_by_name::<XFlow>("add_1") {
+        Err(DocStoreError::AmbiguousName { doctype, name, ids }) => {
+            assert_eq!(doctype, DocType::XFlow);
+            assert_eq!(name, "add_1");
+            assert_eq!(ids.len(), 2);
+            assert!(ids.contains(&latest.id));
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_docstore_directory_backend() {
+    let _ = env_logger::try_init();
+    let root = TempDir::new("docstore").expect("Should have created a temp directory");
+    let path = format!("{}", root.path().display());
+
+    let model = model_from_fs("resource/projects/basic").unwrap();
+    let latest = {
+        let mut store = DocStore::open(DirectoryBackend::open(&path).unwrap()).unwrap();
+        store.add_model(&model).unwrap();
+        fill_store(&mut store)
+    };
+
+    let store = DocStore::open(DirectoryBackend::open(&path).unwrap()).unwrap();
+    assert_eq!(store.get_model(&model.id).unwrap(), Some(model.clone()));
+    assert_eq!(store.get_domain(&model.body.domain.id).unwrap(), Some(model.body.domain.clone()));
+    assert_eq!(store.get_config(&model.body.config.id).unwrap(), Some(model.body.config.clone()));
+    assert_eq!(store.list(DocType::Page).len(), model.body.pages.len());
+    assert_eq!(store.list(DocType::Translation).len(), model.body.translations.len());
+    assert_eq!(store.get_xflow(&latest.id).unwrap(), Some(latest.clone()));
+    assert_eq!(store.versions::<XFlow>(&latest.id), vec![1, 2]);
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn test_docstore_sled_backend() {
+    let _ = env_logger::try_init();
+    let mut store = DocStore::open(SledBackend::temporary().unwrap()).unwrap();
+    let latest = fill_store(&mut store);
+
+    assert_eq!(store.get_by_name::<XFlow>("add_1").unwrap(), Some(latest.clone()));
+    assert_eq!(store.versions::<XFlow>(&latest.id), vec![1, 2]);
+}
+
+#[test]
+fn test_call_resolves_from_docstore() {
+    let _ = env_logger::try_init();
+    let mut store = DocStore::in_memory();
+    for xflow in &load_flows() {
+        store.add(xflow).unwrap();
+    }
+
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher.register_receiver(
+        XFlowNodeType::Call,
+        actiondispatch::call::Call::from_source(&store),
+    );
+
+    let xflow = store
+        .get_by_name::<XFlow>("xflow_call_xflow")
+        .unwrap()
+        .unwrap();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "CounterValue".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(41),
+    });
+
+    let mut xfrunner = XFlowRunner::new(&xflow, &dispatcher, &state).unwrap();
+    xfrunner.run().unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(
+        output.get("CounterValue").unwrap().value,
+        XFlowValue::Integer(42)
+    );
+
+    let model = ModelDocument::default();
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher.register_receiver(XFlowNodeType::Call, actiondispatch::call::Call::new(&model));
+    let mut xfrunner = XFlowRunner::new(&xflow, &dispatcher, &state).unwrap();
+    assert!(xfrunner.run().is_err());
+}
+
+#[test]
+fn test_docstore_model_references() {
+    let _ = env_logger::try_init();
+    let mut store = DocStore::in_memory();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+    store.add_model(&model).unwrap();
+
+    // The model is stored as references to its documents, which are stored on their own
+    let refs = store.get::<ModelRefs>(&model.id).unwrap().unwrap();
+    assert_eq!(refs.get_header(), model.get_header());
+    assert_eq!(refs.body.config.id, model.body.config.id);
+    assert_eq!(refs.body.xflows.len(), model.body.xflows.len());
+    assert_eq!(store.list(DocType::Config).len(), 1);
+
+    // A new version of one of its documents is not part of the stored model version
+    let mut xflow = model.body.xflows[0].clone();
+    xflow.body.nodes.clear();
+    assert_eq!(store.save(&mut xflow), Ok(true));
+    assert_eq!(store.get_model(&model.id).unwrap(), Some(model.clone()));
+
+    let mut updated = model.clone();
+    updated.version += 1;
+    updated.body.xflows[0] = xflow.clone();
+    store.add_model(&updated).unwrap();
+    assert_eq!(store.get_model(&model.id).unwrap(), Some(updated));
+    assert_eq!(store.get_model_version(&model.id, model.version).unwrap(), Some(model.clone()));
+
+    store.remove::<XFlow>(&xflow.id).unwrap();
+    assert_eq!(
+        store.get_model(&model.id),
+        Err(DocStoreError::MissingDocument {
+            model: model.id,
+            key: DocKey::new(DocType::XFlow, xflow.id, xflow.version),
+        })
+    );
+}
@@ -1,11 +1,14 @@
 extern crate env_logger;
 extern crate gears;
+extern crate serde_json;
 
 use gears::structure::xflow::*;
 use gears::*;
 use gears::runtime::xfstate::*;
 use gears::parser::flox;
 
+use std::collections::HashMap;
+
 fn expect_context_integer(input: &str, context: &XFState, expected: i64) -> () {
     let _ = env_logger::try_init();
     match flox::parse_context(input, context) {
@@ -365,6 +368,7 @@ fn test_variable_error_reporting() {
         Err(err) => {
             match err {
                 flox::Error::ParseError(_) => assert!(true),
+                _ => assert!(false),
             }
         }
     }
@@ -374,6 +378,7 @@ fn test_variable_error_reporting() {
         Err(err) => {
             match err {
                 flox::Error::ParseError(_) => assert!(true),
+                _ => assert!(false),
             }
         }
     }
@@ -383,6 +388,7 @@ fn test_variable_error_reporting() {
         Err(err) => {
             match err {
                 flox::Error::ParseError(_) => assert!(true),
+                _ => assert!(false),
             }
         }
     }
@@ -394,6 +400,7 @@ fn test_variable_error_reporting() {
         Err(err) => {
             match err {
                 flox::Error::ParseError(_) => assert!(true),
+                _ => assert!(false),
             }
         }
     }
@@ -404,8 +411,407 @@ fn test_variable_error_reporting() {
             println!("flox::extract_variable_names Error {:?}", err);
             match err {
                 flox::Error::ParseError(_) => assert!(true),
+                _ => assert!(false),
+            }
+        }
+    }
+
+}
+
+#[test]
+// #TST-flox-ast
+fn test_compile_once_evaluate_many() {
+    let _ = env_logger::try_init();
+
+    let expr = flox::compile("$CounterValue * 2 > 4").unwrap();
+    assert_eq!(expr.variable_names(), vec!["CounterValue"]);
+    assert_eq!(expr.to_string(), "(($CounterValue * 2) > 4)");
+
+    let mut state = XFState::default();
+    for (value, expected) in vec![(1, false), (2, false), (3, true)] {
+        state.add(&XFlowVariable {
+            name: "CounterValue".to_owned(),
+            vtype: XFlowValueType::Integer,
+            value: XFlowValue::Integer(value),
+        });
+        assert_eq!(expr.evaluate(&state), Ok(XFlowValue::Boolean(expected)));
+    }
+}
+
+#[test]
+fn test_compile_ast() {
+    let _ = env_logger::try_init();
+
+    match flox::compile("!$TrueValue").unwrap().kind {
+        flox::ExprKind::Unary(flox::UnaryOp::Not, ref e) => {
+            assert_eq!(e.kind, flox::ExprKind::Variable("TrueValue".to_owned()));
+            assert_eq!(e.offset, 1);
+        }
+        kind => panic!("Unexpected expression {:?}", kind),
+    }
+
+    match flox::compile("1 + 2 * 3").unwrap().kind {
+        flox::ExprKind::Binary(flox::BinaryOp::Add, _, ref r) => {
+            assert_eq!(r.to_string(), "(2 * 3)");
+            assert_eq!(r.offset, 6);
+        }
+        kind => panic!("Unexpected expression {:?}", kind),
+    }
+}
+
+#[test]
+fn test_evaluation_error_offset() {
+    let _ = env_logger::try_init();
+    let state = XFState::default();
+
+    let err = flox::evaluate("1 + $Missing", &state).unwrap_err();
+    assert_eq!(err.offset, 4);
+
+    let err = flox::evaluate("1 + true", &state).unwrap_err();
+    assert_eq!(err.offset, 2);
+
+    let err = flox::compile("1 + ").unwrap_err();
+    assert_eq!(err.offset, 4);
+}
+
+fn expect_context_string(input: &str, context: &XFState, expected: &str) -> () {
+    let _ = env_logger::try_init();
+    match flox::parse_context(input, context) {
+        Err(err) => {
+            println!("Error parsing result for ('{:?}') is {:?}", input, err);
+            assert!(false);
+        }
+        Ok(res) => {
+            match res {
+                XFlowValue::String(res) => assert_eq!(res, expected),
+                _ => assert!(false),
             }
         }
     }
+}
+
+#[test]
+// #TST-flox-string
+fn test_strings() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "first_name".to_owned(),
+        vtype: XFlowValueType::String,
+        value: XFlowValue::String("Ada".to_owned()),
+    });
+    state.add(&XFlowVariable {
+        name: "Line2".to_owned(),
+        vtype: XFlowValueType::String,
+        value: XFlowValue::String("Lovelace".to_owned()),
+    });
 
+    expect_context_string("\"abc\"", &state, "abc");
+    expect_context_string("'abc'", &state, "abc");
+    expect_context_string(r#""say \"hi\"\n""#, &state, "say \"hi\"\n");
+    expect_context_string(r#"'it\'s'"#, &state, "it's");
+    expect_context_string(r#""a\\b""#, &state, "a\\b");
+    expect_context_string("$first_name + ' ' + $Line2", &state, "Ada Lovelace");
+
+    expect_context_boolean("$first_name == 'Ada'", &state, true);
+    expect_context_boolean("$first_name != 'Ada'", &state, false);
+    expect_context_boolean("$first_name < $Line2", &state, true);
+    expect_context_boolean("'b' >= 'a'", &state, true);
+}
+
+#[test]
+fn test_string_functions() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Email".to_owned(),
+        vtype: XFlowValueType::String,
+        value: XFlowValue::String("Ada@Example.org".to_owned()),
+    });
+
+    expect_context_integer("len($Email)", &state, 15);
+    expect_context_integer("len('') + 1", &state, 1);
+    expect_context_string("upper($Email)", &state, "ADA@EXAMPLE.ORG");
+    expect_context_string("lower( $Email )", &state, "ada@example.org");
+    expect_context_boolean("contains($Email, '@')", &state, true);
+    expect_context_boolean("contains(lower($Email), 'EXAMPLE')", &state, false);
+    expect_context_string("substr($Email, 0, 3)", &state, "Ada");
+    expect_context_string("substr($Email, 12, 10)", &state, "org");
+    expect_context_boolean("len($Email) > 5 && contains($Email, '.')", &state, true);
+
+    assert!(flox::parse_context("substr($Email, 0)", &state).is_err());
+    assert!(flox::parse_context("len(1)", &state).is_err());
+    assert!(flox::parse_context("'a' + 1", &state).is_err());
+    assert!(flox::compile("reverse('abc')").is_err());
+}
+
+#[test]
+fn test_string_display() {
+    let _ = env_logger::try_init();
+
+    let expr = flox::compile(r#"upper('a"b') + "\n""#).unwrap();
+    assert_eq!(expr.to_string(), r#"(upper("a\"b") + "\n")"#);
+    assert_eq!(flox::compile(&expr.to_string()).unwrap().to_string(), expr.to_string());
+}
+
+#[test]
+// #TST-flox-type-checking
+fn test_infer_type() {
+    let _ = env_logger::try_init();
+    let mut types = HashMap::<String, XFlowValueType>::new();
+    types.insert("Count".to_owned(), XFlowValueType::Integer);
+    types.insert("Name".to_owned(), XFlowValueType::String);
+    types.insert("Flag".to_owned(), XFlowValueType::Boolean);
+
+    let infer = |input: &str| flox::compile(input).unwrap().infer_type(&types);
+
+    assert_eq!(infer("$Count + 1"), Ok(XFlowValueType::Integer));
+    assert_eq!(infer("$Name + 'x'"), Ok(XFlowValueType::String));
+    assert_eq!(infer("$Count > 1 && !$Flag"), Ok(XFlowValueType::Boolean));
+    assert_eq!(infer("$Flag == true"), Ok(XFlowValueType::Boolean));
+    assert_eq!(infer("substr($Name, 0, len($Name) - 1)"), Ok(XFlowValueType::String));
+
+    let err = infer("1 + ($Count + $Flag)").unwrap_err();
+    assert_eq!(err.offset, 12);
+    assert!(infer("$Name == 1").is_err());
+    assert!(infer("!$Count").is_err());
+    assert!(infer("contains($Name)").is_err());
+    assert!(infer("upper($Count)").is_err());
+    assert!(infer("$Missing").is_err());
+}
+
+fn expect_context_decimal(input: &str, context: &XFState, expected: &str) -> () {
+    let _ = env_logger::try_init();
+    match flox::parse_context(input, context) {
+        Err(err) => {
+            println!("Error parsing result for ('{:?}') is {:?}", input, err);
+            assert!(false);
+        }
+        Ok(res) => {
+            match res {
+                XFlowValue::Decimal(res) => assert_eq!(res.to_string(), expected),
+                _ => assert!(false),
+            }
+        }
+    }
+}
+
+#[test]
+// #TST-flox-decimal
+fn test_decimals() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Price".to_owned(),
+        vtype: XFlowValueType::Decimal,
+        value: XFlowValue::Decimal("19.99".parse().unwrap()),
+    });
+
+    expect_context_decimal("0.1 + 0.2", &state, "0.3");
+    expect_context_decimal("$Price * 3", &state, "59.97");
+    expect_context_decimal("10 / 4.0", &state, "2.5");
+    expect_context_decimal("1.0 / 3", &state, "0.33333333333333333333");
+    expect_context_decimal("1.5 ^ 2", &state, "2.25");
+    expect_context_decimal("round(2.345, 2)", &state, "2.34");
+    expect_context_decimal("round_half_up(2.345, 2)", &state, "2.35");
+    expect_context_decimal("floor(0 - 2.345, 1)", &state, "-2.4");
+    expect_context_decimal("ceil($Price, 0)", &state, "20");
+    expect_context_decimal("trunc(7, 2)", &state, "7");
+    expect_context_decimal("decimal('12.50')", &state, "12.50");
+    expect_context_integer("10 / 4", &state, 2);
+
+    expect_context_boolean("1.0 == 1.00", &state, true);
+    expect_context_boolean("1 == 1.0", &state, true);
+    expect_context_boolean("$Price > 19.9", &state, true);
+
+    assert!(flox::parse_context("1.0 / 0", &state).is_err());
+    assert!(flox::parse_context("1.5 ^ 0.5", &state).is_err());
+    assert!(flox::parse_context("decimal('abc')", &state).is_err());
+
+    let mut types = HashMap::<String, XFlowValueType>::new();
+    types.insert("Price".to_owned(), XFlowValueType::Decimal);
+    let expr = flox::compile("$Price * 2 + 1").unwrap();
+    assert_eq!(expr.infer_type(&types).unwrap(), XFlowValueType::Decimal);
+    assert!(flox::compile("$Price && true").unwrap().infer_type(&types).is_err());
+}
+
+#[test]
+// #TST-flox-arithmetic-errors
+fn test_arithmetic_errors() {
+    let _ = env_logger::try_init();
+
+    match flox::parse("1 / 0") {
+        Err(flox::Error::DivisionByZero(_)) => assert!(true),
+        res => panic!("Expected a division by zero, found {:?}", res),
+    }
+    match flox::parse("1.5 / (2 - 2)") {
+        Err(flox::Error::DivisionByZero(_)) => assert!(true),
+        res => panic!("Expected a division by zero, found {:?}", res),
+    }
+    match flox::parse("9223372036854775807 + 1") {
+        Err(flox::Error::Overflow(_)) => assert!(true),
+        res => panic!("Expected an overflow, found {:?}", res),
+    }
+    match flox::parse("-9223372036854775808 / -1") {
+        Err(flox::Error::Overflow(_)) => assert!(true),
+        res => panic!("Expected an overflow, found {:?}", res),
+    }
+    match flox::parse("10 ^ 19") {
+        Err(flox::Error::Overflow(_)) => assert!(true),
+        res => panic!("Expected an overflow, found {:?}", res),
+    }
+    match flox::parse("-(-9223372036854775808)") {
+        Err(flox::Error::Overflow(_)) => assert!(true),
+        res => panic!("Expected an overflow, found {:?}", res),
+    }
+    match flox::parse("2 ^ -1") {
+        Err(flox::Error::InvalidExponent(_)) => assert!(true),
+        res => panic!("Expected an invalid exponent, found {:?}", res),
+    }
+    match flox::parse("2 ^ 4294967296") {
+        Err(flox::Error::InvalidExponent(_)) => assert!(true),
+        res => panic!("Expected an invalid exponent, found {:?}", res),
+    }
+    match flox::parse("1.5 ^ -2") {
+        Err(flox::Error::InvalidExponent(_)) => assert!(true),
+        res => panic!("Expected an invalid exponent, found {:?}", res),
+    }
+
+    let err = flox::evaluate("3 + 1 / 0", &XFState::default()).unwrap_err();
+    assert_eq!(err.kind, flox::ErrorKind::DivisionByZero);
+    assert_eq!(err.offset, 6);
+}
+
+#[test]
+// #TST-flox-short-circuit
+fn test_boolean_short_circuit() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "N".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(0),
+    });
+    state.add(&XFlowVariable {
+        name: "X".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(10),
+    });
+    state.add(&XFlowVariable {
+        name: "L".to_owned(),
+        vtype: XFlowValueType::List,
+        value: XFlowValue::List(Vec::new()),
+    });
+
+    // The right operand is not evaluated when the left one decides the result
+    expect_context_boolean("$N != 0 && $X / $N > 1", &state, false);
+    expect_context_boolean("count($L) > 0 && $L[0] == 1", &state, false);
+    expect_context_boolean("$N == 0 || $X / $N > 1", &state, true);
+    expect_context_boolean("count($L) == 0 || $L[0] == 1", &state, true);
+
+    // Otherwise it is, with its errors
+    match flox::parse_context("$N == 0 && $X / $N > 1", &state) {
+        Err(flox::Error::DivisionByZero(_)) => {}
+        res => panic!("Expected a division by zero, found {:?}", res),
+    }
+    assert!(flox::parse_context("count($L) == 0 && $L[0] == 1", &state).is_err());
+    assert!(flox::parse_context("$N != 0 || $X / $N > 1", &state).is_err());
+}
+
+#[test]
+// #TST-flox-unary-minus
+fn test_unary_minus() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Count".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(4),
+    });
+
+    expect_integer("-5", -5);
+    expect_integer("-5 + 3", -2);
+    expect_integer("3 - -5", 8);
+    expect_integer("3-5", -2);
+    expect_integer("-(2 + 3)", -5);
+    expect_integer("- 2 * 3", -6);
+    expect_integer("-9223372036854775808", i64::min_value());
+    expect_context_integer("-$Count", &state, -4);
+    expect_context_integer("10 - -$Count", &state, 14);
+    expect_context_decimal("-1.25 * 2", &state, "-2.50");
+    expect_context_decimal("-(0.5)", &state, "-0.5");
+    expect_context_boolean("-$Count < 0", &state, true);
+
+    let expr = flox::compile("-$Count - -1").unwrap();
+    assert_eq!(expr.to_string(), "(-($Count) - -1)");
+    assert_eq!(flox::compile(&expr.to_string()).unwrap().to_string(), expr.to_string());
+
+    let mut types = HashMap::<String, XFlowValueType>::new();
+    types.insert("Count".to_owned(), XFlowValueType::Integer);
+    assert_eq!(expr.infer_type(&types).unwrap(), XFlowValueType::Integer);
+    assert!(flox::compile("-true").unwrap().infer_type(&types).is_err());
+    assert!(flox::parse("-'abc'").is_err());
+}
+
+#[test]
+// #TST-flox-lists-and-records
+fn test_lists_and_records() {
+    let _ = env_logger::try_init();
+    let mut state = XFState::default();
+    let order: XFlowValue = serde_json::from_str(
+        r#"{
+            "customer": { "name": "Ada" },
+            "items": [
+                { "price": "9.95", "quantity": 2 },
+                { "price": "0.10", "quantity": 10 }
+            ]
+        }"#,
+    ).unwrap();
+    state.add(&XFlowVariable {
+        name: "order".to_owned(),
+        vtype: XFlowValueType::Record,
+        value: order,
+    });
+    state.add(&XFlowVariable {
+        name: "tags".to_owned(),
+        vtype: XFlowValueType::List,
+        value: XFlowValue::List(vec![
+            XFlowValue::String("new".to_owned()),
+            XFlowValue::String("gift".to_owned()),
+        ]),
+    });
+
+    expect_context_string("$order.customer.name", &state, "Ada");
+    expect_context_string("$order['customer'].name", &state, "Ada");
+    expect_context_string("$tags[1]", &state, "gift");
+    expect_context_string("upper($tags[1 - 1])", &state, "NEW");
+    expect_context_integer("$order.items[1].quantity * 2", &state, 20);
+    expect_context_integer("count($order.items)", &state, 2);
+    expect_context_decimal("decimal($order.items[0].price) * 2", &state, "19.90");
+    expect_context_boolean("$tags[0] == 'new'", &state, true);
+    expect_context_boolean("$order.items[0] != $order.items[1]", &state, true);
+
+    assert!(flox::parse_context("$tags[2]", &state).is_err());
+    assert!(flox::parse_context("$tags[-1]", &state).is_err());
+    assert!(flox::parse_context("$tags['a']", &state).is_err());
+    assert!(flox::parse_context("$order.total", &state).is_err());
+    assert!(flox::parse_context("$tags.name", &state).is_err());
+
+    let expr = flox::compile("$order.items[0 + 1].price").unwrap();
+    assert_eq!(expr.to_string(), "$order.items[(0 + 1)].price");
+    assert_eq!(flox::compile(&expr.to_string()).unwrap().to_string(), expr.to_string());
+    assert_eq!(expr.variable_names(), vec!["order"]);
+
+    let mut types = HashMap::<String, XFlowValueType>::new();
+    types.insert("order".to_owned(), XFlowValueType::Record);
+    types.insert("tags".to_owned(), XFlowValueType::List);
+    assert_eq!(expr.infer_type(&types).unwrap(), XFlowValueType::Any);
+    assert_eq!(
+        flox::compile("$order.items[0].quantity > 1").unwrap().infer_type(&types).unwrap(),
+        XFlowValueType::Boolean
+    );
+    assert!(flox::compile("$tags['a']").unwrap().infer_type(&types).is_err());
+    assert!(flox::compile("$order[0]").unwrap().infer_type(&types).is_err());
+    assert!(flox::compile("$tags.name").unwrap().infer_type(&types).is_err());
+    assert!(flox::compile("count($order)").unwrap().infer_type(&types).is_err());
 }
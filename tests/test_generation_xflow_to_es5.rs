@@ -30,3 +30,22 @@ fn test_load_basic_page_document() {
     // println!("JS! {}", s_es5);
     println!("JS LATEST! {}", s_es);
 }
+
+#[test]
+fn test_decimal_flows() {
+    let _ = env_logger::try_init();
+
+    let xflow = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_decimal.json");
+    let s_es = xflow_to_es5::output(&xflow);
+    let _ = parser::parse(xflow_to_es5::output_es5(&xflow)).expect("Must compile");
+
+    // Decimal locals keep their exact value as strings, and are numbers in expressions
+    assert!(s_es.contains("this.input_vars.ReturnValue = \"0.00\""));
+    assert!(s_es.contains("Number(this.local_vars.Price)"));
+    assert!(s_es.contains("this.local_vars.ReturnValue = String("));
+
+    let xflow = load_doc::<XFlowDocument>("resource/docs/xflow/flows/record_total.json");
+    let s_es = xflow_to_es5::output(&xflow);
+    let _ = parser::parse(xflow_to_es5::output_es5(&xflow)).expect("Must compile");
+    assert!(s_es.contains("Number(this.local_vars.order.items[0].price)"));
+}
@@ -0,0 +1,189 @@
+extern crate env_logger;
+extern crate serde_json;
+extern crate serde_yaml;
+
+extern crate gears;
+use gears::parser::flox;
+use gears::runtime::trace::XFlowVariableChange;
+use gears::runtime::xfstate::XFState;
+use gears::structure::decimal::*;
+use gears::structure::xflow::*;
+
+use std::time::{Duration, Instant};
+
+fn dec(s: &str) -> Decimal {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_parse_and_display() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(dec("12.50").to_string(), "12.50");
+    assert_eq!(dec("-0.05").to_string(), "-0.05");
+    assert_eq!(dec("7").to_string(), "7");
+    assert_eq!(dec("12.50").normalize().to_string(), "12.5");
+
+    assert!("1.".parse::<Decimal>().is_err());
+    assert!(".5".parse::<Decimal>().is_err());
+    assert!("1e5".parse::<Decimal>().is_err());
+    assert!("abc".parse::<Decimal>().is_err());
+}
+
+#[test]
+fn test_equality_and_ordering() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(dec("1.0"), dec("1.00"));
+    assert_eq!(dec("1.0"), Decimal::from_i64(1));
+    assert!(dec("-1.5") < dec("-1.25"));
+    assert!(dec("0.1") > dec("0.09"));
+}
+
+#[test]
+fn test_arithmetic() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(dec("0.1").checked_add(&dec("0.2")).unwrap().to_string(), "0.3");
+    assert_eq!(dec("1.50").checked_sub(&dec("2")).unwrap().to_string(), "-0.50");
+    assert_eq!(dec("1.5").checked_mul(&dec("1.5")).unwrap().to_string(), "2.25");
+    assert_eq!(dec("10").checked_div(&dec("4")).unwrap().to_string(), "2.5");
+    assert_eq!(
+        dec("1").checked_div(&dec("3")).unwrap().to_string(),
+        "0.33333333333333333333"
+    );
+    assert_eq!(
+        dec("2").checked_div(&dec("3")).unwrap().to_string(),
+        "0.66666666666666666667"
+    );
+    assert_eq!(dec("1.1").checked_pow(2).unwrap().to_string(), "1.21");
+    assert_eq!(dec("1").checked_div(&dec("0")), None);
+}
+
+#[test]
+fn test_large_scales() {
+    let _ = env_logger::try_init();
+
+    assert_eq!(Decimal::new(1, 100), Decimal::from_i64(0));
+    assert_eq!(Decimal::new(-1, 67).scale(), MAX_SCALE);
+    assert_eq!(Decimal::new(i128::max_value(), 200), Decimal::from_i64(0));
+    assert_eq!(
+        Decimal::new(i128::max_value(), 66).to_string(),
+        "0.0000000000000000000000000002"
+    );
+
+    let d = Decimal::new(i128::max_value(), MAX_SCALE);
+    assert_eq!(d.round(0, RoundingMode::HalfEven).to_string(), "17014118346");
+    assert_eq!(d.round(0, RoundingMode::Up).to_string(), "17014118347");
+    assert_eq!(Decimal::new(1, MAX_SCALE).round(0, RoundingMode::Up).to_string(), "1");
+}
+
+#[test]
+fn test_large_exponents() {
+    let _ = env_logger::try_init();
+
+    let start = Instant::now();
+    assert_eq!(dec("1.0").checked_pow(MAX_EXPONENT), Some(dec("1")));
+    assert_eq!(dec("0.5").checked_pow(MAX_EXPONENT), Some(dec("0")));
+    assert_eq!(dec("1.1").checked_pow(MAX_EXPONENT), None);
+    assert_eq!(dec("1.0").checked_pow(MAX_EXPONENT + 1), None);
+    assert_eq!(dec("1.0").checked_pow(u32::max_value()), None);
+
+    match flox::evaluate("1.0 ^ 4294967295", &XFState::default()) {
+        Err(err) => assert_eq!(err.kind, flox::ErrorKind::InvalidExponent),
+        res => panic!("Expected an invalid exponent, found {:?}", res),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_rounding_modes() {
+    let _ = env_logger::try_init();
+
+    let cases = vec![
+        ("2.5", RoundingMode::HalfEven, "2"),
+        ("3.5", RoundingMode::HalfEven, "4"),
+        ("2.5", RoundingMode::HalfUp, "3"),
+        ("-2.5", RoundingMode::HalfUp, "-3"),
+        ("2.7", RoundingMode::Down, "2"),
+        ("-2.7", RoundingMode::Down, "-2"),
+        ("2.1", RoundingMode::Up, "3"),
+        ("-2.1", RoundingMode::Floor, "-3"),
+        ("2.1", RoundingMode::Floor, "2"),
+        ("-2.1", RoundingMode::Ceiling, "-2"),
+        ("2.1", RoundingMode::Ceiling, "3"),
+    ];
+
+    for (input, mode, expected) in cases {
+        assert_eq!(dec(input).round(0, mode).to_string(), expected);
+    }
+}
+
+#[test]
+fn test_decimal_variable_serialization() {
+    let _ = env_logger::try_init();
+
+    let xvar: XFlowVariable =
+        serde_json::from_str(r#"{"name": "Price", "vtype": "decimal", "value": "12.50"}"#).unwrap();
+    assert_eq!(xvar.value, XFlowValue::Decimal(dec("12.50")));
+    assert_eq!(
+        serde_json::to_string(&xvar).unwrap(),
+        r#"{"name":"Price","vtype":"decimal","value":"12.50"}"#
+    );
+
+    let xvar: XFlowVariable =
+        serde_json::from_str(r#"{"name": "Price", "vtype": "decimal", "value": 12.5}"#).unwrap();
+    assert_eq!(xvar.value, XFlowValue::Decimal(dec("12.5")));
+
+    let xvar: XFlowVariable =
+        serde_json::from_str(r#"{"name": "Price", "vtype": "decimal", "value": 3}"#).unwrap();
+    assert_eq!(xvar.value, XFlowValue::Decimal(dec("3")));
+
+    let res = serde_json::from_str::<XFlowVariable>(
+        r#"{"name": "Price", "vtype": "decimal", "value": "twelve"}"#,
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_nested_decimal_serialization() {
+    let _ = env_logger::try_init();
+
+    let mut order = XFlowRecord::new();
+    order.insert("total".to_owned(), XFlowValue::Decimal(dec("12.50")));
+    order.insert("code".to_owned(), XFlowValue::String("12.50".to_owned()));
+    order.insert(
+        "prices".to_owned(),
+        XFlowValue::List(vec![XFlowValue::Decimal(dec("0.1")), XFlowValue::Integer(2)]),
+    );
+    let xvar = XFlowVariable {
+        name: "order".to_owned(),
+        vtype: XFlowValueType::Record,
+        value: XFlowValue::Record(order),
+    };
+
+    let json = serde_json::to_string(&xvar).unwrap();
+    assert!(json.contains(r#""total":{"$decimal":"12.50"}"#));
+    assert_eq!(serde_json::from_str::<XFlowVariable>(&json).unwrap(), xvar);
+    let yaml = serde_yaml::to_string(&xvar).unwrap();
+    assert_eq!(serde_yaml::from_str::<XFlowVariable>(&yaml).unwrap(), xvar);
+
+    let mut state = XFState::default();
+    state.add(&xvar);
+    let json = serde_json::to_string(&state).unwrap();
+    assert_eq!(serde_json::from_str::<XFState>(&json).unwrap(), state);
+
+    let change = XFlowVariableChange {
+        name: "order".to_owned(),
+        old: None,
+        new: Some(xvar.clone()),
+    };
+    let json = serde_json::to_string(&change).unwrap();
+    assert_eq!(serde_json::from_str::<XFlowVariableChange>(&json).unwrap(), change);
+
+    let value: XFlowValue = serde_json::from_str(r#"{"total": "12.50"}"#).unwrap();
+    assert_eq!(value.get_path(&["total"]), Some(&XFlowValue::String("12.50".to_owned())));
+    let value: XFlowValue = serde_json::from_str(r#"{"total": {"$decimal": "12.50"}}"#).unwrap();
+    assert_eq!(value.get_path(&["total"]), Some(&XFlowValue::Decimal(dec("12.50"))));
+    assert!(serde_json::from_str::<XFlowValue>(r#"{"$decimal": "twelve"}"#).is_err());
+}
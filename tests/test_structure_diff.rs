@@ -0,0 +1,129 @@
+extern crate env_logger;
+#[macro_use]
+extern crate serde_json;
+
+extern crate gears;
+
+use gears::structure::diff::*;
+use gears::structure::xflow::*;
+use gears::runtime::docstore::DocStore;
+use gears::util::fs::model_from_fs;
+
+mod common;
+use crate::common::load_doc;
+
+#[test]
+fn test_diff_values() {
+    let old = json!({"a": 1, "b": [1, 2, 3], "c": {"d/e": "x"}, "f": true});
+    let new = json!({"a": 2, "b": [1], "c": {"d/e": "y", "g": null}});
+
+    let changes = diff_values(&old, &new);
+    let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths, vec!["/a", "/b/2", "/b/1", "/c/d~1e", "/c/g", "/f"]);
+    assert_eq!(changes[0].old, Some(json!(1)));
+    assert_eq!(changes[0].new, Some(json!(2)));
+    assert_eq!(changes[4].old, None);
+    assert_eq!(changes[5].new, None);
+
+    let mut patched = old.clone();
+    apply_changes(&mut patched, &changes).unwrap();
+    assert_eq!(patched, new);
+
+    let mut reverse = new.clone();
+    apply_changes(&mut reverse, &diff_values(&new, &old)).unwrap();
+    assert_eq!(reverse, old);
+
+    assert!(diff_values(&old, &old).is_empty());
+}
+
+#[test]
+fn test_document_diff_and_patch() {
+    let _ = env_logger::try_init();
+    let old = model_from_fs("resource/projects/basic").unwrap();
+
+    let mut new = old.clone();
+    new.version += 1;
+    new.body.pages.pop();
+    new.body.xflows[0].name = "renamed".to_owned();
+    new.body.xflows.push(load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json"));
+
+    let diff = old.diff(&new);
+    assert_eq!(diff.from_version, 1);
+    assert_eq!(diff.to_version, 2);
+    assert!(diff.changes.iter().any(|c| c.path == "/version"));
+    assert!(diff.changes.iter().any(|c| c.path == "/body/xflows/0/name"));
+    assert!(diff.changes.iter().any(|c| {
+        c.path == format!("/body/pages/{}", new.body.pages.len()) && c.new.is_none()
+    }));
+    assert!(diff.changes.iter().any(|c| {
+        c.path == format!("/body/xflows/{}", old.body.xflows.len()) && c.old.is_none()
+    }));
+    assert!(format!("{}", diff).starts_with("version 1 -> 2\n"));
+
+    assert_eq!(old.apply_diff(&diff), Ok(new.clone()));
+
+    let serialized: DocumentDiff = serde_json::from_str(&diff.to_json()).unwrap();
+    assert_eq!(serialized, diff);
+}
+
+#[test]
+fn test_document_patch_conflicts() {
+    let _ = env_logger::try_init();
+    let old = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let mut new = old.clone();
+    new.set_body(XFlow::default());
+
+    let diff = old.diff(&new);
+
+    match new.apply_diff(&diff) {
+        Err(PatchError::Conflict { path, .. }) => assert_eq!(path, "/version"),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let mut renamed = old.clone();
+    renamed.name = "renamed".to_owned();
+    match renamed.apply_diff(&diff) {
+        Ok(_) => {}
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let mut changed = old.clone();
+    changed.body.nodes.last_mut().unwrap().label = "changed".to_owned();
+    match changed.apply_diff(&diff) {
+        Err(PatchError::Conflict { path, .. }) => assert!(path.starts_with("/body/nodes")),
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_document_version_bump() {
+    let _ = env_logger::try_init();
+    let mut doc = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let version = doc.version;
+
+    let body = doc.body.clone();
+    assert!(!doc.set_body(body));
+    assert_eq!(doc.version, version);
+
+    let mut body = doc.body.clone();
+    body.nodes.pop();
+    assert!(doc.set_body(body));
+    assert_eq!(doc.version, version + 1);
+
+    let mut store = DocStore::in_memory();
+    let mut doc = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    assert_eq!(store.save(&mut doc), Ok(true));
+    assert_eq!(store.save(&mut doc), Ok(false));
+
+    doc.body.nodes.pop();
+    doc.version = 1;
+    assert_eq!(store.save(&mut doc), Ok(true));
+    assert_eq!(doc.version, version + 1);
+    assert_eq!(store.versions::<XFlow>(&doc.id), vec![version, version + 1]);
+
+    let diff = store
+        .diff::<XFlow>(&doc.id, version, version + 1)
+        .unwrap()
+        .unwrap();
+    assert!(diff.changes.iter().any(|c| c.path.starts_with("/body/nodes/")));
+}
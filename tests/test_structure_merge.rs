@@ -0,0 +1,221 @@
+extern crate env_logger;
+extern crate tempdir;
+extern crate uuid;
+
+extern crate gears;
+
+use gears::structure::common::{DocumentFormat, FileLayout, I18NString};
+use gears::structure::domain::Attribute;
+use gears::structure::merge::*;
+use gears::structure::model::ModelDocument;
+use gears::structure::xflow::*;
+use gears::util::fs::{merge_model_fs, model_from_fs, model_to_fs};
+use tempdir::TempDir;
+
+mod common;
+use crate::common::load_doc;
+
+fn load_basic() -> ModelDocument {
+    model_from_fs("resource/projects/basic").unwrap()
+}
+
+fn xflow_mut<'a>(model: &'a mut ModelDocument, name: &str) -> &'a mut XFlowDocument {
+    model
+        .body
+        .xflows
+        .iter_mut()
+        .find(|xflow| xflow.name == name)
+        .expect("Flow should exist in model")
+}
+
+fn node_label(model: &ModelDocument, name: &str, id: i32) -> String {
+    let xflow = model
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == name)
+        .unwrap();
+    xflow.body.get_node_id(id).unwrap().label.clone()
+}
+
+fn add_attribute(model: &mut ModelDocument, entity: &str, name: &str) {
+    let entity = model
+        .body
+        .domain
+        .body
+        .entities
+        .iter_mut()
+        .find(|e| e.name == entity)
+        .unwrap();
+    entity.attributes.push(Attribute {
+        name: name.to_owned(),
+        vtype: "string".to_owned(),
+        default: "".to_owned(),
+        validations: Vec::new(),
+    });
+}
+
+#[test]
+fn test_merge_models_clean() {
+    let _ = env_logger::try_init();
+    let base = load_basic();
+
+    let mut ours = base.clone();
+    add_attribute(&mut ours, "post", "author");
+    xflow_mut(&mut ours, "steps").body.nodes[1].label = "First".to_owned();
+    ours.body.pages.pop();
+
+    let mut theirs = base.clone();
+    add_attribute(&mut theirs, "post", "summary");
+    add_attribute(&mut theirs, "comment", "body");
+    xflow_mut(&mut theirs, "steps").body.nodes[2].label = "Second".to_owned();
+    theirs.body.xflows.push(load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json"));
+    theirs.body.translations[0].body.items.insert(
+        "new_key".to_owned(),
+        I18NString::new("new value".to_owned()),
+    );
+
+    let res = merge_models(&base, &ours, &theirs).unwrap();
+    assert!(res.is_clean(), "Unexpected conflicts {:?}", res.conflicts);
+    let merged = res.merged;
+
+    let post = &merged.body.domain.body.entities[0];
+    let names: Vec<&str> = post.attributes.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, vec!["title", "body", "created", "author", "summary"]);
+    assert_eq!(merged.body.domain.body.entities[1].attributes.len(), 2);
+
+    assert_eq!(node_label(&merged, "steps", 2), "First");
+    assert_eq!(node_label(&merged, "steps", 3), "Second");
+    assert_eq!(merged.body.xflows.len(), base.body.xflows.len() + 1);
+    assert_eq!(merged.body.pages.len(), base.body.pages.len() - 1);
+    assert!(merged.body.translations[0].body.items.contains_key("new_key"));
+    assert_eq!(merged.version, base.version + 1);
+}
+
+#[test]
+fn test_merge_models_conflicts() {
+    let _ = env_logger::try_init();
+    let base = load_basic();
+
+    let mut ours = base.clone();
+    xflow_mut(&mut ours, "steps").body.nodes[1].label = "Ours".to_owned();
+    let mut theirs = base.clone();
+    xflow_mut(&mut theirs, "steps").body.nodes[1].label = "Theirs".to_owned();
+    theirs.body.domain.body.entities[0].name = "article".to_owned();
+
+    let res = merge_models(&base, &ours, &theirs).unwrap();
+    assert_eq!(res.conflicts.len(), 1);
+
+    let id = ours.body.xflows.iter().find(|x| x.name == "steps").unwrap().id;
+    let conflict = &res.conflicts[0];
+    assert_eq!(conflict.document, format!("xflows/{}.json", id));
+    assert_eq!(conflict.path, "/body/nodes/1/label");
+    assert_eq!(conflict.ours, Some("Ours".into()));
+    assert_eq!(conflict.theirs, Some("Theirs".into()));
+    assert_eq!(conflict.base, Some("Increment".into()));
+
+    // Ours is kept for conflicts, other changes are merged
+    assert_eq!(node_label(&res.merged, "steps", 2), "Ours");
+    assert!(
+        res.merged
+            .body
+            .domain
+            .body
+            .entities
+            .iter()
+            .any(|e| e.name == "article")
+    );
+}
+
+#[test]
+fn test_merge_models_conflict_files() {
+    let _ = env_logger::try_init();
+    let mut base = load_basic();
+    base.body.config.body.layout = FileLayout::Name;
+    base.body.config.body.format = DocumentFormat::Yaml;
+
+    let mut ours = base.clone();
+    xflow_mut(&mut ours, "steps").body.nodes[1].label = "Ours".to_owned();
+    let mut theirs = base.clone();
+    xflow_mut(&mut theirs, "steps").body.nodes[1].label = "Theirs".to_owned();
+    theirs.body.config.body.default_locale = "nl_NL".to_owned();
+    ours.body.config.body.default_locale = "fr_FR".to_owned();
+
+    // Conflicts name the files the merged model is written to
+    let res = merge_models(&base, &ours, &theirs).unwrap();
+    let documents: Vec<&str> = res.conflicts.iter().map(|c| c.document.as_str()).collect();
+    assert_eq!(documents, vec!["config.yaml", "xflows/steps.yaml"]);
+
+    let root = TempDir::new("merge").expect("Should have created a temp directory");
+    let dir = format!("{}", root.path().display());
+    model_to_fs(&res.merged, &dir).unwrap();
+    for document in documents {
+        assert!(std::path::Path::new(&format!("{}/{}", dir, document)).is_file());
+    }
+}
+
+#[test]
+fn test_merge_model_fs() {
+    let _ = env_logger::try_init();
+    let base = load_basic();
+
+    let mut ours = base.clone();
+    xflow_mut(&mut ours, "steps").body.nodes[1].label = "First".to_owned();
+    let mut theirs = base.clone();
+    theirs.body.xflows.retain(|xflow| xflow.name != "loop_infinite");
+
+    let root = TempDir::new("merge").expect("Should have created a temp directory");
+    let dirs: Vec<String> = ["ours", "theirs"]
+        .iter()
+        .map(|name| format!("{}/{}", root.path().display(), name))
+        .collect();
+    for (dir, model) in dirs.iter().zip([&ours, &theirs].iter()) {
+        std::fs::create_dir(dir).unwrap();
+        model_to_fs(model, dir).unwrap();
+    }
+
+    let conflicts = merge_model_fs("resource/projects/basic", &dirs[0], &dirs[1], &dirs[0]).unwrap();
+    assert_eq!(conflicts, Vec::<MergeConflict>::new());
+
+    let merged = model_from_fs(&dirs[0]).unwrap();
+    assert_eq!(node_label(&merged, "steps", 2), "First");
+    assert_eq!(merged.body.xflows.len(), base.body.xflows.len() - 1);
+    assert!(!merged.body.xflows.iter().any(|x| x.name == "loop_infinite"));
+}
+
+#[test]
+fn test_merge_model_fs_write_error() {
+    let _ = env_logger::try_init();
+    let base = load_basic();
+
+    let ours = base.clone();
+    let mut theirs = base.clone();
+    theirs.body.xflows.retain(|xflow| xflow.name != "loop_infinite");
+    let mut added = xflow_mut(&mut theirs, "steps").clone();
+    added.id = uuid::Uuid::new_v4();
+    added.name = "steps_copy".to_owned();
+    theirs.body.xflows.push(added.clone());
+
+    let root = TempDir::new("merge").expect("Should have created a temp directory");
+    let dirs: Vec<String> = ["ours", "theirs"]
+        .iter()
+        .map(|name| format!("{}/{}", root.path().display(), name))
+        .collect();
+    for (dir, model) in dirs.iter().zip([&ours, &theirs].iter()) {
+        std::fs::create_dir(dir).unwrap();
+        model_to_fs(model, dir).unwrap();
+    }
+
+    // The file of the added flow cannot be written
+    std::fs::create_dir(format!("{}/xflows/{}.json", dirs[0], added.id)).unwrap();
+    assert!(merge_model_fs("resource/projects/basic", &dirs[0], &dirs[1], &dirs[0]).is_err());
+
+    // The documents the merge removes are still there
+    let removed = ours
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == "loop_infinite")
+        .unwrap();
+    assert!(std::path::Path::new(&format!("{}/xflows/{}.json", dirs[0], removed.id)).is_file());
+}
@@ -0,0 +1,152 @@
+extern crate env_logger;
+#[macro_use]
+extern crate serde_json;
+extern crate tempdir;
+
+extern crate gears;
+
+use gears::structure::common::ModelLoadError;
+use gears::structure::migration::*;
+use gears::structure::xflow::XFlowDocument;
+use gears::util::fs::{migrate_model_fs, model_from_fs, model_from_fs_with_migrations, model_to_fs};
+use serde_json::Value;
+use tempdir::TempDir;
+
+/// Migrations of xflows from version 1, in which nodes were called steps
+fn xflow_migrations() -> Migrations {
+    let mut migrations = Migrations::new();
+    migrations.register("xflow", 1, "Rename 'steps' to 'nodes'", |value| {
+        let steps = value["body"].as_object_mut().and_then(|body| body.remove("steps"));
+        if let Some(steps) = steps {
+            value["body"]["nodes"] = steps;
+        }
+        Ok(())
+    });
+    migrations
+}
+
+#[test]
+fn test_migrations() {
+    let mut migrations = xflow_migrations();
+    migrations.register("xflow", 2, "Add a label to every edge", |value| {
+        match value["body"]["nodes"].as_array() {
+            Some(_) => Ok(()),
+            None => Err("xflow has no nodes".to_owned()),
+        }
+    });
+    assert_eq!(migrations.current_version("xflow"), 3);
+    assert_eq!(migrations.current_version("page"), BASE_DOCTYPE_VERSION);
+
+    let mut doc = json!({"doctype_version": 1, "body": {"steps": [1, 2]}});
+    let steps = migrations.plan("xflow", &doc).unwrap();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(format!("{}", steps[0]), "xflow 1 -> 2 : Rename 'steps' to 'nodes'");
+
+    assert_eq!(migrations.migrate("xflow", &mut doc), Ok(steps));
+    assert_eq!(doc, json!({"doctype_version": 3, "body": {"nodes": [1, 2]}}));
+    assert_eq!(migrations.migrate("xflow", &mut doc), Ok(Vec::new()));
+
+    let mut newer = json!({"doctype_version": 4, "body": {}});
+    assert_eq!(
+        migrations.migrate("xflow", &mut newer),
+        Err(MigrationError::TooNew {
+            doctype: "xflow".to_owned(),
+            version: 4,
+            current: 3,
+        })
+    );
+
+    let mut broken = json!({"doctype_version": 2, "body": {}});
+    match migrations.migrate("xflow", &mut broken) {
+        Err(MigrationError::Failed { version, .. }) => assert_eq!(version, 2),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let mut unversioned = json!({"body": {}});
+    assert_eq!(
+        migrations.migrate("xflow", &mut unversioned),
+        Err(MigrationError::MissingVersion("xflow".to_owned()))
+    );
+
+    migrations.register("page", 2, "Starts at the wrong version", |_| Ok(()));
+    let mut page = json!({"doctype_version": 1, "body": {}});
+    assert_eq!(
+        migrations.plan("page", &page),
+        Err(MigrationError::MissingStep {
+            doctype: "page".to_owned(),
+            version: 1,
+        })
+    );
+    assert_eq!(page["doctype_version"], 1);
+    assert!(migrations.migrate("page", &mut page).is_err());
+}
+
+#[test]
+fn test_new_document_version() {
+    let migrations = Migrations::builtin();
+    let doc = XFlowDocument::default();
+    assert_eq!(doc.doctype_version, migrations.current_version("xflow"));
+    let mut value = serde_json::to_value(&doc).unwrap();
+    assert_eq!(migrations.migrate("xflow", &mut value), Ok(Vec::new()));
+}
+
+#[test]
+fn test_migrate_model_fs() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+    let root = TempDir::new("migrate").expect("Should have created a temp directory");
+    let path = format!("{}", root.path().display());
+    model_to_fs(&model, &path).unwrap();
+
+    // Write the first xflow in its version 1 structure
+    let xflow = &model.body.xflows[0];
+    let xflow_path = format!("{}/xflows/{}.json", path, xflow.id);
+    let mut old: Value = serde_json::to_value(xflow).unwrap();
+    let nodes = old["body"].as_object_mut().unwrap().remove("nodes").unwrap();
+    old["body"]["steps"] = nodes;
+    let old = serde_json::to_string_pretty(&old).unwrap();
+    std::fs::write(&xflow_path, &old).unwrap();
+
+    match model_from_fs(&path) {
+        Err(ModelLoadError::Parse { path, .. }) => assert_eq!(path, xflow_path),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let migrations = xflow_migrations();
+    let report = migrate_model_fs(&path, &migrations, true).unwrap();
+    assert_eq!(report.documents.len(), model.body.xflows.len());
+    assert!(report.documents.iter().any(|doc| doc.document == xflow_path));
+    assert!(report.documents.iter().all(|doc| doc.steps.len() == 1));
+    assert!(format!("{}", report).contains("xflow 1 -> 2"));
+    assert_eq!(std::fs::read_to_string(&xflow_path).unwrap(), old);
+
+    let (migrated, report) = model_from_fs_with_migrations(&path, &migrations).unwrap();
+    assert!(!report.is_empty());
+    let loaded = migrated
+        .body
+        .xflows
+        .iter()
+        .find(|doc| doc.id == xflow.id)
+        .unwrap();
+    assert_eq!(loaded.body, xflow.body);
+    assert_eq!(loaded.doctype_version, 2);
+    assert_eq!(migrated.body.pages, model.body.pages);
+
+    assert_eq!(migrate_model_fs(&path, &migrations, false), Ok(report));
+    let (_, report) = model_from_fs_with_migrations(&path, &migrations).unwrap();
+    assert!(report.is_empty());
+
+    // Without the migrations the xflows are newer than their supported version
+    match model_from_fs(&path) {
+        Err(ModelLoadError::Multiple(errors)) => {
+            assert_eq!(errors.len(), model.body.xflows.len());
+            match errors[0] {
+                ModelLoadError::Migration {
+                    error: MigrationError::TooNew { version, .. }, ..
+                } => assert_eq!(version, 2),
+                ref err => panic!("Unexpected error {:?}", err),
+            }
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
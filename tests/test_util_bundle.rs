@@ -0,0 +1,153 @@
+extern crate env_logger;
+extern crate serde_json;
+extern crate tempdir;
+
+extern crate gears;
+
+use gears::structure::common::ModelLoadError;
+use gears::structure::migration::{MigrationError, Migrations};
+use gears::util::bundle::*;
+use gears::util::fs::model_from_fs;
+use tempdir::TempDir;
+
+#[test]
+fn test_model_to_and_from_bundle() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+
+    let bundle = model_to_bundle(&model);
+    assert_eq!(bundle.manifest.format_version, BUNDLE_FORMAT_VERSION);
+    assert_eq!(bundle.manifest.model, model.get_header());
+    assert_eq!(
+        bundle.manifest.entries.len(),
+        2 + model.body.xflows.len() + model.body.pages.len() + model.body.translations.len()
+    );
+    assert_eq!(bundle.documents.len(), bundle.manifest.entries.len());
+
+    let xflow = &model.body.xflows[0];
+    let entry = bundle
+        .manifest
+        .entries
+        .iter()
+        .find(|entry| entry.id == xflow.id)
+        .unwrap();
+    assert_eq!(entry.path, format!("xflows/{}.json", xflow.id));
+    assert_eq!(entry.sha256.len(), 64);
+    assert_eq!(entry.sha256, content_hash(&serde_json::to_value(xflow).unwrap()));
+
+    let serialized: ModelBundle = serde_json::from_str(&bundle.to_json()).unwrap();
+    assert_eq!(model_from_bundle(&serialized), Ok(model));
+}
+
+#[test]
+fn test_model_from_bundle_errors() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+
+    let mut bundle = model_to_bundle(&model);
+    bundle.manifest.format_version = BUNDLE_FORMAT_VERSION + 1;
+    match model_from_bundle(&bundle) {
+        Err(ModelLoadError::BadStructure(message)) => assert!(message.contains("format version")),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let mut bundle = model_to_bundle(&model);
+    let tampered = format!("xflows/{}.json", model.body.xflows[0].id);
+    bundle.documents.get_mut(&tampered).unwrap()["name"] = "tampered".into();
+    let missing = format!("pages/{}.json", model.body.pages[0].id);
+    bundle.documents.remove(&missing);
+    bundle.documents.insert("extra.json".to_owned(), serde_json::Value::Null);
+
+    let err = model_from_bundle(&bundle).unwrap_err();
+    let errors = err.errors();
+    assert_eq!(errors.len(), 3);
+    assert!(errors.contains(&&ModelLoadError::MissingFile(missing)));
+    assert!(errors.iter().any(|e| match **e {
+        ModelLoadError::ChecksumMismatch { ref path, .. } => *path == tampered,
+        _ => false,
+    }));
+    assert!(errors.iter().any(|e| match **e {
+        ModelLoadError::BadStructure(ref message) => message.starts_with("extra.json"),
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_model_from_bundle_migrations() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+
+    // Pack the first xflow in its version 1 structure, in which nodes were called steps
+    let mut bundle = model_to_bundle(&model);
+    let xflow = &model.body.xflows[0];
+    let path = format!("xflows/{}.json", xflow.id);
+    {
+        let old = bundle.documents.get_mut(&path).unwrap();
+        let nodes = old["body"].as_object_mut().unwrap().remove("nodes").unwrap();
+        old["body"]["steps"] = nodes;
+    }
+    let hash = content_hash(&bundle.documents[&path]);
+    for entry in &mut bundle.manifest.entries {
+        if entry.path == path {
+            entry.sha256 = hash.clone();
+        }
+    }
+
+    match model_from_bundle(&bundle) {
+        Err(ModelLoadError::BadStructure(message)) => assert!(message.starts_with(&path)),
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let mut migrations = Migrations::new();
+    migrations.register("xflow", 1, "Rename 'steps' to 'nodes'", |value| {
+        let steps = value["body"].as_object_mut().and_then(|body| body.remove("steps"));
+        if let Some(steps) = steps {
+            value["body"]["nodes"] = steps;
+        }
+        Ok(())
+    });
+    let (migrated, report) = model_from_bundle_with_migrations(&bundle, &migrations).unwrap();
+    assert_eq!(report.documents.len(), model.body.xflows.len());
+    assert!(report.documents.iter().any(|doc| doc.document == path));
+    let loaded = migrated
+        .body
+        .xflows
+        .iter()
+        .find(|doc| doc.id == xflow.id)
+        .unwrap();
+    assert_eq!(loaded.body, xflow.body);
+    assert_eq!(loaded.doctype_version, 2);
+
+    // Without the migrations the migrated model is newer than its supported version
+    match model_from_bundle(&model_to_bundle(&migrated)) {
+        Err(ModelLoadError::Multiple(errors)) => {
+            assert_eq!(errors.len(), model.body.xflows.len());
+            match errors[0] {
+                ModelLoadError::Migration {
+                    error: MigrationError::TooNew { version, .. }, ..
+                } => assert_eq!(version, 2),
+                ref err => panic!("Unexpected error {:?}", err),
+            }
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_bundle_to_and_from_file() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+
+    let root = TempDir::new("bundle").expect("Should have created a temp directory");
+    let path = format!("{}/basic.json", root.path().display());
+    bundle_to_file(&model, &path).unwrap();
+
+    let files: Vec<_> = std::fs::read_dir(root.path()).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    assert_eq!(bundle_from_file(&path), Ok(model));
+
+    match bundle_from_file(&format!("{}/missing.json", root.path().display())) {
+        Err(ModelLoadError::MissingFile(_)) => {}
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
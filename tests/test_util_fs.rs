@@ -3,7 +3,11 @@ extern crate tempdir;
 
 extern crate gears;
 
-use gears::util::fs::{model_from_fs, model_to_fs};
+use gears::structure::common::{DocumentFormat, FileLayout, ModelLoadError};
+use gears::structure::model::ModelDocument;
+use gears::util::fs::{build_dotfiles, convert_model_fs, is_model_dir, model_from_fs, model_to_fs};
+use gears::util::naming::{name_to_path, slugify};
+use std::path::Path;
 use tempdir::TempDir;
 
 mod common;
@@ -40,3 +44,220 @@ fn test_model_to_and_from_fs() {
     assert_eq!(model_a.id, model_b.id);
     assert_eq!(model_a.body.xflows.len(), model_b.body.xflows.len());
 }
+
+fn copy_basic_project(name: &str) -> (TempDir, String) {
+    let model = model_from_fs("resource/projects/basic").unwrap();
+    let root = TempDir::new(name).expect("Should have created a temp directory");
+    let path = format!("{}", root.path().display());
+    model_to_fs(&model, &path).unwrap();
+    (root, path)
+}
+
+#[test]
+fn test_model_from_fs_missing_files() {
+    let _ = env_logger::try_init();
+
+    match model_from_fs("resource/projects/does-not-exist") {
+        Err(ModelLoadError::MissingFile(path)) => {
+            assert_eq!(path, "resource/projects/does-not-exist")
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    let (_root, path) = copy_basic_project("missing_config");
+    std::fs::remove_file(format!("{}/config.json", path)).unwrap();
+
+    match model_from_fs(&path) {
+        Err(ModelLoadError::MissingFile(file)) => {
+            assert_eq!(file, format!("{}/config.json", path))
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert!(!is_model_dir(&path));
+}
+
+#[test]
+fn test_model_from_fs_collects_errors() {
+    let _ = env_logger::try_init();
+    let (_root, path) = copy_basic_project("broken_files");
+
+    let xflow_files: Vec<_> = std::fs::read_dir(format!("{}/xflows", path))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    std::fs::write(&xflow_files[0], "{\n  \"id\": \"not an id\",\n").unwrap();
+    std::fs::copy(&xflow_files[1], format!("{}/xflows/copy.json", path)).unwrap();
+    std::fs::remove_file(format!("{}/domain.json", path)).unwrap();
+
+    let err = model_from_fs(&path).unwrap_err();
+    let errors = err.errors();
+    assert_eq!(errors.len(), 3, "Unexpected errors {}", err);
+
+    assert!(errors.iter().any(|e| match **e {
+        ModelLoadError::MissingFile(ref file) => file.ends_with("domain.json"),
+        _ => false,
+    }));
+    assert!(errors.iter().any(|e| match **e {
+        ModelLoadError::Parse { ref path, line, column, .. } => {
+            path.ends_with(&*xflow_files[0].to_string_lossy()) && line == 2 && column > 0
+        }
+        _ => false,
+    }));
+    assert!(errors.iter().any(|e| match **e {
+        ModelLoadError::DuplicateId { .. } => true,
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_model_to_fs_reports_errors() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+
+    let root = TempDir::new("unwritable").expect("Should have created a temp directory");
+    let path = format!("{}/does/not/exist", root.path().display());
+
+    let err = model_to_fs(&model, &path).unwrap_err();
+    assert!(err.errors().len() > 1);
+    assert!(build_dotfiles(&model, &path).is_err());
+}
+
+fn sorted(mut model: ModelDocument) -> ModelDocument {
+    model.body.xflows.sort_by_key(|doc| doc.id);
+    model.body.pages.sort_by_key(|doc| doc.id);
+    model.body.translations.sort_by_key(|doc| doc.id);
+    model
+}
+
+fn files_with_extension(path: &str, ext: &str) -> usize {
+    ["", "/xflows", "/pages", "/translations"]
+        .iter()
+        .map(|dir| {
+            std::fs::read_dir(format!("{}{}", path, dir))
+                .unwrap()
+                .filter(|entry| {
+                    entry.as_ref().unwrap().path().extension().map(|e| e == ext) == Some(true)
+                })
+                .count()
+        })
+        .sum()
+}
+
+#[test]
+fn test_convert_model_fs() {
+    let _ = env_logger::try_init();
+    let model = sorted(model_from_fs("resource/projects/basic").unwrap());
+    let (_root, path) = copy_basic_project("convert");
+    let json_files = files_with_extension(&path, "json");
+
+    convert_model_fs(&path, DocumentFormat::Yaml).unwrap();
+    assert_eq!(files_with_extension(&path, "json"), 0);
+    assert_eq!(files_with_extension(&path, "yaml"), json_files);
+
+    let yaml_model = sorted(model_from_fs(&path).unwrap());
+    assert_eq!(yaml_model.body.config.body.format, DocumentFormat::Yaml);
+    assert_eq!(yaml_model.body.xflows, model.body.xflows);
+    assert_eq!(yaml_model.body.domain, model.body.domain);
+
+    convert_model_fs(&path, DocumentFormat::Json).unwrap();
+    assert_eq!(files_with_extension(&path, "yaml"), 0);
+    assert_eq!(sorted(model_from_fs(&path).unwrap()), model);
+}
+
+#[test]
+fn test_model_from_fs_mixed_formats() {
+    let _ = env_logger::try_init();
+    let model = model_from_fs("resource/projects/basic").unwrap();
+    let (_root, path) = copy_basic_project("mixed");
+
+    let xflow = &model.body.xflows[0];
+    std::fs::remove_file(format!("{}/xflows/{}.json", path, xflow.id)).unwrap();
+    std::fs::write(format!("{}/xflows/{}.yml", path, xflow.id), xflow.to_yaml()).unwrap();
+    std::fs::remove_file(format!("{}/domain.json", path)).unwrap();
+    std::fs::write(format!("{}/domain.yaml", path), model.body.domain.to_yaml()).unwrap();
+    std::fs::write(format!("{}/xflows/README.md", path), "Not a document").unwrap();
+
+    let mixed = model_from_fs(&path).unwrap();
+    assert_eq!(mixed.body.domain, model.body.domain);
+    assert_eq!(mixed.body.xflows.len(), model.body.xflows.len());
+    assert!(mixed.body.xflows.contains(xflow));
+
+    std::fs::write(format!("{}/domain.yaml", path), "id: [\nname: broken\n").unwrap();
+    match model_from_fs(&path) {
+        Err(ModelLoadError::Parse { path, line, .. }) => {
+            assert!(path.ends_with("domain.yaml"));
+            assert!(line > 0);
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_slugify() {
+    assert_eq!(slugify("List Posts"), "list-posts");
+    assert_eq!(slugify("  Create invoice (v2)! "), "create-invoice-v2");
+    assert_eq!(slugify("***"), "document");
+    assert_eq!(name_to_path("billing/Create Invoice"), "billing/create-invoice");
+    assert_eq!(name_to_path("/billing//Create Invoice/"), "billing/create-invoice");
+}
+
+#[test]
+fn test_model_to_fs_name_layout() {
+    let _ = env_logger::try_init();
+    let mut model = sorted(model_from_fs("resource/projects/basic").unwrap());
+    model.body.config.body.layout = FileLayout::Name;
+    model.body.xflows[0].name = "billing/Create Invoice".to_owned();
+    let moved_id = model.body.xflows[0].id;
+
+    let root = TempDir::new("name_layout").expect("Should have created a temp directory");
+    let path = format!("{}", root.path().display());
+    model_to_fs(&model, &path).unwrap();
+
+    assert!(Path::new(&format!("{}/xflows/billing/create-invoice.json", path)).is_file());
+    assert!(Path::new(&format!("{}/xflows/steps.json", path)).is_file());
+    assert!(Path::new(&format!("{}/pages/form.json", path)).is_file());
+    // The two pages named "List Posts" are told apart by the id of the second one
+    assert!(Path::new(&format!("{}/pages/list-posts.json", path)).is_file());
+    assert!(
+        Path::new(&format!("{}/pages/list-posts-e4f0518afd0d403e9c2079241c1c14ae.json", path))
+            .is_file()
+    );
+    assert_eq!(sorted(model_from_fs(&path).unwrap()), model);
+
+    // Renaming moves the file and removes the emptied folder
+    model.body.xflows[0].name = "Create Invoice".to_owned();
+    model_to_fs(&model, &path).unwrap();
+    assert!(!Path::new(&format!("{}/xflows/billing", path)).exists());
+    assert!(Path::new(&format!("{}/xflows/create-invoice.json", path)).is_file());
+    let reloaded = sorted(model_from_fs(&path).unwrap());
+    assert_eq!(reloaded.body.xflows[0].id, moved_id);
+    assert_eq!(reloaded, model);
+
+    // Switching back to the id layout moves every file to its id
+    model.body.config.body.layout = FileLayout::Id;
+    model_to_fs(&model, &path).unwrap();
+    assert!(Path::new(&format!("{}/xflows/{}.json", path, moved_id)).is_file());
+    assert!(!Path::new(&format!("{}/xflows/create-invoice.json", path)).exists());
+    assert_eq!(sorted(model_from_fs(&path).unwrap()), model);
+}
+
+#[test]
+fn test_model_to_fs_name_swap() {
+    let _ = env_logger::try_init();
+    let mut model = sorted(model_from_fs("resource/projects/basic").unwrap());
+    model.body.config.body.layout = FileLayout::Name;
+    model.body.xflows[0].name = "alpha".to_owned();
+    model.body.xflows[1].name = "beta".to_owned();
+
+    let root = TempDir::new("name_swap").expect("Should have created a temp directory");
+    let path = format!("{}", root.path().display());
+    model_to_fs(&model, &path).unwrap();
+
+    // Each document is written to the file of the other one
+    model.body.xflows[0].name = "beta".to_owned();
+    model.body.xflows[1].name = "alpha".to_owned();
+    model_to_fs(&model, &path).unwrap();
+    assert!(Path::new(&format!("{}/xflows/alpha.json", path)).is_file());
+    assert!(Path::new(&format!("{}/xflows/beta.json", path)).is_file());
+    assert_eq!(sorted(model_from_fs(&path).unwrap()), model);
+}
@@ -0,0 +1,394 @@
+extern crate env_logger;
+extern crate uuid;
+
+extern crate gears;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::{ExecutionLimit, RuntimeError};
+use gears::runtime::executor::block_on;
+use gears::runtime::actiondispatch;
+use gears::runtime::actiondispatch::dispatchable::{AsyncDispatchable, DispatchFuture};
+
+mod common;
+use crate::common::load_doc;
+
+/// The outcome of a lookup, filled in by a background thread
+#[derive(Default)]
+struct Slot {
+    values: Option<Result<Vec<(String, XFlowValue)>, String>>,
+    waker: Option<Waker>,
+}
+
+struct Lookup<'f> {
+    node: &'f XFlowNode,
+    state: Option<XFState>,
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl<'f> Future for Lookup<'f> {
+    type Output = (XFState, Result<(), RuntimeError>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut slot = this.slot.lock().unwrap();
+        match slot.values.take() {
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Some(values) => {
+                let mut state = this.state.take().unwrap();
+                let res = match values {
+                    Ok(values) => {
+                        for (name, value) in values {
+                            state.add(&XFlowVariable {
+                                name: name,
+                                vtype: XFlowValueType::Integer,
+                                value: value,
+                            });
+                        }
+                        Ok(())
+                    }
+                    Err(message) => Err(RuntimeError::Dispatch {
+                        node: this.node.id,
+                        message: message,
+                        state: state.clone(),
+                    }),
+                };
+                Poll::Ready((state, res))
+            }
+        }
+    }
+}
+
+/// A stand-in for a database, answering lookups for the `outputmatch` of call nodes on
+/// background threads after a delay
+struct SlowStore {
+    values: HashMap<String, XFlowValue>,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+impl SlowStore {
+    fn new() -> SlowStore {
+        let mut values = HashMap::new();
+        values.insert("price".to_owned(), XFlowValue::Integer(42));
+        values.insert("stock".to_owned(), XFlowValue::Integer(7));
+        SlowStore {
+            values: values,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl AsyncDispatchable for SlowStore {
+    fn dispatch_async<'f>(&'f self, node: &'f XFlowNode, state: XFState) -> DispatchFuture<'f> {
+        let lookups = match node.parameters {
+            XFlowNodeParameters::Call(ref params) => params.outputmatch.clone(),
+            _ => Vec::new(),
+        };
+        let values: Result<Vec<(String, XFlowValue)>, String> = lookups
+            .into_iter()
+            .map(|(key, name)| match self.values.get(&key) {
+                Some(value) => Ok((name, value.clone())),
+                None => Err(format!("No value for key '{}'", key)),
+            })
+            .collect();
+
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let in_flight = self.in_flight.clone();
+        let max_in_flight = self.max_in_flight.clone();
+        let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        max_in_flight.fetch_max(current, Ordering::SeqCst);
+
+        let background = slot.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            let mut slot = background.lock().unwrap();
+            slot.values = Some(values);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        });
+
+        Box::pin(Lookup {
+            node: node,
+            state: Some(state),
+            slot: slot,
+        })
+    }
+}
+
+/// A receiver whose dispatches never complete, nor wake the run
+struct Never;
+
+impl AsyncDispatchable for Never {
+    fn dispatch_async<'f>(&'f self, _node: &'f XFlowNode, _state: XFState) -> DispatchFuture<'f> {
+        Box::pin(std::future::pending())
+    }
+}
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    let flow_receiver = actiondispatch::flow::Flow::default();
+    let flox_receiver = actiondispatch::flox::Flox::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, flow_receiver);
+    dispatcher.register_receiver(XFlowNodeType::Flox, flox_receiver);
+    dispatcher
+}
+
+fn flow_node(id: i32, action: &str, parameters: XFlowNodeParameters) -> XFlowNode {
+    XFlowNode {
+        id: id,
+        nodetype: XFlowNodeType::Flow,
+        action: action.to_owned(),
+        label: action.to_owned(),
+        parameters: parameters,
+    }
+}
+
+fn lookup_node(id: i32, key: &str, name: &str) -> XFlowNode {
+    XFlowNode {
+        id: id,
+        nodetype: XFlowNodeType::Call,
+        action: "lookup".to_owned(),
+        label: name.to_owned(),
+        parameters: XFlowNodeParameters::Call(CallParameters {
+            xflowid: uuid::Uuid::new_v4(),
+            inputmatch: vec![],
+            outputmatch: vec![(key.to_owned(), name.to_owned())],
+        }),
+    }
+}
+
+/// Two lookups in the branches of a fork
+fn lookup_flow(price_key: &str) -> XFlowDocument {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let flow = FlowParameters::default();
+    xfs.body.requirements.push(XFlowRequirement {
+        xtype: XFlowNodeType::Call,
+        version: 1,
+    });
+    xfs.body.variables = XFlowVariables {
+        input: vec![],
+        local: vec![],
+        output: vec![
+            XFlowVariableDefinition {
+                name: "Price".to_owned(),
+                vtype: XFlowValueType::Integer,
+            },
+            XFlowVariableDefinition {
+                name: "Stock".to_owned(),
+                vtype: XFlowValueType::Integer,
+            },
+        ],
+    };
+    xfs.body.nodes = vec![
+        flow_node(1, "start", XFlowNodeParameters::Flow(flow.clone())),
+        flow_node(2, "fork", XFlowNodeParameters::Fork(ForkParameters {
+            join: 5,
+            conflicts: XFlowMergePolicy::Fail,
+        })),
+        lookup_node(3, price_key, "Price"),
+        lookup_node(4, "stock", "Stock"),
+        flow_node(5, "join", XFlowNodeParameters::Flow(flow.clone())),
+        flow_node(6, "end", XFlowNodeParameters::Flow(flow)),
+    ];
+    xfs.body.edges = vec![(1, 2), (2, 3), (2, 4), (3, 5), (4, 5), (5, 6)];
+    xfs
+}
+
+#[test]
+fn test_run_async_with_sync_receivers() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let dispatcher = build_dispatcher();
+    let mut state = XFState::default();
+    for &(name, value) in &[("A", 3), ("B", 4)] {
+        state.add(&XFlowVariable {
+            name: name.to_owned(),
+            vtype: XFlowValueType::Integer,
+            value: XFlowValue::Integer(value),
+        });
+    }
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap().with_tracing();
+    block_on(xfrunner.run_async()).unwrap();
+    assert!(xfrunner.is_completed_ok());
+
+    let steps = xfrunner.get_trace().unwrap().steps.len();
+    assert_eq!(xfrunner.steps(), steps);
+
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("Result").unwrap().value, XFlowValue::Integer(19));
+}
+
+#[test]
+fn test_async_receivers_run_concurrently() {
+    let _ = env_logger::try_init();
+    let xfs = lookup_flow("price");
+    let store = SlowStore::new();
+    let max_in_flight = store.max_in_flight.clone();
+    let mut dispatcher = build_dispatcher();
+    dispatcher.register_async_receiver(XFlowNodeType::Call, store);
+    let state = XFState::default();
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    block_on(xfrunner.run_async()).unwrap();
+
+    // Both lookups were waiting at the same time
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("Price").unwrap().value, XFlowValue::Integer(42));
+    assert_eq!(output.get("Stock").unwrap().value, XFlowValue::Integer(7));
+}
+
+#[test]
+fn test_run_without_sync_receivers() {
+    let _ = env_logger::try_init();
+    let xfs = lookup_flow("price");
+    let mut dispatcher = build_dispatcher();
+    dispatcher.register_async_receiver(XFlowNodeType::Call, SlowStore::new());
+    let state = XFState::default();
+
+    assert!(dispatcher.missing_async_capabilities(&xfs.body).is_empty());
+    let missing = dispatcher.missing_capabilities(&xfs.body);
+    assert!(!missing.is_empty());
+    assert!(missing.iter().all(|capability| capability.nodetype == XFlowNodeType::Call));
+
+    // The lookups only have an asynchronous receiver, so a synchronous run fails early
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    match xfrunner.run() {
+        Err(RuntimeError::MissingCapabilities { missing: run_missing }) => {
+            assert_eq!(run_missing, missing)
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(xfrunner.steps(), 0);
+    assert!(xfrunner.is_initialized());
+
+    block_on(xfrunner.run_async()).unwrap();
+    assert!(xfrunner.is_completed_ok());
+}
+
+#[test]
+fn test_async_receiver_errors() {
+    let _ = env_logger::try_init();
+    let dispatcher = {
+        let mut dispatcher = build_dispatcher();
+        dispatcher.register_async_receiver(XFlowNodeType::Call, SlowStore::new());
+        dispatcher
+    };
+    let state = XFState::default();
+
+    let xfs = lookup_flow("discount");
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    match block_on(xfrunner.run_async()) {
+        Err(RuntimeError::Dispatch { node, .. }) => assert_eq!(node, 3),
+        res => panic!("Expected a dispatch error, found {:?}", res),
+    }
+    assert_eq!(xfrunner.status, XFlowStatus::Aborted);
+
+    // Without an async receiver, call nodes are not handled
+    let dispatcher = build_dispatcher();
+    let xfs = lookup_flow("price");
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Err(RuntimeError::MissingCapabilities { missing }) => assert_eq!(missing.len(), 2),
+        Err(err) => panic!("Expected missing capabilities, found {:?}", err),
+        Ok(_) => panic!("Expected missing capabilities"),
+    }
+}
+
+#[test]
+fn test_dropped_run_async_aborts() {
+    let _ = env_logger::try_init();
+    let mut xfs = lookup_flow("price");
+    let flow = FlowParameters::default();
+    xfs.body.variables.local = vec![
+        XFlowVariable {
+            name: "Stock".to_owned(),
+            vtype: XFlowValueType::Integer,
+            value: XFlowValue::Integer(7),
+        },
+    ];
+    xfs.body.nodes = vec![
+        flow_node(1, "start", XFlowNodeParameters::Flow(flow.clone())),
+        lookup_node(2, "price", "Price"),
+        flow_node(3, "end", XFlowNodeParameters::Flow(flow)),
+    ];
+    xfs.body.edges = vec![(1, 2), (2, 3)];
+
+    let mut dispatcher = build_dispatcher();
+    dispatcher.register_async_receiver(XFlowNodeType::Call, SlowStore::new());
+    let state = XFState::default();
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+
+    {
+        // Drop the run while the lookup is pending
+        let mut future = Box::pin(xfrunner.run_async());
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+    }
+
+    assert_eq!(xfrunner.status, XFlowStatus::Aborted);
+    let checkpoint = xfrunner.checkpoint();
+    assert_eq!(checkpoint.status, XFlowStatus::Aborted);
+    assert_eq!(checkpoint.current_node, Some(2));
+    assert_eq!(
+        checkpoint.state.get("Stock").unwrap().value,
+        XFlowValue::Integer(7)
+    );
+
+    assert_eq!(xfrunner.run(), Ok(()));
+    assert_eq!(xfrunner.get_output(), Err(RuntimeError::NotFinished));
+}
+
+#[test]
+fn test_run_async_deadline() {
+    let _ = env_logger::try_init();
+    let mut xfs = lookup_flow("price");
+    let flow = FlowParameters::default();
+    xfs.body.variables.output = vec![];
+    xfs.body.nodes = vec![
+        flow_node(1, "start", XFlowNodeParameters::Flow(flow.clone())),
+        lookup_node(2, "price", "Price"),
+        lookup_node(3, "stock", "Stock"),
+        flow_node(4, "end", XFlowNodeParameters::Flow(flow)),
+    ];
+    xfs.body.edges = vec![(1, 2), (2, 3), (3, 4)];
+
+    let mut dispatcher = build_dispatcher();
+    dispatcher.register_async(Capability::action(XFlowNodeType::Call, "lookup", 1), Never);
+    let state = XFState::default();
+
+    let mut limits = XFlowLimits::default();
+    limits.max_duration = Some(Duration::from_millis(20));
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+        .unwrap()
+        .with_limits(limits);
+
+    // Nothing wakes the run but the timer of its deadline
+    match block_on(xfrunner.run_async()) {
+        Err(RuntimeError::LimitExceeded { node, limit, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(limit, ExecutionLimit::MaxDuration(Duration::from_millis(20)));
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(xfrunner.status, XFlowStatus::TimedOut);
+}
@@ -0,0 +1,83 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher
+}
+
+fn run_branch_flow(xfs: &XFlowDocument, counter: i64) -> Result<i64, RuntimeError> {
+    let dispatcher = build_dispatcher();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Counter".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(counter),
+    });
+
+    let mut xfrunner = XFlowRunner::new(xfs, &dispatcher, &state)?;
+    xfrunner.run()?;
+    match xfrunner.get_output()?.get("ReturnValue").unwrap().value {
+        XFlowValue::Integer(i) => Ok(i),
+        _ => panic!("ReturnValue is not an integer"),
+    }
+}
+
+#[test]
+fn test_branch_condition() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+
+    assert_eq!(run_branch_flow(&xfs, 0), Ok(1));
+    assert_eq!(run_branch_flow(&xfs, 4), Ok(1));
+    assert_eq!(run_branch_flow(&xfs, 11), Ok(3));
+}
+
+#[test]
+fn test_branch_default() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+
+    assert_eq!(run_branch_flow(&xfs, 5), Ok(2));
+    assert_eq!(run_branch_flow(&xfs, 10), Ok(2));
+}
+
+#[test]
+fn test_branch_without_default() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    xfs.body.branches.retain(|branch| !branch.default);
+
+    match run_branch_flow(&xfs, 7) {
+        Err(RuntimeError::NoMatchingBranch { node, .. }) => assert_eq!(node, 2),
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_branch_condition_not_boolean() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    xfs.body.branches[0].condition = Some("$Counter + 1".to_owned());
+
+    match run_branch_flow(&xfs, 0) {
+        Err(RuntimeError::FloxEvaluation { node, expression, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(expression, "$Counter + 1");
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
@@ -0,0 +1,332 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use gears::structure::xflow::*;
+use gears::structure::model::ModelDocument;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::{ExecutionLimit, RuntimeError};
+use gears::runtime::actiondispatch;
+use gears::runtime::actiondispatch::dispatchable::{AsyncDispatchable, DispatchFuture, Dispatchable};
+use gears::runtime::executor::block_on;
+use gears::validation::common::validate_model;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_model() -> ModelDocument {
+    let mut model = ModelDocument::default();
+    for file in &[
+        "resource/docs/xflow/capability_flows/add_1.json",
+        "resource/docs/xflow/capability_flows/xflow-call-xflow.json",
+        "resource/docs/xflow/capability_flows/xflow-call-xflow-3x.json",
+        "resource/docs/xflow/capability_flows/xflow-call-xflow-indirect.json",
+    ]
+    {
+        model.body.xflows.push(load_doc::<XFlowDocument>(file));
+    }
+    model
+}
+
+fn build_dispatcher<'a>(model: &'a ModelDocument) -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher.register_receiver(XFlowNodeType::Call, actiondispatch::call::Call::new(model));
+    dispatcher
+}
+
+fn counter_state(value: i64) -> XFState {
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "CounterValue".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(value),
+    });
+    state
+}
+
+fn run_counter_flow(model: &ModelDocument, name: &str, input: i64) -> Result<i64, RuntimeError> {
+    let xflow = model
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == name)
+        .expect("Flow should exist in model");
+    let dispatcher = build_dispatcher(model);
+    let state = counter_state(input);
+
+    let mut xfrunner = XFlowRunner::new(xflow, &dispatcher, &state)?;
+    xfrunner.run()?;
+    let output = xfrunner.get_output()?;
+    match output.get("CounterValue").unwrap().value {
+        XFlowValue::Integer(i) => Ok(i),
+        _ => panic!("CounterValue is not an integer"),
+    }
+}
+
+#[test]
+fn test_call_xflow() {
+    let _ = env_logger::try_init();
+    let model = build_model();
+
+    assert_eq!(run_counter_flow(&model, "add_1", 0), Ok(1));
+    assert_eq!(run_counter_flow(&model, "xflow_call_xflow", 0), Ok(1));
+    assert_eq!(run_counter_flow(&model, "xflow_call_xflow_3x", 1), Ok(4));
+    assert_eq!(run_counter_flow(&model, "xflow_call_xflow_indirect", 0), Ok(9));
+}
+
+#[test]
+fn test_call_xflow_validation() {
+    let _ = env_logger::try_init();
+    let mut model = build_model();
+    model.add_locale("en_US").unwrap();
+    model.pad_all_translations();
+
+    assert_eq!(validate_model(&model).len(), 0);
+
+    model.body.xflows.retain(|xflow| xflow.name != "add_1");
+
+    let errors = validate_model(&model);
+    assert_eq!(errors.len(), 4);
+}
+
+#[test]
+fn test_call_xflow_recursion_limit() {
+    let _ = env_logger::try_init();
+    let mut model = build_model();
+
+    for xflow in &mut model.body.xflows {
+        if xflow.name == "xflow_call_xflow" {
+            let id = xflow.id;
+            for node in &mut xflow.body.nodes {
+                if let XFlowNodeParameters::Call(ref mut params) = node.parameters {
+                    params.xflowid = id;
+                }
+            }
+        }
+    }
+
+    match run_counter_flow(&model, "xflow_call_xflow", 0) {
+        Err(RuntimeError::CallFailed { node, source, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(source.node_id(), Some(2));
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_call_xflow_parent_limits() {
+    let _ = env_logger::try_init();
+    let model = build_model();
+    let xflow = model
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == "xflow_call_xflow_3x")
+        .unwrap();
+    let dispatcher = build_dispatcher(&model);
+    let state = counter_state(1);
+
+    // The calling flow takes 4 steps and each called flow 2
+    let mut limits = XFlowLimits::default();
+    limits.max_steps = Some(10);
+    let mut xfrunner = XFlowRunner::new(xflow, &dispatcher, &state)
+        .unwrap()
+        .with_limits(limits.clone());
+    assert_eq!(xfrunner.run(), Ok(()));
+
+    limits.max_steps = Some(8);
+    let mut xfrunner = XFlowRunner::new(xflow, &dispatcher, &state)
+        .unwrap()
+        .with_limits(limits);
+    match xfrunner.run() {
+        Err(RuntimeError::CallFailed { node, source, .. }) => {
+            assert_eq!(node, 4);
+            match *source {
+                RuntimeError::LimitExceeded { node, limit, .. } => {
+                    assert_eq!(node, 3);
+                    assert_eq!(limit, ExecutionLimit::MaxSteps(8));
+                }
+                err => panic!("Unexpected error {:?}", err),
+            }
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(xfrunner.status, XFlowStatus::TimedOut);
+}
+
+#[test]
+fn test_call_xflow_missing_input() {
+    let _ = env_logger::try_init();
+    let model = build_model();
+    let xflow = model
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == "xflow_call_xflow")
+        .unwrap();
+    let dispatcher = build_dispatcher(&model);
+    let mut state = counter_state(0);
+    state.remove("CounterValue");
+
+    assert!(XFlowRunner::new(xflow, &dispatcher, &state).is_err());
+}
+
+/// A Flox receiver counting the nodes it dispatches
+#[derive(Default)]
+struct CountingFlox {
+    flox: actiondispatch::flox::Flox,
+    count: Arc<AtomicUsize>,
+}
+
+impl Dispatchable for CountingFlox {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.flox.dispatch(node, state)
+    }
+}
+
+#[test]
+fn test_call_xflow_uses_caller_receivers() {
+    let _ = env_logger::try_init();
+    let mut model = build_model();
+
+    // The called flow needs a newer Flox receiver than the calling flow
+    for xflow in &mut model.body.xflows {
+        if xflow.name == "add_1" {
+            for req in &mut xflow.body.requirements {
+                if req.xtype == XFlowNodeType::Flox {
+                    req.version = 2;
+                }
+            }
+        }
+    }
+
+    let xflow = model
+        .body
+        .xflows
+        .iter()
+        .find(|xflow| xflow.name == "xflow_call_xflow_3x")
+        .unwrap();
+    let counting = CountingFlox::default();
+    let count = counting.count.clone();
+    let mut dispatcher = build_dispatcher(&model);
+    dispatcher.register(Capability::new(XFlowNodeType::Flox, 2), counting);
+    let state = counter_state(1);
+
+    let mut xfrunner = XFlowRunner::new(xflow, &dispatcher, &state).unwrap();
+    xfrunner.run().unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("CounterValue").unwrap().value, XFlowValue::Integer(4));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+}
+
+/// The dispatch of a Flox node that waits once before it completes
+struct Yielding<'f> {
+    flox: &'f actiondispatch::flox::Flox,
+    node: &'f XFlowNode,
+    state: Option<XFState>,
+    waited: bool,
+}
+
+impl<'f> Future for Yielding<'f> {
+    type Output = (XFState, Result<(), RuntimeError>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if !this.waited {
+            this.waited = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let mut state = this.state.take().unwrap();
+        let res = this.flox.dispatch(this.node, &mut state);
+        Poll::Ready((state, res))
+    }
+}
+
+/// An asynchronous Flox receiver counting the nodes it dispatches
+#[derive(Default)]
+struct AsyncFlox {
+    flox: actiondispatch::flox::Flox,
+    count: Arc<AtomicUsize>,
+}
+
+impl AsyncDispatchable for AsyncFlox {
+    fn dispatch_async<'f>(&'f self, node: &'f XFlowNode, state: XFState) -> DispatchFuture<'f> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Box::pin(Yielding {
+            flox: &self.flox,
+            node: node,
+            state: Some(state),
+            waited: false,
+        })
+    }
+}
+
+#[test]
+fn test_call_xflow_async() {
+    let _ = env_logger::try_init();
+    let mut model = build_model();
+
+    // The called flow needs a Flox receiver that is only registered as asynchronous
+    for xflow in &mut model.body.xflows {
+        if xflow.name == "add_1" {
+            for req in &mut xflow.body.requirements {
+                if req.xtype == XFlowNodeType::Flox {
+                    req.version = 2;
+                }
+            }
+        }
+    }
+
+    let async_flox = AsyncFlox::default();
+    let count = async_flox.count.clone();
+    let mut dispatcher = build_dispatcher(&model);
+    dispatcher.register_async(Capability::new(XFlowNodeType::Flox, 2), async_flox);
+    dispatcher.register_async_receiver(XFlowNodeType::Call, actiondispatch::call::Call::new(&model));
+
+    let find = |name: &str| model.body.xflows.iter().find(|xflow| xflow.name == name).unwrap();
+    let state = counter_state(1);
+
+    // Run synchronously, the called flow cannot reach the asynchronous receiver
+    let mut xfrunner = XFlowRunner::new(find("xflow_call_xflow_3x"), &dispatcher, &state).unwrap();
+    match xfrunner.run() {
+        Err(RuntimeError::CallFailed { source, .. }) => {
+            match *source {
+                RuntimeError::MissingCapabilities { .. } => {}
+                err => panic!("Unexpected error {:?}", err),
+            }
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 0);
+
+    let mut xfrunner = XFlowRunner::new(find("xflow_call_xflow_3x"), &dispatcher, &state).unwrap();
+    block_on(xfrunner.run_async()).unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("CounterValue").unwrap().value, XFlowValue::Integer(4));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    // Flows called by called flows run asynchronously too
+    let state = counter_state(0);
+    let mut xfrunner =
+        XFlowRunner::new(find("xflow_call_xflow_indirect"), &dispatcher, &state).unwrap();
+    block_on(xfrunner.run_async()).unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("CounterValue").unwrap().value, XFlowValue::Integer(9));
+}
@@ -0,0 +1,159 @@
+extern crate env_logger;
+extern crate serde_json;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+use gears::runtime::actiondispatch::dispatchable::Dispatchable;
+
+mod common;
+use crate::common::load_doc;
+
+/// Receiver for the custom `greeting` node type
+struct Greeting;
+
+impl Dispatchable for Greeting {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        let params = match node.parameters {
+            XFlowNodeParameters::Custom(ref params) => params,
+            _ => {
+                return Err(RuntimeError::Dispatch {
+                    node: node.id,
+                    message: "Incorrect parameters for greeting node".to_owned(),
+                    state: state.clone(),
+                })
+            }
+        };
+        let name = match state.get("Name") {
+            Some(xvar) => xvar.value.string_value(),
+            None => "nobody".to_owned(),
+        };
+        state.add(&XFlowVariable {
+            name: params["returns"].as_str().unwrap().to_owned(),
+            vtype: XFlowValueType::String,
+            value: XFlowValue::String(
+                format!("{} {}", params["salutation"].as_str().unwrap(), name),
+            ),
+        });
+        Ok(())
+    }
+}
+
+/// Stores the name of the receiver that handled a flox node
+struct Marker(&'static str);
+
+impl Dispatchable for Marker {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, _node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        state.add(&XFlowVariable {
+            name: "Receiver".to_owned(),
+            vtype: XFlowValueType::String,
+            value: XFlowValue::String(self.0.to_owned()),
+        });
+        Ok(())
+    }
+}
+
+fn name_state() -> XFState {
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Name".to_owned(),
+        vtype: XFlowValueType::String,
+        value: XFlowValue::String("World".to_owned()),
+    });
+    state
+}
+
+#[test]
+fn test_custom_node_type() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/custom_node.json");
+    let greeting = XFlowNodeType::Custom("greeting".to_owned());
+    assert_eq!(xfs.body.nodes[1].nodetype, greeting);
+
+    let json = serde_json::to_string(&xfs.body.nodes[1]).unwrap();
+    assert!(json.contains("\"nodetype\":\"greeting\""));
+
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register(Capability::action(greeting, "greet", 2), Greeting);
+    let state = name_state();
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    xfrunner.run().unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(
+        output.get("Greeting").unwrap().value,
+        XFlowValue::String("Hello World".to_owned())
+    );
+}
+
+#[test]
+fn test_capability_versions() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/custom_node.json");
+    let greeting = XFlowNodeType::Custom("greeting".to_owned());
+    let state = name_state();
+
+    // Version 1 of the receiver is too old for the flow
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register(Capability::new(greeting.clone(), 1), Greeting);
+    assert_eq!(
+        dispatcher.missing_capabilities(&xfs.body),
+        vec![
+            Capability::new(greeting.clone(), 2),
+            Capability::action(greeting.clone(), "greet", 2),
+        ]
+    );
+    assert!(XFlowRunner::new(&xfs, &dispatcher, &state).is_err());
+
+    // A newer version satisfies older requirements
+    dispatcher.register(Capability::new(greeting.clone(), 3), Greeting);
+    assert_eq!(dispatcher.missing_capabilities(&xfs.body).len(), 0);
+    assert!(XFlowRunner::new(&xfs, &dispatcher, &state).is_ok());
+
+    // A receiver for a different action does not cover the node
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register(Capability::action(greeting.clone(), "wave", 2), Greeting);
+    assert_eq!(
+        dispatcher.missing_capabilities(&xfs.body),
+        vec![Capability::action(greeting, "greet", 2)]
+    );
+}
+
+#[test]
+fn test_action_receiver_takes_precedence() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    let state = XFState::default();
+
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register(Capability::new(XFlowNodeType::Flox, 5), Marker("generic"));
+    dispatcher.register(Capability::action(XFlowNodeType::Flox, "evalexpr", 1), Marker("action"));
+    dispatcher.register(Capability::action(XFlowNodeType::Flox, "evalexpr", 2), Marker("newer"));
+
+    let node = &xfs.body.nodes[1];
+    let mut result = XFState::default();
+    dispatcher.dispatch(node, &mut result).unwrap();
+    assert_eq!(
+        result.get("Receiver").unwrap().value,
+        XFlowValue::String("newer".to_owned())
+    );
+
+    assert!(XFlowRunner::new(&xfs, &dispatcher, &state).is_ok());
+}
@@ -0,0 +1,96 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::checkpoint::XFlowCheckpoint;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher
+}
+
+fn counter_state() -> XFState {
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "CounterValue".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(0),
+    });
+    state
+}
+
+fn checkpoint_after(xfs: &XFlowDocument, steps: usize) -> String {
+    let dispatcher = build_dispatcher();
+    let state = counter_state();
+    let mut xfrunner = XFlowRunner::new(xfs, &dispatcher, &state).unwrap();
+    for _ in 0..steps {
+        xfrunner.step().unwrap();
+    }
+    xfrunner.checkpoint().to_json()
+}
+
+#[test]
+fn test_checkpoint_and_resume() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/loop_5x.json");
+    let json = checkpoint_after(&xfs, 7);
+
+    let checkpoint = XFlowCheckpoint::from_json(&json).unwrap();
+    assert_eq!(checkpoint.xflow_id, xfs.id);
+    assert_eq!(checkpoint.status, XFlowStatus::Running);
+    assert_eq!(checkpoint.steps, 7);
+
+    let dispatcher = build_dispatcher();
+    let mut xfrunner = XFlowRunner::resume(&xfs, &dispatcher, &checkpoint).unwrap();
+    assert_eq!(xfrunner.can_run(), true);
+    xfrunner.run().unwrap();
+
+    match xfrunner.get_output().unwrap().get("CounterValue").unwrap().value {
+        XFlowValue::Integer(i) => assert_eq!(i, 6),
+        _ => panic!("CounterValue should be an integer"),
+    }
+}
+
+#[test]
+fn test_resume_rejects_changed_version() {
+    let _ = env_logger::try_init();
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/loop_5x.json");
+    let json = checkpoint_after(&xfs, 3);
+    let checkpoint = XFlowCheckpoint::from_json(&json).unwrap();
+
+    xfs.version += 1;
+    let dispatcher = build_dispatcher();
+
+    match XFlowRunner::resume(&xfs, &dispatcher, &checkpoint) {
+        Err(RuntimeError::InvalidCheckpoint { .. }) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_resume_rejects_other_flow() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/loop_5x.json");
+    let other = load_doc::<XFlowDocument>("resource/docs/xflow/flows/loop_infinite.json");
+    let json = checkpoint_after(&xfs, 3);
+    let checkpoint = XFlowCheckpoint::from_json(&json).unwrap();
+    let dispatcher = build_dispatcher();
+
+    assert!(XFlowRunner::resume(&other, &dispatcher, &checkpoint).is_err());
+    assert!(XFlowCheckpoint::from_json("{}").is_err());
+}
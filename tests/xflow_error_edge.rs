@@ -0,0 +1,147 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+use gears::generation::{xflow_to_dot, xflow_to_es5};
+use gears::validation::xflow::Validation;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    let flow_receiver = actiondispatch::flow::Flow::default();
+    let flox_receiver = actiondispatch::flox::Flox::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, flow_receiver);
+    dispatcher.register_receiver(XFlowNodeType::Flox, flox_receiver);
+    dispatcher
+}
+
+fn input_state(a: i64, b: i64) -> XFState {
+    let mut state = XFState::default();
+    for &(name, value) in &[("A", a), ("B", b)] {
+        state.add(&XFlowVariable {
+            name: name.to_owned(),
+            vtype: XFlowValueType::Integer,
+            value: XFlowValue::Integer(value),
+        });
+    }
+    state
+}
+
+fn run(xfs: &XFlowDocument, state: &XFState) -> Result<XFState, RuntimeError> {
+    let dispatcher = build_dispatcher();
+    let mut xfrunner = XFlowRunner::new(xfs, &dispatcher, state)?;
+    xfrunner.run()?;
+    xfrunner.get_output()
+}
+
+#[test]
+fn test_error_edge_is_taken() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+
+    let output = run(&xfs, &input_state(7, 2)).unwrap();
+    assert_eq!(output.get("Ratio").unwrap().value, XFlowValue::Integer(3));
+    assert_eq!(output.get("Reason").unwrap().value, XFlowValue::String("".to_owned()));
+
+    let output = run(&xfs, &input_state(7, 0)).unwrap();
+    assert_eq!(output.get("Ratio").unwrap().value, XFlowValue::Integer(0));
+    assert_eq!(
+        output.get("Reason").unwrap().value,
+        XFlowValue::String("flox_evaluation".to_owned())
+    );
+}
+
+#[test]
+fn test_error_is_stored_in_state() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+    let dispatcher = build_dispatcher();
+    let state = input_state(7, 0);
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap().with_tracing();
+    xfrunner.step().unwrap();
+    assert!(xfrunner.can_run());
+
+    let trace = xfrunner.get_trace().unwrap();
+    let change = &trace.steps[0].changes[0];
+    assert_eq!(change.name, ERROR_VARIABLE);
+    let error = change.new.as_ref().unwrap();
+    assert_eq!(error.value.get_path(&["node"]), Some(&XFlowValue::Integer(2)));
+    assert_eq!(
+        error.value.get_path(&["kind"]),
+        Some(&XFlowValue::String("flox_evaluation".to_owned()))
+    );
+
+    // A checkpoint taken after the failure resumes through the error edge
+    let checkpoint = xfrunner.checkpoint();
+    assert_eq!(checkpoint.next_edge, Some((2, 3)));
+    let mut xfrunner = XFlowRunner::resume(&xfs, &dispatcher, &checkpoint).unwrap();
+    xfrunner.run().unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(
+        output.get("Reason").unwrap().value,
+        XFlowValue::String("flox_evaluation".to_owned())
+    );
+}
+
+#[test]
+fn test_error_handler() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+    xfs.body.error_edges.clear();
+    xfs.body.error_handler = Some(3);
+    assert_eq!(Validation::validate(&xfs).len(), 0);
+
+    let output = run(&xfs, &input_state(7, 0)).unwrap();
+    assert_eq!(
+        output.get("Reason").unwrap().value,
+        XFlowValue::String("flox_evaluation".to_owned())
+    );
+
+    // Without error handling the failure ends the flow
+    xfs.body.error_handler = None;
+    match run(&xfs, &input_state(7, 0)) {
+        Err(RuntimeError::FloxEvaluation { node, .. }) => assert_eq!(node, 2),
+        res => panic!("Expected a flox evaluation error, found {:?}", res),
+    }
+}
+
+#[test]
+fn test_error_handling_validation() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+    xfs.body.error_edges.push((2, 1));
+    xfs.body.error_edges.push((9, 3));
+    xfs.body.error_handler = Some(8);
+    // An edge to the entry node, a second edge out of node 2, a missing node and a
+    // missing handler
+    assert_eq!(Validation::error_handling_is_well_formed(&xfs).len(), 4);
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+    xfs.body.variables.output.push(XFlowVariableDefinition {
+        name: ERROR_VARIABLE.to_owned(),
+        vtype: XFlowValueType::String,
+    });
+    assert_eq!(Validation::error_handling_is_well_formed(&xfs).len(), 1);
+}
+
+#[test]
+fn test_error_edge_generation() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/error_edge.json");
+
+    let dot = xflow_to_dot::output(&xfs);
+    assert!(dot.contains("node_2 -> node_3[style=dashed,color=red,label=\"error\"];"));
+
+    let js = xflow_to_es5::output(&xfs);
+    assert!(js.contains("catch (e)"));
+    assert!(js.contains("this.local_vars.Error"));
+}
@@ -0,0 +1,102 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+use gears::generation::{xflow_to_dot, xflow_to_es5};
+use gears::validation::xflow::Validation;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    let flow_receiver = actiondispatch::flow::Flow::default();
+    let flox_receiver = actiondispatch::flox::Flox::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, flow_receiver);
+    dispatcher.register_receiver(XFlowNodeType::Flox, flox_receiver);
+    dispatcher
+}
+
+fn run_with_items(xfs: &XFlowDocument, items: XFlowValue) -> Result<XFState, RuntimeError> {
+    let dispatcher = build_dispatcher();
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "Items".to_owned(),
+        vtype: XFlowValueType::List,
+        value: items,
+    });
+
+    let mut xfrunner = XFlowRunner::new(xfs, &dispatcher, &state)?;
+    xfrunner.run()?;
+    xfrunner.get_output()
+}
+
+fn integers(values: &[i64]) -> XFlowValue {
+    XFlowValue::List(values.iter().map(|i| XFlowValue::Integer(*i)).collect())
+}
+
+#[test]
+fn test_foreach_accumulates() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/foreach_sum.json");
+
+    let output = run_with_items(&xfs, integers(&[1, 2, 3])).unwrap();
+    assert_eq!(output.get("Total").unwrap().value, XFlowValue::Integer(14));
+    assert!(!output.has("Position"));
+    assert!(!output.has("Item"));
+
+    let output = run_with_items(&xfs, integers(&[])).unwrap();
+    assert_eq!(output.get("Total").unwrap().value, XFlowValue::Integer(0));
+}
+
+#[test]
+fn test_foreach_requires_a_list() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/foreach_sum.json");
+
+    match run_with_items(&xfs, XFlowValue::Integer(3)) {
+        Err(RuntimeError::Dispatch { node, .. }) => assert_eq!(node, 2),
+        res => panic!("Expected a dispatch error, found {:?}", res),
+    }
+}
+
+#[test]
+fn test_foreach_validation() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/foreach_sum.json");
+    assert_eq!(Validation::validate(&xfs).len(), 0);
+
+    // The body no longer leads back to the loop
+    xfs.body.edges.retain(|edge| *edge != (3, 2));
+    xfs.body.edges.push((3, 4));
+    assert_eq!(Validation::all_foreach_nodes_are_well_formed(&xfs).len(), 1);
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/foreach_sum.json");
+    if let XFlowNodeParameters::Foreach(ref mut params) = xfs.body.nodes[1].parameters {
+        params.list = "Total".to_owned();
+        params.index = "Items".to_owned();
+    }
+    // 'Total' is not a declared list, 'Items' is overwritten by the index
+    assert_eq!(Validation::all_foreach_nodes_are_well_formed(&xfs).len(), 2);
+}
+
+#[test]
+fn test_foreach_generation() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/foreach_sum.json");
+
+    let dot = xflow_to_dot::output(&xfs);
+    assert!(dot.contains("node_2 -> node_3[label=\"each Items\"];"));
+    assert!(dot.contains("node_2 -> node_4[label=\"done\"];"));
+
+    let js = xflow_to_es5::output(&xfs);
+    assert!(js.contains("this.local_vars.Position"));
+    assert!(js.contains("this.node_3()"));
+    assert!(js.contains("this.node_4()"));
+}
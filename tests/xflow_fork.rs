@@ -0,0 +1,232 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+use gears::runtime::actiondispatch::dispatchable::Dispatchable;
+use gears::generation::{xflow_to_dot, xflow_to_es5};
+use gears::validation::xflow::Validation;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    let flow_receiver = actiondispatch::flow::Flow::default();
+    let flox_receiver = actiondispatch::flox::Flox::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, flow_receiver);
+    dispatcher.register_receiver(XFlowNodeType::Flox, flox_receiver);
+    dispatcher
+}
+
+fn input_state(a: i64, b: i64) -> XFState {
+    let mut state = XFState::default();
+    for &(name, value) in &[("A", a), ("B", b)] {
+        state.add(&XFlowVariable {
+            name: name.to_owned(),
+            vtype: XFlowValueType::Integer,
+            value: XFlowValue::Integer(value),
+        });
+    }
+    state
+}
+
+/// Let both branches write `Sum`, and read it after the join
+fn conflicting(policy: XFlowMergePolicy) -> XFlowDocument {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    for node in &mut xfs.body.nodes {
+        match node.parameters {
+            XFlowNodeParameters::Fork(ref mut params) => params.conflicts = policy,
+            XFlowNodeParameters::Flox(ref mut params) if node.id == 4 => {
+                params.returns.name = "Sum".to_owned();
+            }
+            XFlowNodeParameters::Flox(ref mut params) if node.id == 6 => {
+                params.expression = "$Sum".to_owned();
+            }
+            _ => (),
+        }
+    }
+    xfs
+}
+
+/// Evaluates flox nodes after sleeping for the time in milliseconds given by their label
+struct SlowFlox {
+    flox: actiondispatch::flox::Flox,
+}
+
+impl Dispatchable for SlowFlox {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, node: &XFlowNode, state: &mut XFState) -> Result<(), RuntimeError> {
+        let millis = node.label.parse().unwrap_or(0);
+        thread::sleep(Duration::from_millis(millis));
+        self.flox.dispatch(node, state)
+    }
+}
+
+#[test]
+fn test_fork_merges_branches() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let dispatcher = build_dispatcher();
+    let state = input_state(3, 4);
+
+    for threads in 1..3 {
+        let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+            .unwrap()
+            .with_fork_threads(threads);
+        xfrunner.run().unwrap();
+        assert!(xfrunner.is_completed_ok());
+
+        let output = xfrunner.get_output().unwrap();
+        assert_eq!(output.get("Result").unwrap().value, XFlowValue::Integer(19));
+    }
+}
+
+#[test]
+fn test_fork_trace_numbers_steps() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let dispatcher = build_dispatcher();
+    let state = input_state(3, 4);
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+        .unwrap()
+        .with_tracing();
+    xfrunner.run().unwrap();
+
+    let trace = xfrunner.get_trace().unwrap();
+    let numbers: Vec<usize> = trace.steps.iter().map(|step| step.step).collect();
+    let expected: Vec<usize> = (1..numbers.len() + 1).collect();
+    assert_eq!(numbers, expected);
+
+    let nodes: Vec<i32> = trace.steps.iter().map(|step| step.node).collect();
+    assert!(nodes.contains(&3));
+    assert!(nodes.contains(&4));
+    assert_eq!(xfrunner.steps(), trace.steps.len());
+}
+
+#[test]
+fn test_fork_conflicts() {
+    let _ = env_logger::try_init();
+    let dispatcher = build_dispatcher();
+    let state = input_state(3, 4);
+
+    let xfs = conflicting(XFlowMergePolicy::Fail);
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    match xfrunner.run() {
+        Err(RuntimeError::ForkConflict { node, name, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(name, "Sum");
+        }
+        res => panic!("Expected a fork conflict, found {:?}", res),
+    }
+    assert!(xfrunner.is_completed());
+    assert!(!xfrunner.is_completed_ok());
+
+    // The branches are merged in the order of their edges
+    let xfs = conflicting(XFlowMergePolicy::LastWins);
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    xfrunner.run().unwrap();
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("Result").unwrap().value, XFlowValue::Integer(12));
+}
+
+#[test]
+fn test_fork_validation() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    assert_eq!(Validation::validate(&xfs).len(), 0);
+
+    // The second branch leaves the parallel region
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    xfs.body.edges.retain(|edge| *edge != (4, 5));
+    xfs.body.edges.push((4, 7));
+    assert_eq!(Validation::all_forks_have_matching_joins(&xfs).len(), 1);
+
+    // The join is not a join node
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    xfs.body.nodes[4].action = "end".to_owned();
+    assert_eq!(Validation::all_forks_have_matching_joins(&xfs).len(), 1);
+
+    // A join without a fork
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    xfs.body.nodes[1].action = "start".to_owned();
+    xfs.body.nodes[1].parameters = XFlowNodeParameters::Flow(FlowParameters {});
+    assert!(
+        Validation::all_forks_have_matching_joins(&xfs)
+            .iter()
+            .any(|err| err.paths == vec!["/nodes/5".to_owned()])
+    );
+}
+
+#[test]
+fn test_fork_generation() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+
+    let dot = xflow_to_dot::output(&xfs);
+    assert!(dot.contains("subgraph cluster_fork_2 {"));
+    assert!(dot.contains("subgraph cluster_fork_2_0 { label=\"branch 0\"; node_3; }"));
+    assert!(dot.contains("subgraph cluster_fork_2_1 { label=\"branch 1\"; node_4; }"));
+
+    let js = xflow_to_es5::output(&xfs);
+    assert!(js.contains("this.joins[5]"));
+    assert!(js.contains("this.node_6()"));
+}
+
+#[test]
+fn test_fork_workers_take_waiting_branches() {
+    let _ = env_logger::try_init();
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(
+        XFlowNodeType::Flox,
+        SlowFlox {
+            flox: actiondispatch::flox::Flox::default(),
+        },
+    );
+
+    // A third branch after the fast one, with the time to wait in the labels
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/fork_join.json");
+    let mut third = xfs.body.get_node_id(4).unwrap().clone();
+    third.id = 8;
+    third.label = "300".to_owned();
+    if let XFlowNodeParameters::Flox(ref mut params) = third.parameters {
+        params.returns.name = "Extra".to_owned();
+    }
+    xfs.body.nodes.push(third);
+    xfs.body.edges.push((2, 8));
+    xfs.body.edges.push((8, 5));
+    for node in &mut xfs.body.nodes {
+        match node.id {
+            3 => node.label = "300".to_owned(),
+            4 => node.label = "10".to_owned(),
+            _ => (),
+        }
+    }
+
+    // The worker done with the fast branch runs the third one while the first one is
+    // still running, instead of waiting for it
+    let state = input_state(3, 4);
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+        .unwrap()
+        .with_fork_threads(2);
+    let started = Instant::now();
+    xfrunner.run().unwrap();
+    assert!(started.elapsed() < Duration::from_millis(550));
+    assert!(xfrunner.is_completed_ok());
+
+    let output = xfrunner.get_output().unwrap();
+    assert_eq!(output.get("Result").unwrap().value, XFlowValue::Integer(19));
+}
@@ -48,11 +48,13 @@ fn create_branches(amount: i32) -> Vec<XFlowBranch> {
         let left = 5;
         let right = 5;
         branches.push(XFlowBranch {
-            xvar: XFlowVariable {
+            xvar: Some(XFlowVariable {
                 name: "MatchValue".to_string(),
                 vtype: XFlowValueType::String,
                 value: XFlowValue::String("Some branch".to_owned()),
-            },
+            }),
+            condition: None,
+            default: false,
             edge: (left, right + i),
         })
     }
@@ -77,6 +79,8 @@ fn create_xflow_struct() -> XFlowDocument {
             nodes: create_nodes(5),
             edges: create_edges(5),
             branches: create_branches(5),
+            error_edges: Vec::<XFlowEdge>::new(),
+            error_handler: None,
         },
     }
 }
@@ -181,5 +185,5 @@ fn test_mem_profile() {
     let _ = env_logger::try_init();
     let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/10_steps.json");
 
-    assert_eq!(std::mem::size_of_val(&xfs), 248);
+    assert_eq!(std::mem::size_of_val(&xfs), 280);
 }
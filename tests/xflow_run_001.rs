@@ -6,6 +6,7 @@ use gears::structure::xflow::*;
 use gears::runtime::xfstate::*;
 use gears::runtime::xfrunner::*;
 use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
 use gears::runtime::actiondispatch;
 
 mod common;
@@ -20,12 +21,12 @@ fn build_dispatcher<'a>() -> Dispatcher<'a> {
     dispatcher
 }
 
-fn fail_and_report_error(err: String) -> () {
+fn fail_and_report_error(err: RuntimeError) -> () {
     println!("fail_and_report_error : {:?}", err);
     assert!(false);
 }
 
-fn run_xflow(flow_file: &str) -> Result<XFState, String> {
+fn run_xflow(flow_file: &str) -> Result<XFState, RuntimeError> {
 
     let xfs = load_doc::<XFlowDocument>(flow_file);
     let dispatcher = build_dispatcher();
@@ -135,6 +136,71 @@ fn test_run_arithmetic() {
     }
 }
 
+#[test]
+fn test_run_arithmetic_decimal() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_decimal.json");
+    let dispatcher = build_dispatcher();
+    let mut state = XFState::default();
+
+    state.add(&XFlowVariable {
+        name: "Price".to_owned(),
+        vtype: XFlowValueType::Decimal,
+        value: XFlowValue::Decimal("19.99".parse().unwrap()),
+    });
+    state.add(&XFlowVariable {
+        name: "Quantity".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(3),
+    });
+
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Ok(mut xfrunner) => {
+            xfrunner.run();
+            let output = xfrunner.get_output().unwrap();
+            match output.get("ReturnValue").unwrap().value {
+                XFlowValue::Decimal(d) => assert_eq!(d.to_string(), "71.96"),
+                _ => assert!(false),
+            }
+        }
+        Err(err) => fail_and_report_error(err),
+    }
+}
+
+#[test]
+fn test_run_record_field_assignment() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/record_total.json");
+    let dispatcher = build_dispatcher();
+    let mut state = XFState::default();
+
+    let mut item = XFlowRecord::new();
+    item.insert("price".to_owned(), XFlowValue::String("9.95".to_owned()));
+    item.insert("quantity".to_owned(), XFlowValue::Integer(3));
+    let mut order = XFlowRecord::new();
+    order.insert("items".to_owned(), XFlowValue::List(vec![XFlowValue::Record(item)]));
+
+    state.add(&XFlowVariable {
+        name: "order".to_owned(),
+        vtype: XFlowValueType::Record,
+        value: XFlowValue::Record(order),
+    });
+
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Ok(mut xfrunner) => {
+            xfrunner.run();
+            let output = xfrunner.get_output().unwrap();
+            match output.get_path("order.total") {
+                Some(&XFlowValue::Decimal(d)) => assert_eq!(d.to_string(), "29.85"),
+                res => panic!("Expected a decimal total, found {:?}", res),
+            }
+        }
+        Err(err) => fail_and_report_error(err),
+    }
+}
+
 #[test]
 fn test_run_arithmetic_multiple_return_values() {
     let _ = env_logger::try_init();
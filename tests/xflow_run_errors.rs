@@ -0,0 +1,183 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::actiondispatch;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher
+}
+
+fn match_value_state(value: XFlowValue, vtype: XFlowValueType) -> XFState {
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "MatchValue".to_owned(),
+        vtype: vtype,
+        value: value,
+    });
+    state
+}
+
+#[test]
+fn test_flox_evaluation_error() {
+    let _ = env_logger::try_init();
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    if let XFlowNodeParameters::Flox(ref mut params) = xfs.body.nodes[1].parameters {
+        params.expression = "1+$Missing".to_owned();
+    }
+
+    let dispatcher = build_dispatcher();
+    let state = XFState::default();
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+
+    match xfrunner.run() {
+        Err(RuntimeError::FloxEvaluation {
+                node,
+                expression,
+                position,
+                ..
+            }) => {
+            assert_eq!(node, 2);
+            assert_eq!(expression, "1+$Missing");
+            assert!(position <= expression.len());
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+
+    assert_eq!(xfrunner.status, XFlowStatus::Aborted);
+    assert_eq!(xfrunner.is_completed_ok(), false);
+}
+
+#[test]
+fn test_flox_syntax_error() {
+    let _ = env_logger::try_init();
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    if let XFlowNodeParameters::Flox(ref mut params) = xfs.body.nodes[1].parameters {
+        params.expression = "1 + ".to_owned();
+    }
+
+    // Node expressions are compiled before the flow starts
+    let dispatcher = build_dispatcher();
+    let state = XFState::default();
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Err(RuntimeError::FloxEvaluation { node, position, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(position, 4);
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Expected a Flox error"),
+    }
+}
+
+#[test]
+fn test_missing_capabilities_error() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    let state = XFState::default();
+
+    // The flow fails before it starts, listing the requirement and the node action
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Err(RuntimeError::MissingCapabilities { missing }) => {
+            assert_eq!(
+                missing,
+                vec![
+                    Capability::new(XFlowNodeType::Flox, 1),
+                    Capability::action(XFlowNodeType::Flox, "evalexpr", 1),
+                ]
+            );
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Expected missing capabilities"),
+    }
+}
+
+#[test]
+fn test_no_matching_branch_error() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    let dispatcher = build_dispatcher();
+    let state = match_value_state(
+        XFlowValue::String("maybe".to_owned()),
+        XFlowValueType::String,
+    );
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+
+    let err = xfrunner.run().unwrap_err();
+    match err {
+        RuntimeError::NoMatchingBranch { node, .. } => assert_eq!(node, 2),
+        err => panic!("Unexpected error {:?}", err),
+    }
+    assert!(err.state().unwrap().has("MatchValue"));
+    assert_eq!(xfrunner.status, XFlowStatus::InvalidState);
+}
+
+#[test]
+fn test_ambiguous_branch_error() {
+    let _ = env_logger::try_init();
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    for branch in &mut xfs.body.branches {
+        if let Some(ref mut xvar) = branch.xvar {
+            xvar.value = XFlowValue::Boolean(true);
+        }
+    }
+
+    let dispatcher = build_dispatcher();
+    let state = match_value_state(XFlowValue::Boolean(true), XFlowValueType::Boolean);
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+
+    match xfrunner.run() {
+        Err(RuntimeError::AmbiguousBranch { node, edges, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(edges, vec![(2, 3), (2, 4)]);
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_missing_input_error() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    let dispatcher = build_dispatcher();
+    let state = XFState::default();
+
+    match XFlowRunner::new(&xfs, &dispatcher, &state) {
+        Err(RuntimeError::MissingVariable { node, name, .. }) => {
+            assert_eq!(node, None);
+            assert_eq!(name, "MatchValue");
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_output_before_finish_error() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    let dispatcher = build_dispatcher();
+    let state = XFState::default();
+    let xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+
+    assert_eq!(xfrunner.get_output().unwrap_err(), RuntimeError::NotFinished);
+}
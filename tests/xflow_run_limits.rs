@@ -0,0 +1,153 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use std::thread;
+use std::time::Duration;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::{ExecutionLimit, RuntimeError};
+use gears::runtime::actiondispatch;
+use gears::runtime::actiondispatch::dispatchable::Dispatchable;
+
+mod common;
+use crate::common::load_doc;
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher
+}
+
+fn counter_state() -> XFState {
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "CounterValue".to_owned(),
+        vtype: XFlowValueType::Integer,
+        value: XFlowValue::Integer(0),
+    });
+    state
+}
+
+fn run_with_limits(flow_file: &str, limits: XFlowLimits) -> (XFlowStatus, Result<(), RuntimeError>) {
+    let xfs = load_doc::<XFlowDocument>(flow_file);
+    let dispatcher = build_dispatcher();
+    let state = counter_state();
+
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+        .unwrap()
+        .with_limits(limits);
+    let res = xfrunner.run();
+    (xfrunner.status, res)
+}
+
+#[test]
+fn test_limits_allow_finite_loop() {
+    let _ = env_logger::try_init();
+
+    let limits = XFlowLimits {
+        max_steps: Some(100),
+        max_duration: Some(Duration::from_secs(10)),
+        max_node_visits: Some(10),
+    };
+
+    let (status, res) = run_with_limits("resource/docs/xflow/flows/loop_5x.json", limits);
+    assert_eq!(res, Ok(()));
+    assert_eq!(status, XFlowStatus::Finished);
+}
+
+#[test]
+fn test_max_steps() {
+    let _ = env_logger::try_init();
+
+    let mut limits = XFlowLimits::default();
+    limits.max_steps = Some(50);
+
+    let (status, res) = run_with_limits("resource/docs/xflow/flows/loop_infinite.json", limits);
+    assert_eq!(status, XFlowStatus::TimedOut);
+    match res {
+        Err(RuntimeError::LimitExceeded { limit, .. }) => {
+            assert_eq!(limit, ExecutionLimit::MaxSteps(50))
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_max_node_visits() {
+    let _ = env_logger::try_init();
+
+    let mut limits = XFlowLimits::default();
+    limits.max_node_visits = Some(3);
+
+    let (status, res) = run_with_limits("resource/docs/xflow/flows/loop_infinite.json", limits);
+    assert_eq!(status, XFlowStatus::TimedOut);
+    match res {
+        Err(RuntimeError::LimitExceeded { node, limit, state }) => {
+            assert_eq!(node, 2);
+            assert_eq!(limit, ExecutionLimit::MaxNodeVisits(3));
+            assert!(state.has("LoopValue"));
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_max_duration() {
+    let _ = env_logger::try_init();
+
+    let mut limits = XFlowLimits::default();
+    limits.max_duration = Some(Duration::from_millis(20));
+
+    let (status, res) = run_with_limits("resource/docs/xflow/flows/loop_infinite.json", limits);
+    assert_eq!(status, XFlowStatus::TimedOut);
+    match res {
+        Err(RuntimeError::LimitExceeded { limit, .. }) => {
+            assert_eq!(limit, ExecutionLimit::MaxDuration(Duration::from_millis(20)))
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+}
+
+/// A receiver that takes its time
+struct Sleep(Duration);
+
+impl Dispatchable for Sleep {
+    fn init(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn dispatch(&self, _node: &XFlowNode, _state: &mut XFState) -> Result<(), RuntimeError> {
+        thread::sleep(self.0);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_max_duration_slow_receiver() {
+    let _ = env_logger::try_init();
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/loop_5x.json");
+    let mut dispatcher = build_dispatcher();
+    dispatcher.register_receiver(XFlowNodeType::Flox, Sleep(Duration::from_millis(50)));
+    let state = counter_state();
+
+    let mut limits = XFlowLimits::default();
+    limits.max_duration = Some(Duration::from_millis(20));
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state)
+        .unwrap()
+        .with_limits(limits);
+
+    // The node that overran the deadline fails, not the one after it
+    match xfrunner.run() {
+        Err(RuntimeError::LimitExceeded { node, limit, .. }) => {
+            assert_eq!(node, 2);
+            assert_eq!(limit, ExecutionLimit::MaxDuration(Duration::from_millis(20)));
+        }
+        res => panic!("Unexpected result {:?}", res),
+    }
+    assert_eq!(xfrunner.status, XFlowStatus::TimedOut);
+}
@@ -0,0 +1,148 @@
+extern crate env_logger;
+
+extern crate gears;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gears::structure::xflow::*;
+use gears::runtime::xfstate::*;
+use gears::runtime::xfrunner::*;
+use gears::runtime::dispatcher::*;
+use gears::runtime::error::RuntimeError;
+use gears::runtime::trace::*;
+use gears::runtime::actiondispatch;
+
+mod common;
+use crate::common::{load_doc, read_json_file};
+
+fn build_dispatcher<'a>() -> Dispatcher<'a> {
+    let mut dispatcher = Dispatcher::default();
+    dispatcher.register_receiver(XFlowNodeType::Flow, actiondispatch::flow::Flow::default());
+    dispatcher.register_receiver(XFlowNodeType::Flox, actiondispatch::flox::Flox::default());
+    dispatcher
+}
+
+fn run_traced(flow_file: &str, state: &XFState) -> XFlowTrace {
+    let xfs = load_doc::<XFlowDocument>(flow_file);
+    let dispatcher = build_dispatcher();
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, state)
+        .unwrap()
+        .with_tracing();
+    xfrunner.run().unwrap();
+    xfrunner.get_trace().unwrap().clone()
+}
+
+#[derive(Default)]
+struct Recorder {
+    nodes: Vec<i32>,
+    errors: Vec<RuntimeError>,
+    finished: Option<XFlowStatus>,
+}
+
+impl XFlowObserver for Recorder {
+    fn on_step(&mut self, step: &XFlowTraceStep) {
+        self.nodes.push(step.node);
+    }
+
+    fn on_error(&mut self, error: &RuntimeError) {
+        self.errors.push(error.clone());
+    }
+
+    fn on_finish(&mut self, status: &XFlowStatus) {
+        self.finished = Some(status.clone());
+    }
+}
+
+#[test]
+fn test_trace_branch() {
+    let _ = env_logger::try_init();
+
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "MatchValue".to_owned(),
+        vtype: XFlowValueType::Boolean,
+        value: XFlowValue::Boolean(false),
+    });
+
+    let trace = run_traced("resource/docs/xflow/flows/branch_boolean.json", &state);
+
+    assert_eq!(trace.path(), vec![2, 4]);
+    assert_eq!(trace.steps[0].edge, (1, 2));
+    assert_eq!(trace.steps[0].branch, None);
+    assert_eq!(trace.steps[1].edge, (2, 4));
+    assert_eq!(
+        trace.steps[1].branch.as_ref().unwrap().xvar.as_ref().unwrap().value,
+        XFlowValue::Boolean(false)
+    );
+}
+
+#[test]
+fn test_trace_variable_changes() {
+    let _ = env_logger::try_init();
+
+    let state = XFState::default();
+    let trace = run_traced("resource/docs/xflow/flows/arithmetic_addition.json", &state);
+
+    let change = &trace.steps[0].changes[0];
+    assert_eq!(change.name, "ReturnValue");
+    assert_eq!(change.old.as_ref().unwrap().value, XFlowValue::Integer(0));
+    assert_eq!(change.new.as_ref().unwrap().value, XFlowValue::Integer(3));
+
+    let mut replayed = XFState::default();
+    trace.replay(&mut replayed);
+    assert_eq!(replayed.get("ReturnValue").unwrap().value, XFlowValue::Integer(3));
+}
+
+#[test]
+fn test_trace_golden() {
+    let _ = env_logger::try_init();
+
+    let state = XFState::default();
+    let trace = run_traced(
+        "resource/docs/xflow/flows/arithmetic_addition_multiple_return_values.json",
+        &state,
+    );
+    let golden = XFlowTrace::from_json(&read_json_file(
+        "resource/docs/xflow/traces/arithmetic_addition_multiple_return_values.json",
+    )).unwrap();
+
+    assert_eq!(trace, golden);
+    assert_eq!(XFlowTrace::from_json(&trace.to_json()).unwrap(), trace);
+}
+
+#[test]
+fn test_observer() {
+    let _ = env_logger::try_init();
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    let dispatcher = build_dispatcher();
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+
+    let mut state = XFState::default();
+    state.add(&XFlowVariable {
+        name: "MatchValue".to_owned(),
+        vtype: XFlowValueType::Boolean,
+        value: XFlowValue::Boolean(true),
+    });
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    xfrunner.add_observer(recorder.clone());
+    xfrunner.run().unwrap();
+
+    assert_eq!(recorder.borrow().nodes, vec![2, 3]);
+    assert_eq!(recorder.borrow().finished, Some(XFlowStatus::Finished));
+    assert!(recorder.borrow().errors.is_empty());
+
+    state.add(&XFlowVariable {
+        name: "MatchValue".to_owned(),
+        vtype: XFlowValueType::String,
+        value: XFlowValue::String("neither".to_owned()),
+    });
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut xfrunner = XFlowRunner::new(&xfs, &dispatcher, &state).unwrap();
+    xfrunner.add_observer(recorder.clone());
+    assert!(xfrunner.run().is_err());
+
+    assert_eq!(recorder.borrow().errors.len(), 1);
+    assert_eq!(recorder.borrow().finished, None);
+}
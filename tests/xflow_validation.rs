@@ -117,12 +117,19 @@ fn test_all_good_flows_validate() {
         "arithmetic_addition.json",
         "arithmetic_addition_multiple_return_values.json",
         "arithmetic_addition_with_variables.json",
+        "arithmetic_decimal.json",
         "branch_boolean_and_expressions_return.json",
         "branch_boolean_condition.json",
         "branch_boolean.json",
+        "branch_condition.json",
+        "custom_node.json",
+        "error_edge.json",
+        "foreach_sum.json",
+        "fork_join.json",
         // "create_object.json",
         "loop_5x.json",
         "loop_infinite.json",
+        "record_total.json",
     ];
 
     for flow in flows {
@@ -148,6 +155,7 @@ fn test_no_bad_flows_validate() {
         "double_variables_per_scope.json",
         "edges_without_nodes.json",
         "empty.json",
+        "flox_type_mismatch.json",
         "multiple_entry_nodes.json",
         "no_entry_nodes.json",
         "no_terminal_nodes.json",
@@ -169,3 +177,117 @@ fn test_no_bad_flows_validate() {
     }
 
 }
+
+fn set_branch_conditions(xfs: &mut XFlowDocument, conditions: &[Option<&str>]) {
+    for (branch, condition) in xfs.body.branches.iter_mut().zip(conditions) {
+        branch.condition = condition.map(|c| c.to_owned());
+        branch.default = condition.is_none();
+    }
+}
+
+#[test]
+fn test_branch_conditions_well_formed() {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    assert_eq!(Validation::all_branches_are_well_formed(&xfs).len(), 0);
+
+    set_branch_conditions(&mut xfs, &[Some("$Counter <"), Some("$Counter + 1"), Some("$Missing")]);
+
+    let res_a = Validation::all_branches_are_well_formed(&xfs);
+    assert_eq!(res_a.len(), 3);
+    assert_eq!(res_a[0].paths[0], "/branches/(2, 3)");
+    assert_eq!(res_a[1].paths[0], "/branches/(2, 4)");
+    assert_eq!(res_a[2].paths[0], "/branches/(2, 5)");
+
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), None, None]);
+    assert_eq!(Validation::all_branches_are_well_formed(&xfs).len(), 1);
+}
+
+#[test]
+fn test_branch_conditions_overlap() {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    assert_eq!(Validation::branch_conditions_do_not_overlap(&xfs).len(), 0);
+
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), Some("$Counter <= 5"), None]);
+    let res_a = Validation::branch_conditions_do_not_overlap(&xfs);
+    assert_eq!(res_a.len(), 1);
+    assert_eq!(res_a[0].paths, vec!["/branches/(2, 3)", "/branches/(2, 4)"]);
+
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), Some("5 <= $Counter"), None]);
+    assert_eq!(Validation::branch_conditions_do_not_overlap(&xfs).len(), 0);
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    assert_eq!(Validation::branch_conditions_do_not_overlap(&xfs).len(), 0);
+}
+
+#[test]
+fn test_branch_conditions_coverage() {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 0);
+
+    xfs.body.branches.pop();
+    let res_a = Validation::branch_conditions_cover_all_values(&xfs);
+    assert_eq!(res_a.len(), 1);
+    assert_eq!(res_a[0].paths[0], "/nodes/2");
+
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), Some("$Counter >= 5")]);
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 0);
+
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_boolean.json");
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 0);
+    xfs.body.branches.pop();
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 1);
+}
+
+#[test]
+fn test_branch_conditions_decimal_ranges() {
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/branch_condition.json");
+    xfs.body.branches.pop();
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), Some("$Counter > 5")]);
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 1);
+    set_branch_conditions(&mut xfs, &[Some("$Counter >= 5"), Some("$Counter <= 5")]);
+    assert_eq!(Validation::branch_conditions_do_not_overlap(&xfs).len(), 1);
+
+    // Decimal variables have values between the integers, their ranges are not analysed
+    xfs.body.variables.input[0].vtype = XFlowValueType::Decimal;
+    set_branch_conditions(&mut xfs, &[Some("$Counter <= 4"), Some("$Counter >= 5")]);
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 0);
+    set_branch_conditions(&mut xfs, &[Some("$Counter < 5"), Some("$Counter > 5")]);
+    assert_eq!(Validation::branch_conditions_cover_all_values(&xfs).len(), 0);
+    set_branch_conditions(&mut xfs, &[Some("$Counter >= 5"), Some("$Counter <= 5")]);
+    assert_eq!(Validation::branch_conditions_do_not_overlap(&xfs).len(), 0);
+}
+
+fn set_flox_expression(xfs: &mut XFlowDocument, expression: &str) {
+    for node in &mut xfs.body.nodes {
+        if let XFlowNodeParameters::Flox(ref mut params) = node.parameters {
+            params.expression = expression.to_owned();
+        }
+    }
+}
+
+#[test]
+fn test_flox_expressions_type_check() {
+    let _ = env_logger::try_init();
+    let mut xfs = load_doc::<XFlowDocument>("resource/docs/xflow/flows/arithmetic_addition.json");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 0);
+
+    set_flox_expression(&mut xfs, "$ReturnValue * 2 + len('abc')");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 0);
+
+    set_flox_expression(&mut xfs, "1 < 2");
+    let res_a = Validation::all_flox_expressions_type_check(&xfs);
+    assert_eq!(res_a.len(), 1);
+    assert_eq!(res_a[0].paths[0], "/nodes/2");
+
+    set_flox_expression(&mut xfs, "1 + $ReturnValue + true");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 1);
+
+    set_flox_expression(&mut xfs, "len($ReturnValue)");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 1);
+
+    set_flox_expression(&mut xfs, "$Undeclared + 1");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 1);
+
+    let xfs = load_doc::<XFlowDocument>("resource/docs/xflow/bad_flows/flox_type_mismatch.json");
+    assert_eq!(Validation::all_flox_expressions_type_check(&xfs).len(), 1);
+}
@@ -1,4 +1,5 @@
 extern crate gears;
+extern crate serde_json;
 use gears::structure::xflow::*;
 use gears::runtime::xfstate::*;
 
@@ -46,3 +47,49 @@ fn test_store_add_and_remove() {
     assert_eq!(xfstate.has("number1"), false);
     assert_eq!(xfstate.is_empty(), true);
 }
+
+#[test]
+fn test_store_paths() {
+    let mut xfstate = XFState::default();
+    let xvar: XFlowVariable = serde_json::from_str(
+        r#"{
+            "name": "order",
+            "vtype": "record",
+            "value": {
+                "customer": { "name": "Ada" },
+                "items": [ { "price": "9.95", "quantity": 2 } ],
+                "paid": false
+            }
+        }"#,
+    ).unwrap();
+    xfstate.add(&xvar);
+
+    assert_eq!(
+        xfstate.get_path("order.customer.name"),
+        Some(&XFlowValue::String("Ada".to_owned()))
+    );
+    assert_eq!(
+        xfstate.get_path("order.items.0.quantity"),
+        Some(&XFlowValue::Integer(2))
+    );
+    assert_eq!(xfstate.get_path("order.items.1"), None);
+    assert_eq!(xfstate.get_path("order.missing"), None);
+    assert_eq!(xfstate.get_path("missing"), None);
+
+    xfstate.set_path("order.paid", XFlowValue::Boolean(true)).unwrap();
+    xfstate.set_path("order.note", XFlowValue::String("Gift".to_owned())).unwrap();
+    xfstate.set_path("order.items.0.quantity", XFlowValue::Integer(3)).unwrap();
+    assert_eq!(xfstate.get_path("order.paid"), Some(&XFlowValue::Boolean(true)));
+    assert_eq!(
+        xfstate.get_path("order.note"),
+        Some(&XFlowValue::String("Gift".to_owned()))
+    );
+    assert_eq!(
+        xfstate.get_path("order.items.0.quantity"),
+        Some(&XFlowValue::Integer(3))
+    );
+
+    assert!(xfstate.set_path("order.items.5.quantity", XFlowValue::Integer(1)).is_err());
+    assert!(xfstate.set_path("order.paid.when", XFlowValue::Integer(1)).is_err());
+    assert!(xfstate.set_path("missing.field", XFlowValue::Integer(1)).is_err());
+}